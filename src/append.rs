@@ -0,0 +1,449 @@
+//! Append
+//!
+//! Hashes append only files, such as logs and journals, incrementally. A
+//! file is split into fixed size blocks, and its digest is that of the list
+//! of block digests, so that a file which has grown keeps the digests of its
+//! complete blocks and only the new tail needs to be read.
+//!
+//! Given somewhere to keep state, AppendHash records the size, inode and
+//! block digests of each file it hashes. When a file has since grown, and
+//! its recorded prefix still verifies, hashing resumes from the last block.
+//! Anything else, such as a file which shrank or was replaced, is hashed in
+//! full. By default every recorded block is read back and verified before
+//! resuming, which saves keeping more than one block in memory but still
+//! reads the whole file.
+//!
+//! with_verified_blocks opts into a lossy mode, which reads back only the
+//! last block and a sample of the earlier ones, chosen afresh each time the
+//! file grows. It is only suitable for files which are truly append only: a
+//! rewrite of an unsampled block, in a file which also grew, is taken for an
+//! append, and the stale digest of that block is carried forward for good.
+//!
+//! Records are kept one per digest, with a pointer to the latest record for
+//! each file, named by the digest of its path. Each record lists the digests
+//! of the earlier records it is known to extend, which lets a report tell an
+//! append from a modification however many checks came in between. Only the
+//! records which a stamp refers to, and the latest record of each file, are
+//! kept. A record is a header followed by one block digest per line:
+//!
+//! ```text
+//! <size>\t<mtime>\t<inode>\t<block size>\t<hex digests extended, separated by commas, or ->
+//! <hex block digest>
+//! ```
+use crate::error::{HashitError, Result};
+use crate::traits::CalcHash;
+use crate::utils::blake_hash;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_BLOCK_SIZE: u64 = 1024 * 1024;
+// the length of a Blake2b digest, which is what stamps are made of
+const DIGEST_LEN: usize = 64;
+
+/// What was recorded when a file was hashed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub size: u64,
+    /// modification time, in nanoseconds since the epoch
+    pub mtime: i128,
+    pub inode: u64,
+    pub block_size: u64,
+    /// the digests of the earlier records which this one extends, that a
+    /// stamp refers to, when it was hashed incrementally
+    pub extends: Vec<Vec<u8>>,
+    /// the digest of each block, the last of which may be partial
+    pub blocks: Vec<Vec<u8>>,
+}
+
+impl Record {
+    /// The digest of the file, which covers its size and block digests
+    pub fn digest(&self) -> Vec<u8> {
+        let mut contents = format!("{}\t{}\n", self.size, self.block_size).into_bytes();
+        for block in &self.blocks {
+            contents.extend(block);
+        }
+        blake_hash(&contents)
+    }
+
+    /// Parse a record, as written by Display
+    pub fn parse(contents: &str) -> Option<Self> {
+        let mut lines = contents.lines();
+        let mut header = lines.next()?.split('\t');
+        let size = header.next()?.parse().ok()?;
+        let mtime = header.next()?.parse().ok()?;
+        let inode = header.next()?.parse().ok()?;
+        let block_size = header.next()?.parse().ok()?;
+        let extends = match header.next()? {
+            "-" => Vec::new(),
+            digests => digests
+                .split(',')
+                .map(|digest| hex::decode(digest).ok())
+                .collect::<Option<Vec<_>>>()?,
+        };
+        let blocks = lines
+            .map(|line| hex::decode(line).ok())
+            .collect::<Option<Vec<_>>>()?;
+        Some(Record {
+            size,
+            mtime,
+            inode,
+            block_size,
+            extends,
+            blocks,
+        })
+    }
+
+    /// Describe the change from previous: appended, when this record
+    /// extends it, or modified
+    pub fn diff(&self, previous: &Record) -> String {
+        // a prefix ending on a block boundary is recognizable by its blocks
+        let aligned = previous.size.is_multiple_of(previous.block_size)
+            && previous.block_size == self.block_size
+            && self.blocks.starts_with(&previous.blocks);
+        let extends = self.extends.contains(&previous.digest());
+        if self.size > previous.size && (extends || aligned) {
+            format!("appended {} bytes", self.size - previous.size)
+        } else {
+            String::from("modified")
+        }
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let extends = if self.extends.is_empty() {
+            String::from("-")
+        } else {
+            self.extends
+                .iter()
+                .map(hex::encode)
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        writeln!(
+            f,
+            "{}\t{}\t{}\t{}\t{}",
+            self.size, self.mtime, self.inode, self.block_size, extends
+        )?;
+        for block in &self.blocks {
+            writeln!(f, "{}", hex::encode(block))?;
+        }
+        Ok(())
+    }
+}
+
+// read until buffer is full or the end of reader is reached, returning the
+// number of bytes read
+fn read_block<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(filled)
+}
+
+/// A CalcHash which hashes files incrementally as they are appended to.
+/// Directories are hashed by the fallback.
+#[derive(Debug, Clone)]
+pub struct AppendHash<H> {
+    fallback: H,
+    block_size: u64,
+    verified_blocks: usize,
+    state: Option<PathBuf>,
+}
+
+impl<H> AppendHash<H> {
+    /// New up an AppendHash with blocks of 1 MiB, which verifies every block
+    /// before resuming
+    pub fn new(fallback: H) -> Self {
+        AppendHash {
+            fallback,
+            block_size: DEFAULT_BLOCK_SIZE,
+            verified_blocks: usize::MAX,
+            state: None,
+        }
+    }
+
+    /// Split files into blocks of block_size bytes, which must not be zero.
+    /// Changing the block size changes every digest.
+    pub fn with_block_size(mut self, block_size: u64) -> Self {
+        self.block_size = block_size.max(1);
+        self
+    }
+
+    /// Lossy: verify only a sample of count complete blocks before the last,
+    /// as well as the last, before resuming. A rewrite of any other block goes
+    /// unnoticed should the file also grow, so only use this for files which
+    /// are never modified other than by appending. usize::MAX, the default,
+    /// verifies every block.
+    pub fn with_verified_blocks(mut self, count: usize) -> Self {
+        self.verified_blocks = count;
+        self
+    }
+
+    /// Keep records within dir, without which every file is hashed in full
+    pub fn with_state<P>(mut self, dir: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.state = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    fn load(&self, digest: &[u8]) -> Option<Record> {
+        let path = self
+            .state
+            .as_ref()?
+            .join("records")
+            .join(hex::encode(digest));
+        Record::parse(&fs::read_to_string(path).ok()?)
+    }
+
+    fn latest_path(&self, path: &Path) -> Option<PathBuf> {
+        let path = fs::canonicalize(path).ok()?;
+        let name = hex::encode(blake_hash(path.to_string_lossy().as_bytes()));
+        Some(self.state.as_ref()?.join("files").join(name))
+    }
+
+    fn latest(&self, path: &Path) -> Option<Record> {
+        let digest = fs::read_to_string(self.latest_path(path)?).ok()?;
+        self.load(&hex::decode(digest.trim()).ok()?)
+    }
+
+    // the hex digests which stamps refer to
+    fn pins(&self) -> HashSet<String> {
+        let mut pins = HashSet::new();
+        let dir = match &self.state {
+            Some(dir) => dir.join("stamps"),
+            None => return pins,
+        };
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                let contents = fs::read_to_string(entry.path()).unwrap_or_default();
+                pins.extend(contents.lines().map(String::from));
+            }
+        }
+        pins
+    }
+
+    fn store(
+        &self,
+        path: &Path,
+        digest: &[u8],
+        record: &Record,
+        pins: &HashSet<String>,
+    ) -> Result<()> {
+        let (dir, latest) = match (&self.state, self.latest_path(path)) {
+            (Some(dir), Some(latest)) => (dir, latest),
+            _ => return Ok(()),
+        };
+        let superseded = fs::read_to_string(&latest).ok();
+        let records = dir.join("records");
+        fs::create_dir_all(&records)?;
+        fs::write(records.join(hex::encode(digest)), record.to_string())?;
+        fs::create_dir_all(dir.join("files"))?;
+        fs::write(latest, hex::encode(digest))?;
+        // the record this one replaces is only kept while a stamp needs it
+        if let Some(superseded) = superseded {
+            let superseded = superseded.trim();
+            if superseded != hex::encode(digest) && !pins.contains(superseded) {
+                let _ = fs::remove_file(records.join(superseded));
+            }
+        }
+        Ok(())
+    }
+
+    // remove every record which is neither referred to by a stamp nor the
+    // latest record of a file
+    fn collect_garbage(&self, dir: &Path) -> Result<()> {
+        let mut live = self.pins();
+        if let Ok(entries) = fs::read_dir(dir.join("files")) {
+            for entry in entries.flatten() {
+                if let Ok(digest) = fs::read_to_string(entry.path()) {
+                    live.insert(digest.trim().to_string());
+                }
+            }
+        }
+        if let Ok(entries) = fs::read_dir(dir.join("records")) {
+            for entry in entries.flatten() {
+                if !live.contains(entry.file_name().to_string_lossy().as_ref()) {
+                    let _ = fs::remove_file(entry.path());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Hash the file at path, resuming from its latest record where the file
+    /// has only grown since
+    pub fn record<P>(&self, path: P) -> Result<Record>
+    where
+        P: AsRef<Path>,
+    {
+        self.record_with(path.as_ref(), &self.pins())
+    }
+
+    // record, given the digests which stamps refer to
+    fn record_with(&self, path: &Path, pins: &HashSet<String>) -> Result<Record> {
+        let mut file = fs::File::open(path).map_err(|e| {
+            if e.kind() == ErrorKind::NotFound {
+                HashitError::NotFound {
+                    source: e,
+                    file: PathBuf::from(path),
+                }
+            } else {
+                e.into()
+            }
+        })?;
+        let metadata = file.metadata()?;
+        let mtime = metadata.mtime() as i128 * 1_000_000_000 + metadata.mtime_nsec() as i128;
+        let mut record = Record {
+            size: metadata.len(),
+            mtime,
+            inode: metadata.ino(),
+            block_size: self.block_size,
+            extends: Vec::new(),
+            blocks: Vec::new(),
+        };
+        let previous = self
+            .latest(path)
+            .filter(|p| p.inode == record.inode && p.block_size == record.block_size);
+        if let Some(previous) = previous {
+            if previous.size == record.size && previous.mtime == record.mtime {
+                return Ok(previous);
+            }
+            if previous.size < record.size && self.verify(&mut file, &previous, record.size)? {
+                // resume from the last block, which may have been partial
+                let resume = previous.blocks.len().saturating_sub(1);
+                record.blocks = previous.blocks[..resume].to_vec();
+                // we extend whatever previous extended, but only the records
+                // a stamp refers to are worth remembering
+                record.extends = previous
+                    .extends
+                    .iter()
+                    .filter(|digest| pins.contains(&hex::encode(digest)))
+                    .cloned()
+                    .collect();
+                record.extends.push(previous.digest());
+                self.hash_from(&mut file, resume as u64 * self.block_size, &mut record)?;
+                return Ok(record);
+            }
+        }
+        self.hash_from(&mut file, 0, &mut record)?;
+        Ok(record)
+    }
+
+    // whether the last block recorded in previous, and a sample of the
+    // complete blocks before it, are unchanged. The sample depends upon the
+    // current size, so that each time the file grows other blocks are read.
+    fn verify(&self, file: &mut fs::File, previous: &Record, size: u64) -> Result<bool> {
+        let last = match previous.blocks.len().checked_sub(1) {
+            Some(last) => last,
+            None => return Ok(true),
+        };
+        let mut indices = if last <= self.verified_blocks {
+            (0..last).collect::<Vec<_>>()
+        } else {
+            let seed = format!("{}\t{}", hex::encode(previous.digest()), size);
+            (0..self.verified_blocks)
+                .map(|idx| {
+                    let digest = blake_hash(format!("{}\t{}", seed, idx).as_bytes());
+                    let mut bytes = [0; 8];
+                    bytes.copy_from_slice(&digest[..8]);
+                    (u64::from_le_bytes(bytes) % last as u64) as usize
+                })
+                .collect()
+        };
+        indices.push(last);
+        for idx in indices {
+            let start = idx as u64 * previous.block_size;
+            let len = previous.block_size.min(previous.size - start);
+            let mut buffer = vec![0; len as usize];
+            file.seek(SeekFrom::Start(start))?;
+            let read = read_block(file, &mut buffer)?;
+            if read != buffer.len() || blake_hash(&buffer) != previous.blocks[idx] {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // hash the blocks of file from offset up to the size recorded, which
+    // ignores anything appended while we read
+    fn hash_from(&self, file: &mut fs::File, offset: u64, record: &mut Record) -> Result<()> {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = file.take(record.size - offset);
+        let mut buffer = vec![0; self.block_size as usize];
+        loop {
+            let read = read_block(&mut reader, &mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            record.blocks.push(blake_hash(&buffer[..read]));
+        }
+        Ok(())
+    }
+}
+
+impl<H: CalcHash> CalcHash for AppendHash<H> {
+    fn calc_hash<P>(&self, files: &[P]) -> Result<Vec<u8>>
+    where
+        P: AsRef<str>,
+    {
+        let pins = self.pins();
+        let mut resvec = Vec::new();
+        for f in files {
+            let path = Path::new(f.as_ref());
+            if path.is_dir() {
+                resvec.extend(self.fallback.calc_hash(&[f])?);
+                continue;
+            }
+            let record = self.record_with(path, &pins)?;
+            let digest = record.digest();
+            self.store(path, &digest, &record, &pins)?;
+            resvec.extend(digest);
+        }
+        Ok(resvec)
+    }
+
+    fn changed_entries(&self, input: &str, old: &[u8], new: &[u8]) -> Vec<String> {
+        match (self.load(old), self.load(new)) {
+            (Some(old), Some(new)) => vec![new.diff(&old)],
+            _ => self.fallback.changed_entries(input, old, new),
+        }
+    }
+
+    // remember which of our records the stamp refers to, replacing whatever
+    // it referred to before, and drop the records nothing refers to any more
+    fn committed(&self, output: &str, hash: &[u8]) -> Result<()> {
+        self.fallback.committed(output, hash)?;
+        let dir = match &self.state {
+            Some(dir) => dir,
+            None => return Ok(()),
+        };
+        let records = dir.join("records");
+        let pins = hash
+            .chunks(DIGEST_LEN)
+            .map(hex::encode)
+            .filter(|digest| records.join(digest).exists())
+            .collect::<Vec<_>>();
+        let stamps = dir.join("stamps");
+        fs::create_dir_all(&stamps)?;
+        let name = hex::encode(blake_hash(output.as_bytes()));
+        fs::write(stamps.join(name), pins.join("\n"))?;
+        self.collect_garbage(dir)
+    }
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/append_test.rs"]
+mod tests;
@@ -0,0 +1,363 @@
+//! Archive
+//!
+//! Hashes tar and zip archives by their contents rather than their bytes, so
+//! that repacking an archive with new timestamps or a different compression
+//! level does not count as a change. An archive is summarized by a Manifest,
+//! listing the digest, mode, kind and name of each entry, sorted by name.
+//! Names are length prefixed, since an entry name may contain anything, even
+//! a newline:
+//!
+//! ```text
+//! <hex digest>\t<octal mode>\t<kind>\t<name length in bytes>\t<name>
+//! ```
+//!
+//! The digest of an archive is the digest of its manifest. Directory entries
+//! are ignored. Files are hashed by their decompressed contents, which are
+//! streamed rather than held in memory, and links by their target.
+//!
+//! When given somewhere to keep manifests, ArchiveHash is able to say which
+//! entries of an archive changed, which Hashit::report passes along.
+use crate::error::{HashitError, Result};
+use crate::read::stream;
+use crate::traits::CalcHash;
+use crate::utils::blake_hash;
+use blake2::{Blake2b, Digest};
+use flate2::read::GzDecoder;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// The archive formats understood by ArchiveHash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveKind {
+    /// Determine the kind of archive from the name of path
+    pub fn from_path<P>(path: P) -> Option<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let name = path.as_ref().file_name()?.to_str()?.to_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else if name.ends_with(".zip") || name.ends_with(".jar") {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// The kinds of entry within an archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Symlink,
+    HardLink,
+}
+
+impl EntryKind {
+    fn code(&self) -> char {
+        match self {
+            Self::File => 'F',
+            Self::Symlink => 'L',
+            Self::HardLink => 'H',
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "F" => Some(Self::File),
+            "L" => Some(Self::Symlink),
+            "H" => Some(Self::HardLink),
+            _ => None,
+        }
+    }
+}
+
+/// A single entry of an archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    pub name: String,
+    pub mode: u32,
+    pub kind: EntryKind,
+    /// hex encoded digest of the decompressed contents
+    pub digest: String,
+}
+
+/// The entries of an archive, sorted by name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    entries: Vec<ArchiveEntry>,
+}
+
+// the hex digest of everything reader yields, read a block at a time so that
+// a huge entry is never held in memory
+fn digest_reader<R: Read>(reader: R, buffer: &mut [u8]) -> Result<String> {
+    let mut hasher = Blake2b::new();
+    stream(reader, buffer, &mut hasher, |_, _| ())?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+fn tar_entries<R: Read>(reader: R, entries: &mut BTreeMap<String, ArchiveEntry>) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    let mut buffer = vec![0; BUFFER_SIZE];
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let header = entry.header().entry_type();
+        let kind = if header.is_file() {
+            EntryKind::File
+        } else if header.is_symlink() {
+            EntryKind::Symlink
+        } else if header.is_hard_link() {
+            EntryKind::HardLink
+        } else {
+            continue;
+        };
+        let name = entry.path()?.to_string_lossy().to_string();
+        let mode = entry.header().mode()? & 0o7777;
+        let digest = match kind {
+            EntryKind::File => digest_reader(&mut entry, &mut buffer)?,
+            _ => {
+                let target = entry.link_name()?.unwrap_or_default();
+                hex::encode(blake_hash(target.to_string_lossy().as_bytes()))
+            }
+        };
+        entries.insert(
+            name.clone(),
+            ArchiveEntry {
+                name,
+                mode,
+                kind,
+                digest,
+            },
+        );
+    }
+    Ok(())
+}
+
+fn zip_entries(file: fs::File, entries: &mut BTreeMap<String, ArchiveEntry>) -> Result<()> {
+    let invalid =
+        |e: zip::result::ZipError| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+    let mut archive = zip::ZipArchive::new(file).map_err(invalid)?;
+    let mut buffer = vec![0; BUFFER_SIZE];
+    for idx in 0..archive.len() {
+        let mut entry = archive.by_index(idx).map_err(invalid)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let name = entry.name().to_string();
+        let mode = entry.unix_mode().unwrap_or(0o644) & 0o7777;
+        entries.insert(
+            name.clone(),
+            ArchiveEntry {
+                name,
+                mode,
+                kind: EntryKind::File,
+                digest: digest_reader(&mut entry, &mut buffer)?,
+            },
+        );
+    }
+    Ok(())
+}
+
+impl Manifest {
+    /// Read the entries of the archive at path
+    pub fn from_archive<P>(path: P, kind: ArchiveKind) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let file = fs::File::open(path).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                HashitError::NotFound {
+                    source: e,
+                    file: PathBuf::from(path),
+                }
+            } else {
+                e.into()
+            }
+        })?;
+        let mut entries = BTreeMap::new();
+        let read = match kind {
+            ArchiveKind::Tar => tar_entries(file, &mut entries),
+            ArchiveKind::TarGz => tar_entries(GzDecoder::new(file), &mut entries),
+            ArchiveKind::Zip => zip_entries(file, &mut entries),
+        };
+        read.map_err(|e| match e {
+            HashitError::IoError(e) => HashitError::Parse {
+                file: path.to_path_buf(),
+                message: e.to_string(),
+            },
+            e => e,
+        })?;
+        Ok(Manifest {
+            entries: entries.into_values().collect(),
+        })
+    }
+
+    /// Parse a manifest, as written by Display
+    pub fn parse(contents: &str) -> Option<Self> {
+        let mut entries = Vec::new();
+        let mut rest = contents;
+        while !rest.is_empty() {
+            let mut fields = rest.splitn(5, '\t');
+            let digest = fields.next()?.to_string();
+            let mode = u32::from_str_radix(fields.next()?, 8).ok()?;
+            let kind = EntryKind::from_code(fields.next()?)?;
+            let len = fields.next()?.parse::<usize>().ok()?;
+            let remainder = fields.next()?;
+            let name = remainder.get(..len)?.to_string();
+            rest = remainder.get(len..)?.strip_prefix('\n')?;
+            entries.push(ArchiveEntry {
+                name,
+                mode,
+                kind,
+                digest,
+            });
+        }
+        Some(Manifest { entries })
+    }
+
+    pub fn entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    /// The digest of the manifest, which stands in for that of the archive
+    pub fn digest(&self) -> Vec<u8> {
+        blake_hash(self.to_string().as_bytes())
+    }
+
+    /// The names of the entries which were added, removed or modified since
+    /// previous
+    pub fn diff(&self, previous: &Manifest) -> Vec<String> {
+        let before = previous
+            .entries
+            .iter()
+            .map(|e| (e.name.as_str(), e))
+            .collect::<BTreeMap<_, _>>();
+        let after = self
+            .entries
+            .iter()
+            .map(|e| (e.name.as_str(), e))
+            .collect::<BTreeMap<_, _>>();
+        let mut names = before
+            .keys()
+            .chain(after.keys())
+            .filter(|name| before.get(*name) != after.get(*name))
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(
+                f,
+                "{}\t{:o}\t{}\t{}\t{}",
+                entry.digest,
+                entry.mode,
+                entry.kind.code(),
+                entry.name.len(),
+                entry.name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A CalcHash which hashes archives by their contents. Other files are
+/// hashed by the fallback.
+#[derive(Debug, Clone)]
+pub struct ArchiveHash<H> {
+    fallback: H,
+    manifests: Option<PathBuf>,
+}
+
+impl<H> ArchiveHash<H> {
+    pub fn new(fallback: H) -> Self {
+        ArchiveHash {
+            fallback,
+            manifests: None,
+        }
+    }
+
+    /// Keep the manifest of each archive hashed within dir, named by its
+    /// digest, so that changed_entries is able to compare them
+    pub fn with_manifests<P>(mut self, dir: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.manifests = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    fn load(&self, digest: &[u8]) -> Option<Manifest> {
+        let path = self.manifests.as_ref()?.join(hex::encode(digest));
+        Manifest::parse(&fs::read_to_string(path).ok()?)
+    }
+
+    fn store(&self, digest: &[u8], manifest: &Manifest) -> Result<()> {
+        if let Some(dir) = &self.manifests {
+            let path = dir.join(hex::encode(digest));
+            if !path.exists() {
+                fs::create_dir_all(dir)?;
+                fs::write(path, manifest.to_string())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<H: CalcHash> CalcHash for ArchiveHash<H> {
+    fn calc_hash<P>(&self, files: &[P]) -> Result<Vec<u8>>
+    where
+        P: AsRef<str>,
+    {
+        let mut resvec = Vec::new();
+        for f in files {
+            match ArchiveKind::from_path(f.as_ref()) {
+                Some(kind) => {
+                    let manifest = Manifest::from_archive(f.as_ref(), kind)?;
+                    let digest = manifest.digest();
+                    self.store(&digest, &manifest)?;
+                    resvec.extend(digest);
+                }
+                None => resvec.extend(self.fallback.calc_hash(&[f])?),
+            }
+        }
+        Ok(resvec)
+    }
+
+    fn changed_entries(&self, input: &str, old: &[u8], new: &[u8]) -> Vec<String> {
+        if ArchiveKind::from_path(input).is_none() {
+            return self.fallback.changed_entries(input, old, new);
+        }
+        match (self.load(old), self.load(new)) {
+            (Some(old), Some(new)) => new.diff(&old),
+            _ => Vec::new(),
+        }
+    }
+
+    fn committed(&self, output: &str, hash: &[u8]) -> Result<()> {
+        self.fallback.committed(output, hash)
+    }
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/archive_test.rs"]
+mod tests;
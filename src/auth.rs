@@ -0,0 +1,381 @@
+//! Auth
+//!
+//! Tamper evident stamps. AuthFile stores stamps much like HtFile, but
+//! appends an HMAC-SHA256 or ed25519 signature of the contents, which is
+//! verified every time a stamp is fetched. A stamp which has been edited by
+//! hand, to fake "unchanged" and skip a required step, fails with
+//! HashitError::StampVerification rather than being treated as changed.
+//!
+//! A signed stamp ends with a single line naming the scheme:
+//!
+//! ```text
+//! <contents>
+//! hashtest-auth hmac-sha256 <hex tag>
+//! ```
+//!
+//! The signature covers the absolute, canonical path of the stamp along with
+//! its contents, so a stamp copied over that of another target fails to
+//! verify, while the same stamp named as `out/stamp`, `./out/stamp` or by
+//! its absolute path verifies alike.
+//!
+//! A missing or empty stamp is not an error, as it always counts as changed.
+//!
+//! The key may be supplied via the environment (see Authenticator::from_env).
+//! Trailing whitespace is trimmed from an HMAC key read from a file, as
+//! `echo secret > key` leaves a newline behind.
+//! Machines which only need to check stamps may be given just the ed25519
+//! public key, in which case committing fails.
+use crate::error::{HashitError, Result};
+use crate::traits::{FetchCachedHash, Open, OpenMut};
+use crate::utils::read_file;
+use crate::OpenMode;
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer};
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io::prelude::*;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// Environment variable holding the HMAC key itself
+pub const HMAC_KEY_ENV: &str = "HASHTEST_HMAC_KEY";
+/// Environment variable holding the path of a file containing the HMAC key
+pub const HMAC_KEY_FILE_ENV: &str = "HASHTEST_HMAC_KEY_FILE";
+/// Environment variable holding the path of a hex encoded ed25519 secret key
+pub const SIGNING_KEY_FILE_ENV: &str = "HASHTEST_SIGNING_KEY_FILE";
+/// Environment variable holding the path of a hex encoded ed25519 public key
+pub const VERIFY_KEY_FILE_ENV: &str = "HASHTEST_VERIFY_KEY_FILE";
+
+// precedes the scheme and tag on the final line of a stamp
+const MARKER: &[u8] = b"\nhashtest-auth ";
+const HMAC_SCHEME: &str = "hmac-sha256";
+const ED25519_SCHEME: &str = "ed25519";
+
+/// Signs and verifies the contents of stamps
+pub enum Authenticator {
+    /// HMAC-SHA256 with a shared key
+    Hmac(Vec<u8>),
+    /// ed25519, able to both sign and verify
+    Sign(Keypair),
+    /// ed25519, only able to verify
+    Verify(PublicKey),
+}
+
+// keys are kept out of logs and error messages
+impl fmt::Debug for Authenticator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Hmac(_) => f.write_str("Hmac(<redacted>)"),
+            Self::Sign(keypair) => f
+                .debug_struct("Sign")
+                .field("public", &hex::encode(keypair.public.as_bytes()))
+                .finish_non_exhaustive(),
+            Self::Verify(public) => f
+                .debug_tuple("Verify")
+                .field(&hex::encode(public.as_bytes()))
+                .finish(),
+        }
+    }
+}
+
+// the path which a stamp named name is bound to. The stamp itself, or failing
+// that its directory, is canonicalized, so that every way of naming the same
+// file agrees.
+fn bound_name(name: &str) -> String {
+    let path = Path::new(name);
+    let canonical = fs::canonicalize(path).or_else(|e| {
+        let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
+        match (parent.map(fs::canonicalize), path.file_name()) {
+            (Some(Ok(parent)), Some(file)) => Ok(parent.join(file)),
+            (None, Some(file)) => Ok(std::env::current_dir()?.join(file)),
+            _ => Err(e),
+        }
+    });
+    match canonical {
+        Ok(path) => path.to_string_lossy().to_string(),
+        Err(_) => std::path::absolute(path)
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|_| name.to_string()),
+    }
+}
+
+// the message signed for a stamp: the length of its bound name, the name, and
+// then the contents, so that the two can not be confused
+fn message(name: &str, contents: &[u8]) -> Vec<u8> {
+    let name = bound_name(name);
+    let mut message = format!("{}\n{}", name.len(), name).into_bytes();
+    message.extend_from_slice(contents);
+    message
+}
+
+fn invalid_key(what: &str) -> HashitError {
+    HashitError::InvalidAuthKey(what.to_string())
+}
+
+// read a hex encoded key from path
+fn read_hex_key(path: &Path) -> Result<Vec<u8>> {
+    let contents = read_file(path)?;
+    hex::decode(String::from_utf8_lossy(&contents).trim())
+        .map_err(|_| invalid_key(&format!("{} is not hex encoded", path.display())))
+}
+
+impl Authenticator {
+    /// An HMAC-SHA256 authenticator. The key may not be empty.
+    pub fn hmac<K>(key: K) -> Result<Self>
+    where
+        K: AsRef<[u8]>,
+    {
+        if key.as_ref().is_empty() {
+            return Err(invalid_key("empty HMAC key"));
+        }
+        Ok(Authenticator::Hmac(key.as_ref().to_vec()))
+    }
+
+    /// An ed25519 authenticator, given the 32 byte secret key
+    pub fn signing(secret: &[u8]) -> Result<Self> {
+        let secret = SecretKey::from_bytes(secret).map_err(|e| invalid_key(&e.to_string()))?;
+        let public = PublicKey::from(&secret);
+        Ok(Authenticator::Sign(Keypair { secret, public }))
+    }
+
+    /// An ed25519 authenticator which can only verify, given the 32 byte
+    /// public key
+    pub fn verifying(public: &[u8]) -> Result<Self> {
+        let public = PublicKey::from_bytes(public).map_err(|e| invalid_key(&e.to_string()))?;
+        Ok(Authenticator::Verify(public))
+    }
+
+    /// Configure from the environment, returning None if no key is set.
+    /// In order of preference:
+    ///
+    /// - HASHTEST_HMAC_KEY, the HMAC key itself
+    /// - HASHTEST_HMAC_KEY_FILE, a file containing the HMAC key, less any
+    ///   trailing whitespace
+    /// - HASHTEST_SIGNING_KEY_FILE, a file containing a hex encoded ed25519 secret key
+    /// - HASHTEST_VERIFY_KEY_FILE, a file containing a hex encoded ed25519 public key
+    pub fn from_env() -> Result<Option<Self>> {
+        let var = |name| std::env::var_os(name).map(PathBuf::from);
+        if let Ok(key) = std::env::var(HMAC_KEY_ENV) {
+            return Self::hmac(key).map(Some);
+        }
+        if let Some(path) = var(HMAC_KEY_FILE_ENV) {
+            let mut key = read_file(path)?;
+            while key.last().is_some_and(|b| b.is_ascii_whitespace()) {
+                key.pop();
+            }
+            return Self::hmac(key).map(Some);
+        }
+        if let Some(path) = var(SIGNING_KEY_FILE_ENV) {
+            return Self::signing(&read_hex_key(&path)?).map(Some);
+        }
+        if let Some(path) = var(VERIFY_KEY_FILE_ENV) {
+            return Self::verifying(&read_hex_key(&path)?).map(Some);
+        }
+        Ok(None)
+    }
+
+    fn scheme(&self) -> &'static str {
+        match self {
+            Self::Hmac(_) => HMAC_SCHEME,
+            Self::Sign(_) | Self::Verify(_) => ED25519_SCHEME,
+        }
+    }
+
+    /// Calculate the tag for contents
+    pub fn sign(&self, contents: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::Hmac(key) => {
+                let mut mac = Hmac::<Sha256>::new_varkey(key).map_err(|_| invalid_key("HMAC"))?;
+                mac.update(contents);
+                Ok(mac.finalize().into_bytes().to_vec())
+            }
+            Self::Sign(keypair) => Ok(keypair.sign(contents).to_bytes().to_vec()),
+            Self::Verify(_) => Err(HashitError::MissingKey("ed25519 secret key".to_string())),
+        }
+    }
+
+    /// Whether tag is valid for contents
+    pub fn verify(&self, contents: &[u8], tag: &[u8]) -> bool {
+        match self {
+            Self::Hmac(key) => Hmac::<Sha256>::new_varkey(key)
+                .map(|mut mac| {
+                    mac.update(contents);
+                    mac.verify(tag).is_ok()
+                })
+                .unwrap_or(false),
+            Self::Sign(Keypair { public, .. }) | Self::Verify(public) => Signature::try_from(tag)
+                .map(|signature| public.verify_strict(contents, &signature).is_ok())
+                .unwrap_or(false),
+        }
+    }
+
+    /// Append the signature line to contents, the stamp named name
+    pub fn seal(&self, contents: &[u8], name: &str) -> Result<Vec<u8>> {
+        let tag = self.sign(&message(name, contents))?;
+        let mut sealed = contents.to_vec();
+        sealed.extend_from_slice(MARKER);
+        sealed.extend(format!("{} {}\n", self.scheme(), hex::encode(tag)).into_bytes());
+        Ok(sealed)
+    }
+
+    /// Verify a sealed stamp, returning the contents. name must be that the
+    /// stamp was sealed with, and is used for reporting.
+    pub fn open_sealed(&self, sealed: &[u8], name: &str) -> Result<Vec<u8>> {
+        let failed = || HashitError::StampVerification(name.to_string());
+        let idx = sealed
+            .windows(MARKER.len())
+            .rposition(|w| w == MARKER)
+            .ok_or_else(failed)?;
+        let (contents, line) = sealed.split_at(idx);
+        let line = std::str::from_utf8(&line[MARKER.len()..]).map_err(|_| failed())?;
+        let (scheme, tag) = line.trim_end().split_once(' ').ok_or_else(failed)?;
+        let tag = hex::decode(tag).map_err(|_| failed())?;
+        if scheme != self.scheme() || !self.verify(&message(name, contents), &tag) {
+            return Err(failed());
+        }
+        Ok(contents.to_vec())
+    }
+}
+
+/// Stores signed stamps on disk
+#[derive(Debug)]
+pub struct AuthFile {
+    auth: Authenticator,
+}
+
+impl AuthFile {
+    pub fn new(auth: Authenticator) -> Self {
+        AuthFile { auth }
+    }
+
+    pub fn authenticator(&self) -> &Authenticator {
+        &self.auth
+    }
+}
+
+/// Writes a stamp, which is sealed and written out once, when flushed or
+/// dropped. Flush in order to learn of a failure to seal.
+#[derive(Debug)]
+pub struct AuthWriter<'a> {
+    auth: &'a Authenticator,
+    path: PathBuf,
+    contents: Cursor<Vec<u8>>,
+    // whether anything has been written since the stamp was last sealed
+    dirty: bool,
+}
+
+impl Read for AuthWriter<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.contents.read(buf)
+    }
+}
+
+impl Write for AuthWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.dirty = true;
+        self.contents.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let sealed = self
+            .auth
+            .seal(self.contents.get_ref(), &self.path.to_string_lossy())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::PermissionDenied, e))?;
+        fs::write(&self.path, sealed)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl Drop for AuthWriter<'_> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+impl Open for AuthFile {
+    type O = fs::File;
+    fn open<I>(&self, input: I) -> std::result::Result<Self::O, HashitError>
+    where
+        I: AsRef<str>,
+    {
+        let file = input.as_ref();
+        fs::File::open(file).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                HashitError::NotFound {
+                    source: e,
+                    file: PathBuf::from(file),
+                }
+            } else {
+                e.into()
+            }
+        })
+    }
+
+    fn exists<I>(&self, input: I) -> bool
+    where
+        I: AsRef<str>,
+    {
+        Path::new(input.as_ref()).exists()
+    }
+}
+
+impl<'a> OpenMut<'a> for AuthFile {
+    type OW = AuthWriter<'a>;
+    fn open_mut<I>(
+        &'a mut self,
+        input: I,
+        mode: OpenMode,
+    ) -> std::result::Result<Self::OW, HashitError>
+    where
+        I: AsRef<str>,
+    {
+        let name = input.as_ref();
+        let contents = match mode {
+            OpenMode::WriteAppend => self.fetch_cached_hash(name)?,
+            OpenMode::WriteTruncate => Vec::new(),
+        };
+        let mut contents = Cursor::new(contents);
+        contents.seek(std::io::SeekFrom::End(0))?;
+        Ok(AuthWriter {
+            auth: &self.auth,
+            path: PathBuf::from(name),
+            contents,
+            dirty: false,
+        })
+    }
+
+    fn create<I>(&'a mut self, path: I) -> Result<()>
+    where
+        I: AsRef<str>,
+    {
+        if let Some(parent) = Path::new(path.as_ref()).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::File::create(path.as_ref())?;
+        Ok(())
+    }
+}
+
+impl<'a> FetchCachedHash<'a> for AuthFile {
+    fn fetch_cached_hash(&mut self, input: &str) -> Result<Vec<u8>> {
+        if !self.exists(input) {
+            self.create(input)?;
+        }
+        let mut buffer = Vec::new();
+        self.open(input)?.read_to_end(&mut buffer)?;
+        if buffer.is_empty() {
+            return Ok(buffer);
+        }
+        self.auth.open_sealed(&buffer, input)
+    }
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/auth_test.rs"]
+mod tests;
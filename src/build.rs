@@ -0,0 +1,184 @@
+//! Build
+//!
+//! Helpers for using Hashit from a cargo build script, in order to avoid
+//! regenerating code when its inputs have not changed.
+//!
+//! ```no_run
+//! // build.rs
+//! fn main() -> Result<(), hashtest::HashitError> {
+//!     hashtest::build::regenerate_if_changed(&["schema/*.json"], "schema.rs", |out| {
+//!         std::fs::write(out, "// generated")
+//!     })?;
+//!     Ok(())
+//! }
+//! ```
+use crate::error::{HashitError, Result};
+use crate::hashit::Hashit;
+use crate::inputs::{expand_globs, GLOB_CHARS};
+use std::path::{Path, PathBuf};
+
+/// The name of the stamp used when none is supplied
+pub const DEFAULT_STAMP: &str = "hashtest.stamp";
+
+/// The OUT_DIR set by cargo for build scripts
+pub fn out_dir() -> Result<PathBuf> {
+    std::env::var_os("OUT_DIR")
+        .map(PathBuf::from)
+        .ok_or_else(|| HashitError::MissingKey("OUT_DIR".to_string()))
+}
+
+// the portion of a glob pattern which precedes the first glob character, so
+// that cargo reruns when files are added to or removed from it
+fn glob_base(pattern: &str) -> Option<PathBuf> {
+    if !pattern.contains(GLOB_CHARS) {
+        return None;
+    }
+    let base = Path::new(pattern)
+        .components()
+        .take_while(|c| !c.as_os_str().to_string_lossy().contains(GLOB_CHARS))
+        .collect::<PathBuf>();
+    if base.as_os_str().is_empty() {
+        Some(PathBuf::from("."))
+    } else {
+        Some(base)
+    }
+}
+
+/// The inputs of a build script, along with the stamp used to track them
+#[derive(Debug, Clone)]
+pub struct Build {
+    patterns: Vec<String>,
+    inputs: Vec<PathBuf>,
+    stamp: PathBuf,
+}
+
+impl Build {
+    /// Expand the supplied globs. The stamp defaults to hashtest.stamp within
+    /// OUT_DIR, so this fails outside of a build script unless followed by
+    /// with_stamp.
+    pub fn new<P>(patterns: &[P]) -> Result<Self>
+    where
+        P: AsRef<str>,
+    {
+        let stamp = out_dir()
+            .map(|dir| dir.join(DEFAULT_STAMP))
+            .unwrap_or_default();
+        Ok(Build {
+            patterns: patterns.iter().map(|x| x.as_ref().to_string()).collect(),
+            inputs: expand_globs(patterns)?,
+            stamp,
+        })
+    }
+
+    /// Use a stamp other than the default
+    pub fn with_stamp<S>(mut self, stamp: S) -> Self
+    where
+        S: AsRef<Path>,
+    {
+        self.stamp = stamp.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn inputs(&self) -> &[PathBuf] {
+        &self.inputs
+    }
+
+    pub fn stamp(&self) -> &Path {
+        &self.stamp
+    }
+
+    // OUT_DIR is only checked here, so that with_stamp may override it
+    fn checked_stamp(&self) -> Result<&Path> {
+        if self.stamp.as_os_str().is_empty() {
+            return Err(HashitError::MissingKey("OUT_DIR".to_string()));
+        }
+        Ok(&self.stamp)
+    }
+
+    /// The `cargo:rerun-if-changed` lines matching the inputs. Along with
+    /// each input, the directory at the root of each glob is included, so
+    /// that adding a file which matches the glob triggers a rerun.
+    pub fn rerun_if_changed(&self) -> Vec<String> {
+        let mut paths = self
+            .patterns
+            .iter()
+            .filter_map(|x| glob_base(x))
+            .collect::<Vec<_>>();
+        paths.extend(self.inputs.iter().cloned());
+        paths.sort();
+        paths.dedup();
+        paths
+            .iter()
+            .map(|x| format!("cargo:rerun-if-changed={}", x.display()))
+            .collect()
+    }
+
+    /// Print the `cargo:rerun-if-changed` lines for cargo to pick up
+    pub fn emit_rerun_if_changed(&self) {
+        for line in self.rerun_if_changed() {
+            println!("{}", line);
+        }
+    }
+
+    /// Determine whether the inputs have changed, without updating the stamp
+    pub fn has_changed(&self) -> Result<bool> {
+        let stamp = self.checked_stamp()?;
+        Ok(Hashit::new().check(&self.inputs, stamp)?.is_some())
+    }
+
+    /// Invoke regenerate if the inputs have changed since the last successful
+    /// regeneration, or if force is set. The stamp is only updated once
+    /// regenerate succeeds. Returns whether regenerate was invoked.
+    pub fn regenerate<F, E>(&self, force: bool, regenerate: F) -> Result<bool>
+    where
+        F: FnOnce() -> std::result::Result<(), E>,
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let stamp = self.checked_stamp()?;
+        let mut hashit = Hashit::new();
+        let pending = hashit.check(&self.inputs, stamp)?;
+        if pending.is_none() && !force {
+            return Ok(false);
+        }
+        regenerate().map_err(|e| HashitError::Regenerate(e.into()))?;
+        if let Some(hash) = pending {
+            hashit.commit(stamp, &hash)?;
+        }
+        Ok(true)
+    }
+}
+
+/// Regenerate out from the inputs matched by the globs, when they have
+/// changed or out is missing.
+///
+/// - Emits `cargo:rerun-if-changed` for the inputs
+/// - Resolves a relative out against OUT_DIR
+/// - Stores the stamp alongside out, as `<out>.hashtest`
+/// - Invokes regenerate with the resolved out, only updating the stamp if it
+///   succeeds
+///
+/// Returns whether regenerate was invoked.
+pub fn regenerate_if_changed<P, O, F, E>(inputs: &[P], out: O, regenerate: F) -> Result<bool>
+where
+    P: AsRef<str>,
+    O: AsRef<Path>,
+    F: FnOnce(&Path) -> std::result::Result<(), E>,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let out = out.as_ref();
+    let out = if out.is_absolute() {
+        out.to_path_buf()
+    } else {
+        out_dir()?.join(out)
+    };
+    let mut stamp = out.clone().into_os_string();
+    stamp.push(".hashtest");
+
+    let build = Build::new(inputs)?.with_stamp(stamp);
+    build.emit_rerun_if_changed();
+    build.regenerate(!out.exists(), || regenerate(&out))
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/build_test.rs"]
+mod tests;
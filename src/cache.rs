@@ -0,0 +1,367 @@
+//! Cache
+//!
+//! A local, content addressed store of build outputs, keyed by the digest of
+//! the inputs which produced them (see Hashit::digest). After a run, the
+//! outputs are saved under the key. When the same set of inputs is seen again,
+//! for instance after switching back to a branch, the outputs are restored
+//! instead of being regenerated.
+//!
+//! The store is laid out as
+//!
+//! ```text
+//! <root>/objects/<digest>   file contents, named by their Blake2b digest
+//! <root>/entries/<key>      one line per output: <digest>\t<mode>\t<path>
+//! ```
+//!
+//! Identical outputs are only stored once, however many entries refer to them.
+//! The modification time of an entry records when it was last used, and is
+//! what the least recently used eviction operates on. Before evicting
+//! anything, objects which no entry refers to and temporary files left behind
+//! by an interrupted write are swept away. An object which no longer matches
+//! its digest is dropped rather than restored.
+use crate::error::{HashitError, Result};
+use crate::utils::{blake_hash, read_file};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+// Unreferenced objects and temporary files younger than this may belong to a
+// save which is still underway in another process, so are left alone
+const SWEEP_GRACE: Duration = Duration::from_secs(600);
+
+/// A single output recorded in an entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Artifact {
+    pub digest: String,
+    pub mode: u32,
+    pub path: PathBuf,
+}
+
+/// A content addressed store of outputs
+#[derive(Debug, Clone)]
+pub struct ArtifactCache {
+    root: PathBuf,
+    max_size: Option<u64>,
+}
+
+// keys and digests name files within the store, so we only accept hex
+pub(crate) fn validate_key(key: &str) -> Result<()> {
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(HashitError::InvalidCacheKey(key.to_string()));
+    }
+    Ok(())
+}
+
+// write contents to path via a temporary file, so that readers never observe
+// a partially written file
+fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut tmp = path.as_os_str().to_os_string();
+    tmp.push(format!(".tmp{}", std::process::id()));
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Serialize the artifacts of an entry
+pub fn format_entry(artifacts: &[Artifact]) -> String {
+    artifacts
+        .iter()
+        .map(|a| format!("{}\t{:o}\t{}\n", a.digest, a.mode, a.path.display()))
+        .collect()
+}
+
+/// Parse the artifacts of the entry for key. See format_entry.
+pub fn parse_entry(key: &str, contents: &[u8]) -> Result<Vec<Artifact>> {
+    let contents = String::from_utf8_lossy(contents);
+    let mut artifacts = Vec::new();
+    for line in contents.lines() {
+        let mut fields = line.splitn(3, '\t');
+        let artifact = match (fields.next(), fields.next(), fields.next()) {
+            (Some(digest), Some(mode), Some(path)) if validate_key(digest).is_ok() => {
+                u32::from_str_radix(mode, 8).ok().map(|mode| Artifact {
+                    digest: digest.to_string(),
+                    mode,
+                    path: PathBuf::from(path),
+                })
+            }
+            _ => None,
+        };
+        match artifact {
+            Some(artifact) => artifacts.push(artifact),
+            None => return Err(HashitError::CorruptCacheEntry(key.to_string())),
+        }
+    }
+    Ok(artifacts)
+}
+
+impl ArtifactCache {
+    /// New up a cache rooted at root. The directory is created on first save.
+    pub fn new<P>(root: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        ArtifactCache {
+            root: root.as_ref().to_path_buf(),
+            max_size: None,
+        }
+    }
+
+    /// Limit the total size of the cache, in bytes. The least recently used
+    /// entries are evicted when a save takes the cache over the limit.
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.root.join("entries").join(key)
+    }
+
+    fn object_path(&self, digest: &str) -> PathBuf {
+        self.root.join("objects").join(digest)
+    }
+
+    /// Whether an entry exists for key
+    pub fn contains(&self, key: &str) -> bool {
+        validate_key(key).is_ok() && self.entry_path(key).is_file()
+    }
+
+    /// Read the artifacts recorded under key, if any
+    pub fn entry(&self, key: &str) -> Result<Option<Vec<Artifact>>> {
+        validate_key(key)?;
+        let path = self.entry_path(key);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(parse_entry(key, &read_file(&path)?)?))
+    }
+
+    /// Store an object, returning its digest
+    pub fn put_object(&self, contents: &[u8]) -> Result<String> {
+        let digest = hex::encode(blake_hash(contents));
+        let path = self.object_path(&digest);
+        if !path.exists() {
+            write_atomic(&path, contents)?;
+        }
+        Ok(digest)
+    }
+
+    /// Retrieve an object by digest, if present. An object which fails
+    /// verification is removed, and treated as absent.
+    pub fn get_object(&self, digest: &str) -> Result<Option<Vec<u8>>> {
+        validate_key(digest)?;
+        let path = self.object_path(digest);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let object = read_file(&path)?;
+        if hex::encode(blake_hash(&object)) != digest.to_lowercase() {
+            fs::remove_file(&path)?;
+            return Ok(None);
+        }
+        Ok(Some(object))
+    }
+
+    /// Record an entry for key, given artifacts whose objects are already
+    /// stored
+    pub fn put_entry(&self, key: &str, artifacts: &[Artifact]) -> Result<()> {
+        validate_key(key)?;
+        write_atomic(&self.entry_path(key), format_entry(artifacts).as_bytes())?;
+        self.evict()
+    }
+
+    /// Save the outputs under key, replacing any existing entry
+    pub fn save<P>(&self, key: &str, outputs: &[P]) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        validate_key(key)?;
+        let mut artifacts = Vec::new();
+        for output in outputs {
+            let path = output.as_ref();
+            let mode = fs::metadata(path)?.permissions().mode() & 0o7777;
+            artifacts.push(Artifact {
+                digest: self.put_object(&read_file(path)?)?,
+                mode,
+                path: path.to_path_buf(),
+            });
+        }
+        self.put_entry(key, &artifacts)
+    }
+
+    /// Restore the outputs saved under key to their original paths. Returns
+    /// false if there is no entry for key, or any of its objects has gone
+    /// missing, in which case nothing is written.
+    pub fn restore(&self, key: &str) -> Result<bool> {
+        let artifacts = match self.entry(key)? {
+            Some(artifacts) => artifacts,
+            None => return Ok(false),
+        };
+        // make sure that everything is present before writing anything
+        let mut contents = Vec::with_capacity(artifacts.len());
+        for artifact in &artifacts {
+            match self.get_object(&artifact.digest)? {
+                Some(object) => contents.push(object),
+                None => return Ok(false),
+            }
+        }
+        for (artifact, object) in artifacts.iter().zip(contents) {
+            write_atomic(&artifact.path, &object)?;
+            fs::set_permissions(&artifact.path, fs::Permissions::from_mode(artifact.mode))?;
+        }
+        // record the use for the benefit of eviction
+        fs::File::options()
+            .write(true)
+            .open(self.entry_path(key))?
+            .set_modified(SystemTime::now())?;
+        Ok(true)
+    }
+
+    /// Restore the outputs for key if present. Otherwise invoke run, and save
+    /// the outputs once it succeeds. Returns whether the outputs were restored.
+    pub fn restore_or_run<P, F>(&self, key: &str, outputs: &[P], run: F) -> Result<bool>
+    where
+        P: AsRef<Path>,
+        F: FnOnce() -> Result<()>,
+    {
+        if self.restore(key)? {
+            return Ok(true);
+        }
+        run()?;
+        self.save(key, outputs)?;
+        Ok(false)
+    }
+
+    // the size of every file in dir, keyed by name
+    fn sizes(dir: &Path) -> Result<HashMap<String, (u64, SystemTime)>> {
+        let mut sizes = HashMap::new();
+        if !dir.is_dir() {
+            return Ok(sizes);
+        }
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                sizes.insert(
+                    entry.file_name().to_string_lossy().to_string(),
+                    (metadata.len(), metadata.modified()?),
+                );
+            }
+        }
+        Ok(sizes)
+    }
+
+    /// The total size of the cache, in bytes
+    pub fn size(&self) -> Result<u64> {
+        let entries = Self::sizes(&self.root.join("entries"))?;
+        let objects = Self::sizes(&self.root.join("objects"))?;
+        Ok(entries
+            .values()
+            .chain(objects.values())
+            .map(|(s, _)| s)
+            .sum())
+    }
+
+    // Remove objects which no entry refers to, and the temporary files of
+    // writes which never completed, sparing anything too recent to be sure
+    // of. Returns the number of bytes freed.
+    fn sweep(&self) -> Result<u64> {
+        let entries = Self::sizes(&self.root.join("entries"))?;
+        let objects = Self::sizes(&self.root.join("objects"))?;
+        let stale = |modified: &SystemTime| {
+            SystemTime::now()
+                .duration_since(*modified)
+                .is_ok_and(|age| age >= SWEEP_GRACE)
+        };
+        let mut referenced = HashSet::new();
+        let mut freed = 0;
+        for (name, (size, modified)) in &entries {
+            if validate_key(name).is_err() {
+                if stale(modified) {
+                    fs::remove_file(self.entry_path(name))?;
+                    freed += size;
+                }
+                continue;
+            }
+            for artifact in self.entry(name).ok().flatten().unwrap_or_default() {
+                referenced.insert(artifact.digest);
+            }
+        }
+        for (name, (size, modified)) in &objects {
+            if !referenced.contains(name) && stale(modified) {
+                fs::remove_file(self.object_path(name))?;
+                freed += size;
+            }
+        }
+        Ok(freed)
+    }
+
+    /// Evict the least recently used entries until the cache fits within its
+    /// size limit. Unreferenced objects and abandoned temporary files are
+    /// swept first, and objects are removed once no remaining entry refers to
+    /// them.
+    pub fn evict(&self) -> Result<()> {
+        let max_size = match self.max_size {
+            Some(max_size) => max_size,
+            None => return Ok(()),
+        };
+        let size = self.size()?;
+        if size <= max_size || size.saturating_sub(self.sweep()?) <= max_size {
+            return Ok(());
+        }
+        let entries = Self::sizes(&self.root.join("entries"))?;
+        let objects = Self::sizes(&self.root.join("objects"))?;
+        let mut total: u64 = entries
+            .values()
+            .chain(objects.values())
+            .map(|(s, _)| s)
+            .sum();
+
+        let mut refs: HashMap<String, usize> = HashMap::new();
+        let mut lru = Vec::new();
+        for (key, (size, modified)) in entries {
+            // a temporary file, which sweep leaves to its writer for now
+            if validate_key(&key).is_err() {
+                continue;
+            }
+            let artifacts = self.entry(&key).ok().flatten().unwrap_or_default();
+            for artifact in &artifacts {
+                *refs.entry(artifact.digest.clone()).or_insert(0) += 1;
+            }
+            lru.push((modified, key, size, artifacts));
+        }
+        lru.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+
+        for (_, key, size, artifacts) in lru {
+            if total <= max_size {
+                break;
+            }
+            fs::remove_file(self.entry_path(&key))?;
+            total = total.saturating_sub(size);
+            for artifact in artifacts {
+                let count = refs.entry(artifact.digest.clone()).or_insert(1);
+                *count -= 1;
+                if *count == 0 {
+                    if let Some((size, _)) = objects.get(&artifact.digest) {
+                        fs::remove_file(self.object_path(&artifact.digest))?;
+                        total = total.saturating_sub(*size);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/cache_test.rs"]
+mod tests;
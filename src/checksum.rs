@@ -0,0 +1,299 @@
+//! Checksum
+//!
+//! coreutils compatible checksum files. `sum_line` produces lines in the
+//! format written by `b2sum`, and `check` verifies files written by either
+//! `b2sum` or `sha256sum`, in both their default and `--tag` formats:
+//!
+//! ```text
+//! <hex digest>  <path>
+//! <hex digest> *<path>
+//! BLAKE2b (<path>) = <hex digest>
+//! SHA256 (<path>) = <hex digest>
+//! ```
+//!
+//! Paths containing a backslash or newline are escaped, and the line is
+//! prefixed with a backslash, matching coreutils.
+use crate::error::{HashitError, Result};
+use crate::utils::{blake_hash, read_file};
+use sha2::{Digest, Sha256};
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The digest algorithms understood by check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Blake2b512,
+    Sha256,
+}
+
+impl Algorithm {
+    /// The name used by coreutils in `--tag` format
+    pub fn tag(&self) -> &'static str {
+        match self {
+            Self::Blake2b512 => "BLAKE2b",
+            Self::Sha256 => "SHA256",
+        }
+    }
+
+    /// The length of the digest, in bytes
+    pub fn digest_len(&self) -> usize {
+        match self {
+            Self::Blake2b512 => 64,
+            Self::Sha256 => 32,
+        }
+    }
+
+    /// Infer the algorithm from the length of a hex encoded digest
+    pub fn from_hex_len(len: usize) -> Option<Self> {
+        match len {
+            128 => Some(Self::Blake2b512),
+            64 => Some(Self::Sha256),
+            _ => None,
+        }
+    }
+
+    /// Calculate the digest of bytes
+    pub fn digest(&self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Blake2b512 => blake_hash(bytes),
+            Self::Sha256 => Sha256::digest(bytes).to_vec(),
+        }
+    }
+
+    /// Calculate the digest of the file at path
+    pub fn digest_file<P>(&self, path: P) -> Result<Vec<u8>>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(self.digest(&read_file(path)?))
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = HashitError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "blake2b" | "blake2b512" | "blake2b-512" | "b2" => Ok(Self::Blake2b512),
+            "sha256" | "sha-256" => Ok(Self::Sha256),
+            _ => Err(HashitError::UnknownAlgorithm(s.to_string())),
+        }
+    }
+}
+
+/// A single line of a checksum file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub digest: String,
+    /// Set when the line names its algorithm (`--tag` format)
+    pub algorithm: Option<Algorithm>,
+}
+
+/// The outcome of verifying a single entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Failed,
+    /// The file could not be read
+    Unreadable(String),
+    /// The line, numbered from one, is not a checksum line. The path of the
+    /// outcome holds the text of the line.
+    Malformed(usize),
+}
+
+/// The result of verifying a single entry of a checksum file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub path: PathBuf,
+    pub status: Status,
+}
+
+impl Outcome {
+    /// Format the outcome the way coreutils does
+    pub fn line(&self) -> String {
+        let path = self.path.to_string_lossy();
+        match &self.status {
+            Status::Ok => format!("{}: OK", path),
+            Status::Failed => format!("{}: FAILED", path),
+            Status::Unreadable(_) => format!("{}: FAILED open or read", path),
+            Status::Malformed(line) => format!("{}: improperly formatted checksum line", line),
+        }
+    }
+}
+
+pub(crate) fn escape(path: &str) -> (bool, String) {
+    if path.contains('\\') || path.contains('\n') {
+        (true, path.replace('\\', "\\\\").replace('\n', "\\n"))
+    } else {
+        (false, path.to_string())
+    }
+}
+
+fn unescape(path: &str) -> String {
+    let mut result = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some(other) => result.push(other),
+                None => result.push('\\'),
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+fn is_hex(digest: &str) -> bool {
+    !digest.is_empty() && digest.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Return a `b2sum` compatible line for the file at path
+pub fn sum_line<P>(path: P) -> Result<String>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let digest = hex::encode(Algorithm::Blake2b512.digest_file(path)?);
+    let (escaped, name) = escape(&path.to_string_lossy());
+    Ok(format!(
+        "{}{}  {}",
+        if escaped { "\\" } else { "" },
+        digest,
+        name
+    ))
+}
+
+/// Return a line in coreutils `--tag` format for the file at path
+pub fn tag_line<P>(path: P, algorithm: Algorithm) -> Result<String>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let digest = hex::encode(algorithm.digest_file(path)?);
+    let (escaped, name) = escape(&path.to_string_lossy());
+    Ok(format!(
+        "{}{} ({}) = {}",
+        if escaped { "\\" } else { "" },
+        algorithm.tag(),
+        name,
+        digest
+    ))
+}
+
+/// Parse a single line of a checksum file. line_no is used for reporting.
+pub fn parse_line(line: &str, line_no: usize) -> Result<Entry> {
+    let malformed = || HashitError::MalformedChecksum {
+        line: line_no,
+        text: line.to_string(),
+    };
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let name = |raw: &str| {
+        if escaped {
+            PathBuf::from(unescape(raw))
+        } else {
+            PathBuf::from(raw)
+        }
+    };
+
+    // default format: digest, a space, a mode character (' ' or '*'), path.
+    // This is tried first, as a path may itself look like the --tag format.
+    if let Some(idx) = line.find(' ') {
+        let digest = &line[..idx];
+        let rest = &line[idx + 1..];
+        let path = rest.strip_prefix(' ').or_else(|| rest.strip_prefix('*'));
+        if let Some(path) = path.filter(|p| !p.is_empty() && is_hex(digest)) {
+            return Ok(Entry {
+                path: name(path),
+                digest: digest.to_lowercase(),
+                algorithm: None,
+            });
+        }
+    }
+
+    // --tag format: ALGO (path) = digest, where ALGO is one we know
+    let open = line.find(" (").ok_or_else(malformed)?;
+    let close = line.rfind(") = ").ok_or_else(malformed)?;
+    let algorithm = Algorithm::from_str(&line[..open]).map_err(|_| malformed())?;
+    let digest = &line[close + 4..];
+    if open + 2 > close || !is_hex(digest) {
+        return Err(malformed());
+    }
+    Ok(Entry {
+        path: name(&line[open + 2..close]),
+        digest: digest.to_lowercase(),
+        algorithm: Some(algorithm),
+    })
+}
+
+/// Verify the entries of a checksum file, read from reader.
+///
+/// The algorithm of each entry is taken from the line itself in `--tag`
+/// format. Otherwise algorithm is used if supplied, falling back to inferring
+/// the algorithm from the length of the digest.
+///
+/// As with coreutils, a malformed line does not stop the check. It is
+/// reported as an outcome of its own, and the remaining lines are verified.
+/// Blank lines, and comment lines starting with `#`, are skipped, which is
+/// also what `b2sum -c` does (GNU coreutils 9.1 was checked), rather than
+/// being reported as improperly formatted.
+pub fn check<R>(reader: R, algorithm: Option<Algorithm>) -> Result<Vec<Outcome>>
+where
+    R: BufRead,
+{
+    let mut outcomes = Vec::new();
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry = parse_line(&line, idx + 1).ok().and_then(|entry| {
+            let algorithm = entry
+                .algorithm
+                .or(algorithm)
+                .or_else(|| Algorithm::from_hex_len(entry.digest.len()))
+                .filter(|a| a.digest_len() * 2 == entry.digest.len())?;
+            Some((entry, algorithm))
+        });
+        let (entry, algorithm) = match entry {
+            Some(entry) => entry,
+            None => {
+                outcomes.push(Outcome {
+                    path: PathBuf::from(line),
+                    status: Status::Malformed(idx + 1),
+                });
+                continue;
+            }
+        };
+        let status = match algorithm.digest_file(&entry.path) {
+            Ok(digest) if hex::encode(&digest) == entry.digest => Status::Ok,
+            Ok(_) => Status::Failed,
+            Err(e) => Status::Unreadable(e.to_string()),
+        };
+        outcomes.push(Outcome {
+            path: entry.path,
+            status,
+        });
+    }
+    Ok(outcomes)
+}
+
+/// Verify the checksum file at path. See check.
+pub fn check_file<P>(path: P, algorithm: Option<Algorithm>) -> Result<Vec<Outcome>>
+where
+    P: AsRef<Path>,
+{
+    let contents = read_file(path)?;
+    check(&contents[..], algorithm)
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/checksum_test.rs"]
+mod tests;
@@ -0,0 +1,371 @@
+//! Chunk
+//!
+//! Splits large files into content defined chunks, in the manner of FastCDC,
+//! and hashes each chunk separately. Chunk boundaries are chosen by a rolling
+//! hash over the contents rather than at fixed offsets, so an insertion or
+//! deletion only disturbs the chunks around it. Comparing the chunks of two
+//! versions of a file then shows which byte ranges changed, and how much of
+//! the file that amounts to.
+//!
+//! A file is summarized by a ChunkList, one chunk per line, in order:
+//!
+//! ```text
+//! <offset>\t<length>\t<hex digest>
+//! ```
+//!
+//! The digest of a file is the digest of its chunk list. Files are streamed,
+//! so no more than the largest chunk is held in memory.
+use crate::error::{HashitError, Result};
+use crate::traits::CalcHash;
+use crate::utils::blake_hash;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io::{ErrorKind, Read};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+// splitmix64, used to fill the gear table
+const fn splitmix(state: u64) -> u64 {
+    let mut z = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut idx = 0;
+    while idx < 256 {
+        table[idx] = splitmix((idx as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        idx += 1;
+    }
+    table
+}
+
+// a random value for each byte, mixed into the rolling hash. Changing the
+// table moves every chunk boundary, invalidating recorded chunk lists.
+const GEAR: [u64; 256] = gear_table();
+
+/// Chooses chunk boundaries. Chunks are at least min and at most max bytes
+/// long, and avg bytes long on average.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunker {
+    min: usize,
+    avg: usize,
+    max: usize,
+    // a boundary is harder to find before avg, and easier after it, which
+    // keeps chunk sizes close to avg
+    mask_hard: u64,
+    mask_easy: u64,
+}
+
+impl Default for Chunker {
+    /// Chunks of 16 KiB to 256 KiB, averaging 64 KiB
+    fn default() -> Self {
+        Self::new(16 * 1024, 64 * 1024, 256 * 1024).unwrap()
+    }
+}
+
+impl Chunker {
+    /// New up a chunker. avg must be a power of two, from 256 bytes to 1 GiB,
+    /// lying between min and max.
+    pub fn new(min: usize, avg: usize, max: usize) -> Result<Self> {
+        let sizes = 256..=1 << 30;
+        if !avg.is_power_of_two() || !sizes.contains(&avg) || min > avg || avg > max {
+            return Err(HashitError::InvalidChunkSize(format!(
+                "min {}, avg {}, max {}",
+                min, avg, max
+            )));
+        }
+        let bits = avg.trailing_zeros();
+        // boundaries are found in the high bits of the hash, which depend
+        // upon the last 64 bytes seen
+        let mask = |bits: u32| !0u64 << (64 - bits);
+        Ok(Chunker {
+            min,
+            avg,
+            max,
+            mask_hard: mask(bits + 2),
+            mask_easy: mask(bits - 2),
+        })
+    }
+
+    // the length of the chunk at the start of data, which holds at least max
+    // bytes unless the end of the input has been reached
+    fn cut(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min {
+            return data.len();
+        }
+        let end = data.len().min(self.max);
+        let normal = end.min(self.avg);
+        let mut hash = 0u64;
+        for (idx, byte) in data.iter().enumerate().take(end).skip(self.min) {
+            hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+            let mask = if idx < normal {
+                self.mask_hard
+            } else {
+                self.mask_easy
+            };
+            if hash & mask == 0 {
+                return idx + 1;
+            }
+        }
+        end
+    }
+
+    /// Split the contents of reader into chunks
+    pub fn chunks<R>(&self, mut reader: R) -> Result<ChunkList>
+    where
+        R: Read,
+    {
+        let mut buffer = Vec::with_capacity(self.max);
+        let mut chunks = Vec::new();
+        let mut offset = 0;
+        let mut eof = false;
+        loop {
+            while !eof && buffer.len() < self.max {
+                let start = buffer.len();
+                buffer.resize(self.max, 0);
+                match reader.read(&mut buffer[start..]) {
+                    Ok(read) => {
+                        buffer.truncate(start + read);
+                        eof = read == 0;
+                    }
+                    Err(e) if e.kind() == ErrorKind::Interrupted => buffer.truncate(start),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            if buffer.is_empty() {
+                break;
+            }
+            let len = self.cut(&buffer);
+            chunks.push(Chunk {
+                offset,
+                len: len as u64,
+                digest: blake_hash(&buffer[..len]),
+            });
+            offset += len as u64;
+            buffer.drain(..len);
+        }
+        Ok(ChunkList { chunks })
+    }
+
+    /// Split the contents of the file at path into chunks
+    pub fn chunk_file<P>(&self, path: P) -> Result<ChunkList>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let file = fs::File::open(path).map_err(|e| {
+            if e.kind() == ErrorKind::NotFound {
+                HashitError::NotFound {
+                    source: e,
+                    file: PathBuf::from(path),
+                }
+            } else {
+                e.into()
+            }
+        })?;
+        self.chunks(file)
+    }
+}
+
+/// A single chunk of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub offset: u64,
+    pub len: u64,
+    pub digest: Vec<u8>,
+}
+
+/// The chunks of a file, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChunkList {
+    chunks: Vec<Chunk>,
+}
+
+impl ChunkList {
+    /// Parse a chunk list, as written by Display
+    pub fn parse(contents: &str) -> Option<Self> {
+        let chunks = contents
+            .lines()
+            .map(|line| {
+                let mut fields = line.splitn(3, '\t');
+                Some(Chunk {
+                    offset: fields.next()?.parse().ok()?,
+                    len: fields.next()?.parse().ok()?,
+                    digest: hex::decode(fields.next()?).ok()?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(ChunkList { chunks })
+    }
+
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+
+    /// The size of the file, in bytes
+    pub fn size(&self) -> u64 {
+        self.chunks.last().map_or(0, |c| c.offset + c.len)
+    }
+
+    /// The digest of the chunk list, which stands in for that of the file
+    pub fn digest(&self) -> Vec<u8> {
+        blake_hash(self.to_string().as_bytes())
+    }
+
+    /// The byte ranges whose contents are not found anywhere in previous,
+    /// with adjacent ranges merged
+    pub fn changed_ranges(&self, previous: &ChunkList) -> Vec<Range<u64>> {
+        let known = previous
+            .chunks
+            .iter()
+            .map(|c| &c.digest)
+            .collect::<HashSet<_>>();
+        let mut ranges: Vec<Range<u64>> = Vec::new();
+        for chunk in self.chunks.iter().filter(|c| !known.contains(&c.digest)) {
+            match ranges.last_mut() {
+                Some(last) if last.end == chunk.offset => last.end += chunk.len,
+                _ => ranges.push(chunk.offset..chunk.offset + chunk.len),
+            }
+        }
+        ranges
+    }
+
+    /// Describe what changed since previous: each changed byte range along
+    /// with the percentage of the file it makes up, or the number of bytes
+    /// removed when nothing new was added
+    pub fn diff(&self, previous: &ChunkList) -> Vec<String> {
+        let size = self.size();
+        let percent = |len: u64| match size {
+            0 => 100.0,
+            size => len as f64 * 100.0 / size as f64,
+        };
+        let changed = self
+            .changed_ranges(previous)
+            .into_iter()
+            .map(|range| {
+                let pct = percent(range.end - range.start);
+                format!("bytes {}..{} ({:.1}%)", range.start, range.end, pct)
+            })
+            .collect::<Vec<_>>();
+        let removed = previous.size().saturating_sub(size);
+        if changed.is_empty() && removed > 0 {
+            return vec![format!("{} bytes removed", removed)];
+        }
+        changed
+    }
+}
+
+impl fmt::Display for ChunkList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chunk in &self.chunks {
+            writeln!(
+                f,
+                "{}\t{}\t{}",
+                chunk.offset,
+                chunk.len,
+                hex::encode(&chunk.digest)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A CalcHash which hashes files of at least a minimum size by their chunks.
+/// Smaller files, and directories, are hashed by the fallback.
+#[derive(Debug, Clone)]
+pub struct ChunkedHash<H> {
+    fallback: H,
+    chunker: Chunker,
+    min_size: u64,
+    chunk_lists: Option<PathBuf>,
+}
+
+impl<H> ChunkedHash<H> {
+    /// New up a ChunkedHash which chunks every file with the default Chunker
+    pub fn new(fallback: H) -> Self {
+        ChunkedHash {
+            fallback,
+            chunker: Chunker::default(),
+            min_size: 0,
+            chunk_lists: None,
+        }
+    }
+
+    pub fn with_chunker(mut self, chunker: Chunker) -> Self {
+        self.chunker = chunker;
+        self
+    }
+
+    /// Leave files smaller than min_size bytes to the fallback
+    pub fn with_min_size(mut self, min_size: u64) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Keep the chunk list of each file hashed within dir, named by its
+    /// digest, so that changed_entries is able to compare them
+    pub fn with_chunk_lists<P>(mut self, dir: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.chunk_lists = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    fn load(&self, digest: &[u8]) -> Option<ChunkList> {
+        let path = self.chunk_lists.as_ref()?.join(hex::encode(digest));
+        ChunkList::parse(&fs::read_to_string(path).ok()?)
+    }
+
+    fn store(&self, digest: &[u8], chunks: &ChunkList) -> Result<()> {
+        if let Some(dir) = &self.chunk_lists {
+            let path = dir.join(hex::encode(digest));
+            if !path.exists() {
+                fs::create_dir_all(dir)?;
+                fs::write(path, chunks.to_string())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<H: CalcHash> CalcHash for ChunkedHash<H> {
+    fn calc_hash<P>(&self, files: &[P]) -> Result<Vec<u8>>
+    where
+        P: AsRef<str>,
+    {
+        let mut resvec = Vec::new();
+        for f in files {
+            let chunked = fs::metadata(f.as_ref())
+                .is_ok_and(|metadata| metadata.is_file() && metadata.len() >= self.min_size);
+            if chunked {
+                let chunks = self.chunker.chunk_file(f.as_ref())?;
+                let digest = chunks.digest();
+                self.store(&digest, &chunks)?;
+                resvec.extend(digest);
+            } else {
+                resvec.extend(self.fallback.calc_hash(&[f])?);
+            }
+        }
+        Ok(resvec)
+    }
+
+    fn changed_entries(&self, input: &str, old: &[u8], new: &[u8]) -> Vec<String> {
+        match (self.load(old), self.load(new)) {
+            (Some(old), Some(new)) => new.diff(&old),
+            _ => self.fallback.changed_entries(input, old, new),
+        }
+    }
+
+    fn committed(&self, output: &str, hash: &[u8]) -> Result<()> {
+        self.fallback.committed(output, hash)
+    }
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/chunk_test.rs"]
+mod tests;
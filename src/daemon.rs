@@ -0,0 +1,540 @@
+//! Daemon
+//!
+//! An optional long running process which keeps per-file digests in memory,
+//! and answers requests over a Unix domain socket. The digests are invalidated
+//! via inotify whenever a file changes, so repeated checks of a large, mostly
+//! unchanged tree only rehash the files which were actually touched.
+//!
+//! The protocol is line based. A request consists of a command line, followed
+//! by one absolute input path per line, and is terminated by an empty line:
+//!
+//! ```text
+//! check /path/to/stamp
+//! /path/to/input1
+//! /path/to/input2
+//!
+//! ```
+//!
+//! The supported commands are
+//! - `hash` - responds with the hex digest of each input, one per line
+//! - `check <stamp>` - responds with `changed` or `unchanged`
+//! - `commit <stamp>` - stores the inputs' hash in stamp, responding with `ok`
+//! - `shutdown` - stops the daemon, responding with `ok`
+//!
+//! Failures are reported as a single `error <message>` line. Requests time
+//! out after CLIENT_TIMEOUT, on both sides.
+//!
+//! FileHash transparently asks a running daemon for digests, falling back to
+//! hashing the files itself if no daemon is listening. Digests are only
+//! trusted from a socket owned by the current user, in a directory which no
+//! one else may replace it in, and from a daemon running as the current user.
+use crate::error::{HashitError, Result};
+use crate::file::HtFile;
+use crate::hashit::Hashit;
+use crate::traits::CalcHash;
+use crate::utils::{blake_hash, read_file};
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+lazy_static! {
+    // whether the daemon on each socket is usable, decided once per process
+    static ref AVAILABLE: Mutex<HashMap<PathBuf, bool>> = Mutex::new(HashMap::new());
+}
+
+/// Environment variable used to override the location of the daemon's socket
+pub const SOCKET_ENV: &str = "HASHTEST_SOCKET";
+/// Environment variable which, when set, prevents FileHash from using the daemon
+pub const NO_DAEMON_ENV: &str = "HASHTEST_NO_DAEMON";
+/// How long either side waits on the other before giving up on a request.
+/// FileHash hashes the files itself when the daemon fails to answer in time.
+pub const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The path of the socket used when none is specified. This is the value of
+/// HASHTEST_SOCKET if set, otherwise hashtest.sock in XDG_RUNTIME_DIR, falling
+/// back to a per-user socket in the temp directory.
+pub fn default_socket_path() -> PathBuf {
+    if let Some(path) = std::env::var_os(SOCKET_ENV) {
+        return PathBuf::from(path);
+    }
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+        return Path::new(&dir).join("hashtest.sock");
+    }
+    let user = std::env::var("USER").unwrap_or_else(|_| "default".to_string());
+    std::env::temp_dir().join(format!("hashtest-{}.sock", user))
+}
+
+// Whether the socket at path may be trusted: it must be a socket owned by
+// us, within a directory which only we (or root) may write to, or which is
+// sticky, so that no one else can have created or replaced it.
+fn trusted(path: &Path) -> bool {
+    let uid = unsafe { libc::getuid() };
+    let socket = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return false,
+    };
+    if !socket.file_type().is_socket() || socket.uid() != uid {
+        return false;
+    }
+    let dir = match path.parent().map(std::fs::metadata) {
+        Some(Ok(metadata)) => metadata,
+        _ => return false,
+    };
+    let owner = dir.uid() == uid || dir.uid() == 0;
+    let shared = dir.mode() & 0o022 != 0;
+    let sticky = dir.mode() & 0o1000 != 0;
+    owner && (!shared || sticky)
+}
+
+// the uid of the process on the other end of stream
+fn peer_uid(stream: &UnixStream) -> Result<libc::uid_t> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let rc = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(cred.uid)
+}
+
+// The daemon may well have a different working directory than its clients
+fn absolute<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
+    let path = path.as_ref();
+    if path.is_absolute() {
+        Ok(path.to_path_buf())
+    } else {
+        Ok(std::env::current_dir()?.join(path))
+    }
+}
+
+#[derive(Debug)]
+struct CacheState {
+    inotify: Inotify,
+    digests: HashMap<PathBuf, Vec<u8>>,
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+}
+
+/// A CalcHash implementation which caches the digest of each file, until
+/// inotify reports that the file has changed.
+#[derive(Debug)]
+pub struct CachedHash {
+    state: Mutex<CacheState>,
+}
+
+impl CachedHash {
+    pub fn new() -> Result<Self> {
+        Ok(CachedHash {
+            state: Mutex::new(CacheState {
+                inotify: Inotify::init()?,
+                digests: HashMap::new(),
+                dirs: HashMap::new(),
+            }),
+        })
+    }
+
+    /// Process pending inotify events, discarding the digests of any files
+    /// which have changed since they were cached.
+    pub fn refresh(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let mut buffer = [0; 4096];
+        loop {
+            let mut stale = Vec::new();
+            let mut overflow = false;
+            let mut count = 0;
+            for event in state.inotify.read_events(&mut buffer)? {
+                count += 1;
+                if event.mask.contains(EventMask::Q_OVERFLOW) {
+                    overflow = true;
+                    continue;
+                }
+                if let Some(dir) = state.dirs.get(&event.wd) {
+                    match event.name {
+                        Some(name) => stale.push((dir.join(name), false)),
+                        // the directory itself went away
+                        None => stale.push((dir.clone(), true)),
+                    }
+                }
+                if event.mask.contains(EventMask::IGNORED) {
+                    state.dirs.remove(&event.wd);
+                }
+            }
+            if count == 0 {
+                return Ok(());
+            }
+            if overflow {
+                // we have lost track of what changed
+                state.digests.clear();
+            }
+            for (path, is_dir) in stale {
+                if is_dir {
+                    state.digests.retain(|k, _| !k.starts_with(&path));
+                } else {
+                    state.digests.remove(&path);
+                }
+            }
+        }
+    }
+
+    /// Forget every digest held in memory
+    pub fn clear(&self) {
+        self.state.lock().unwrap().digests.clear();
+    }
+
+    /// The number of digests currently held in memory
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().digests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn digest(&self, path: &Path) -> Result<Vec<u8>> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(digest) = state.digests.get(path) {
+            return Ok(digest.clone());
+        }
+        // register the watch before reading, so that a write which races
+        // with the read is guaranteed to invalidate the entry.
+        let dir = path.parent().unwrap_or_else(|| Path::new("/"));
+        let mask = WatchMask::MODIFY
+            | WatchMask::CLOSE_WRITE
+            | WatchMask::ATTRIB
+            | WatchMask::CREATE
+            | WatchMask::DELETE
+            | WatchMask::MOVED_FROM
+            | WatchMask::MOVED_TO
+            | WatchMask::DELETE_SELF
+            | WatchMask::MOVE_SELF;
+        let watched = match state.inotify.add_watch(dir, mask) {
+            Ok(wd) => {
+                state.dirs.insert(wd, dir.to_path_buf());
+                true
+            }
+            Err(_) => false,
+        };
+        let digest = blake_hash(&read_file(path)?);
+        if watched {
+            state.digests.insert(path.to_path_buf(), digest.clone());
+        }
+        Ok(digest)
+    }
+}
+
+impl CalcHash for CachedHash {
+    fn calc_hash<P>(&self, files: &[P]) -> Result<Vec<u8>>
+    where
+        P: AsRef<str>,
+    {
+        let mut resvec = Vec::new();
+        for f in files {
+            resvec.extend(self.digest(&absolute(f.as_ref())?)?);
+        }
+        Ok(resvec)
+    }
+}
+
+/// The server side of the protocol
+#[derive(Debug)]
+pub struct Daemon {
+    path: PathBuf,
+    listener: UnixListener,
+    hashit: Hashit<HtFile, CachedHash>,
+}
+
+// whether the daemon should keep serving after a request
+enum Next {
+    Continue,
+    Stop,
+}
+
+impl Daemon {
+    /// Bind the daemon to the socket at path. A stale socket left behind by a
+    /// daemon which is no longer running is replaced.
+    pub fn bind<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref().to_path_buf();
+        if path.exists() {
+            if UnixStream::connect(&path).is_ok() {
+                return Err(HashitError::Daemon(format!(
+                    "already running on {}",
+                    path.display()
+                )));
+            }
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        Ok(Daemon {
+            path,
+            listener,
+            hashit: Hashit::from_parts(HtFile::new(), CachedHash::new()?),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Serve requests until a shutdown request is received. A client which
+    /// misbehaves or goes away early is logged and otherwise ignored.
+    pub fn serve(&mut self) -> Result<()> {
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    eprintln!("hashtest daemon: accept failed: {}", e);
+                    continue;
+                }
+            };
+            // losing track of changes must not take the daemon down. Forget
+            // everything instead, so that it is all hashed afresh.
+            if let Err(e) = self.hashit.hasher().refresh() {
+                eprintln!("hashtest daemon: refresh failed: {}", e);
+                self.hashit.hasher().clear();
+            }
+            match self.respond(stream) {
+                Ok(Next::Continue) => (),
+                Ok(Next::Stop) => return Ok(()),
+                Err(e) => eprintln!("hashtest daemon: {}", e),
+            }
+        }
+    }
+
+    // handle a single request, writing the response back to the client
+    fn respond(&mut self, stream: UnixStream) -> Result<Next> {
+        // a client which stops talking must not wedge the daemon
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+        let mut writer = stream.try_clone()?;
+        match self.handle(stream) {
+            Ok((next, response)) => {
+                writer.write_all(response.as_bytes())?;
+                Ok(next)
+            }
+            Err(e) => {
+                writeln!(writer, "error {}", e.to_string().replace('\n', " "))?;
+                Ok(Next::Continue)
+            }
+        }
+    }
+
+    fn handle(&mut self, stream: UnixStream) -> Result<(Next, String)> {
+        let mut lines = BufReader::new(stream).lines();
+        let command = lines
+            .next()
+            .ok_or_else(|| HashitError::Daemon("empty request".to_string()))??;
+        let mut inputs = Vec::new();
+        for line in lines {
+            let line = line?;
+            if line.is_empty() {
+                break;
+            }
+            inputs.push(line);
+        }
+
+        let (verb, arg) = match command.find(' ') {
+            Some(idx) => (&command[..idx], Some(&command[idx + 1..])),
+            None => (command.as_str(), None),
+        };
+        let stamp = || arg.ok_or_else(|| HashitError::Daemon(format!("{} requires a stamp", verb)));
+        match verb {
+            "hash" => {
+                let mut response = String::new();
+                for input in &inputs {
+                    let digest = self.hashit.hasher().calc_hash(&[input])?;
+                    response.push_str(&hex::encode(digest));
+                    response.push('\n');
+                }
+                Ok((Next::Continue, response))
+            }
+            "check" => {
+                let changed = self.hashit.check(&inputs, stamp()?)?.is_some();
+                let response = if changed { "changed\n" } else { "unchanged\n" };
+                Ok((Next::Continue, response.to_string()))
+            }
+            "commit" => {
+                let stamp = stamp()?;
+                if let Some(hash) = self.hashit.check(&inputs, stamp)? {
+                    self.hashit.commit(stamp, &hash)?;
+                }
+                Ok((Next::Continue, "ok\n".to_string()))
+            }
+            "shutdown" => Ok((Next::Stop, "ok\n".to_string())),
+            _ => Err(HashitError::Daemon(format!("unknown command '{}'", verb))),
+        }
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// The client side of the protocol
+#[derive(Debug, Clone)]
+pub struct Client {
+    path: PathBuf,
+    timeout: Duration,
+}
+
+impl Client {
+    pub fn new<P>(path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Client {
+            path: path.as_ref().to_path_buf(),
+            timeout: CLIENT_TIMEOUT,
+        }
+    }
+
+    /// Give up on a request when the daemon has not answered within timeout.
+    /// Defaults to CLIENT_TIMEOUT.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Return a client for the default socket, if the daemon has not been
+    /// disabled via HASHTEST_NO_DAEMON, its socket may be trusted, and it
+    /// answers. The answer is remembered for the life of the process, as is
+    /// a daemon which later stops answering (see `unavailable`).
+    pub fn available() -> Option<Self> {
+        if std::env::var_os(NO_DAEMON_ENV).is_some() {
+            return None;
+        }
+        let path = default_socket_path();
+        let mut available = AVAILABLE.lock().unwrap();
+        let usable = *available.entry(path.clone()).or_insert_with(|| {
+            trusted(&path) && UnixStream::connect(&path).is_ok_and(|s| peer_uid(&s).is_ok())
+        });
+        if usable {
+            Some(Client::new(path))
+        } else {
+            None
+        }
+    }
+
+    /// Stop handing out clients for this daemon, for the life of the process
+    pub fn unavailable(&self) {
+        AVAILABLE.lock().unwrap().insert(self.path.clone(), false);
+    }
+
+    fn request<P>(&self, command: &str, inputs: &[P]) -> Result<Vec<String>>
+    where
+        P: AsRef<Path>,
+    {
+        let mut stream = UnixStream::connect(&self.path)?;
+        // someone else's daemon could answer with whatever digests it likes
+        let uid = peer_uid(&stream)?;
+        if uid != unsafe { libc::getuid() } {
+            self.unavailable();
+            return Err(HashitError::Daemon(format!(
+                "{} is served by uid {}",
+                self.path.display(),
+                uid
+            )));
+        }
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let mut request = format!("{}\n", command);
+        for input in inputs {
+            request.push_str(&absolute(input)?.to_string_lossy());
+            request.push('\n');
+        }
+        request.push('\n');
+        stream.write_all(request.as_bytes())?;
+
+        let lines = BufReader::new(stream)
+            .lines()
+            .collect::<std::io::Result<Vec<_>>>()?;
+        match lines.first() {
+            Some(line) if line.starts_with("error ") => {
+                Err(HashitError::Daemon(line["error ".len()..].to_string()))
+            }
+            _ => Ok(lines),
+        }
+    }
+
+    /// Retrieve the concatenated digests of the inputs
+    pub fn hash<P>(&self, inputs: &[P]) -> Result<Vec<u8>>
+    where
+        P: AsRef<Path>,
+    {
+        let lines = self.request("hash", inputs)?;
+        if lines.len() != inputs.len() {
+            return Err(HashitError::Daemon(format!(
+                "expected {} digests, received {}",
+                inputs.len(),
+                lines.len()
+            )));
+        }
+        let mut resvec = Vec::new();
+        for line in lines {
+            resvec.extend(hex::decode(&line).map_err(|e| HashitError::Daemon(e.to_string()))?);
+        }
+        Ok(resvec)
+    }
+
+    /// Ask the daemon whether the inputs differ from the hash stored in stamp
+    pub fn check<P, S>(&self, inputs: &[P], stamp: S) -> Result<bool>
+    where
+        P: AsRef<Path>,
+        S: AsRef<Path>,
+    {
+        let command = format!("check {}", absolute(stamp)?.to_string_lossy());
+        let lines = self.request(&command, inputs)?;
+        match lines.first().map(|x| x.as_str()) {
+            Some("changed") => Ok(true),
+            Some("unchanged") => Ok(false),
+            other => Err(HashitError::Daemon(format!(
+                "unexpected response {:?}",
+                other
+            ))),
+        }
+    }
+
+    /// Ask the daemon to store the inputs' hash in stamp
+    pub fn commit<P, S>(&self, inputs: &[P], stamp: S) -> Result<()>
+    where
+        P: AsRef<Path>,
+        S: AsRef<Path>,
+    {
+        let command = format!("commit {}", absolute(stamp)?.to_string_lossy());
+        self.request(&command, inputs)?;
+        Ok(())
+    }
+
+    /// Ask the daemon to exit
+    pub fn shutdown(&self) -> Result<()> {
+        self.request::<&str>("shutdown", &[])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/daemon_test.rs"]
+mod tests;
@@ -14,11 +14,77 @@ pub enum HashitError {
     #[error("Directory does not exist: '{0}'")]
     MissingDir(String),
 
+    #[error("Not within a git repository: '{0}'")]
+    NotARepository(String),
+
     #[error("Key does not exist: '{0}'")]
     MissingKey(String),
 
     #[error("{0} Not Implemented")]
     NotImplemented(String),
+
+    #[error("Target does not exist: '{0}'")]
+    MissingTarget(String),
+
+    #[error("Target defined more than once: '{0}'")]
+    DuplicateTarget(String),
+
+    #[error("Dependency cycle between targets: {0}")]
+    DependencyCycle(String),
+
+    #[error("Daemon error: {0}")]
+    Daemon(String),
+
+    #[error("Invalid glob pattern: {0}")]
+    InvalidGlob(String),
+
+    #[error("Unable to parse {file} - {message}")]
+    Parse {
+        file: std::path::PathBuf,
+        message: String,
+    },
+
+    #[error("Invalid regular expression: {0}")]
+    InvalidRegex(String),
+
+    #[error("Unknown digest algorithm: '{0}'")]
+    UnknownAlgorithm(String),
+
+    #[error("Improperly formatted checksum line {line}: '{text}'")]
+    MalformedChecksum { line: usize, text: String },
+
+    #[error("Improperly formatted dependency file line {line}: '{text}'")]
+    MalformedDepfile { line: usize, text: String },
+
+    #[error("Invalid cache key: '{0}'")]
+    InvalidCacheKey(String),
+
+    #[error("Invalid chunk sizes: {0}")]
+    InvalidChunkSize(String),
+
+    #[error("Unknown read strategy: '{0}'")]
+    UnknownReadStrategy(String),
+
+    #[error("Corrupt cache entry: '{0}'")]
+    CorruptCacheEntry(String),
+
+    #[error("Remote cache error: {0}")]
+    Remote(String),
+
+    #[error("{} file(s) do not match the lockfile:{}", .0.len(), crate::lock::format_mismatches(.0))]
+    LockMismatch(Vec<crate::lock::Mismatch>),
+
+    #[error("Stamp failed verification: '{0}'")]
+    StampVerification(String),
+
+    #[error("Invalid authentication key: {0}")]
+    InvalidAuthKey(String),
+
+    #[error("Unable to fingerprint {0}")]
+    Fingerprint(String),
+
+    #[error("Regeneration failed: {0}")]
+    Regenerate(Box<dyn std::error::Error + Send + Sync>),
 }
 
 pub type Result<R> = std::result::Result<R, HashitError>;
@@ -1,3 +1,4 @@
+use crate::daemon::Client;
 use crate::traits::{CalcHash, FetchCachedHash, Open, OpenMut};
 use crate::utils::{blake_hash, read_file};
 use crate::HashitError;
@@ -10,6 +11,7 @@ use std::path::PathBuf;
 #[derive(Debug)]
 pub struct HtFile {}
 
+#[allow(clippy::derivable_impls)]
 impl Default for HtFile {
     fn default() -> Self {
         HtFile {}
@@ -23,6 +25,7 @@ impl HtFile {
 impl Open for HtFile {
     type O = fs::File;
     //type E = HashitError;
+    #[allow(clippy::needless_question_mark)]
     fn open<I>(&self, input: I) -> std::result::Result<Self::O, HashitError>
     where
         I: AsRef<str>,
@@ -153,6 +156,18 @@ impl CalcHash for FileHash {
     where
         P: AsRef<str>,
     {
+        // prefer the digests held by a running daemon. If the daemon is unable
+        // to answer, we hash the files ourselves, which also ensures that
+        // errors are reported in the usual manner.
+        if let Some(client) = Client::available() {
+            let paths = files.iter().map(|f| f.as_ref()).collect::<Vec<_>>();
+            match client.hash(&paths) {
+                Ok(hash) => return Ok(hash),
+                // a daemon which has gone away is not asked again
+                Err(HashitError::IoError(_)) => client.unavailable(),
+                Err(_) => (),
+            }
+        }
         let mut resvec = Vec::new();
 
         for f in files {
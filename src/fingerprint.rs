@@ -0,0 +1,281 @@
+//! Fingerprint
+//!
+//! An output depends on more than its input files: the compiler version,
+//! RUSTFLAGS, the target triple and so on. A Fingerprint names those extra
+//! components, each of which contributes a digest to the stamp alongside
+//! the inputs, so that changing any of them counts as a change:
+//!
+//! - Env: the value of an environment variable. Unset is distinct from empty
+//! - Tool: the output of `<tool> --version`, run at most once per process
+//! - Value: an arbitrary key and value
+//! - Command: the stdout and exit status of a command, such as
+//!   `pkg-config --cflags foo` or `git rev-parse HEAD`, run every time
+use crate::error::{HashitError, Result};
+use crate::utils::blake_hash;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    // tool versions are cached for the life of the process
+    static ref TOOL_VERSIONS: Mutex<HashMap<String, Vec<u8>>> = Mutex::new(HashMap::new());
+}
+
+/// A single component of a fingerprint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Component {
+    Env(String),
+    Tool(String),
+    Value(String, String),
+    /// The program and its arguments, along with an optional timeout
+    Command(Vec<String>, Option<Duration>),
+}
+
+impl fmt::Display for Component {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Env(name) => write!(f, "env {}", name),
+            Self::Tool(name) => write!(f, "tool {}", name),
+            Self::Value(key, _) => write!(f, "value {}", key),
+            Self::Command(argv, _) => write!(f, "command {}", argv.join(" ")),
+        }
+    }
+}
+
+// run `tool --version`, caching the output
+fn tool_version(tool: &str) -> Result<Vec<u8>> {
+    let mut versions = TOOL_VERSIONS.lock().unwrap();
+    if let Some(version) = versions.get(tool) {
+        return Ok(version.clone());
+    }
+    let failed = |e: String| HashitError::Fingerprint(format!("{} --version: {}", tool, e));
+    let output = Command::new(tool)
+        .arg("--version")
+        .output()
+        .map_err(|e| failed(e.to_string()))?;
+    if !output.status.success() {
+        return Err(failed(output.status.to_string()));
+    }
+    // some tools report their version on stderr
+    let mut version = output.stdout;
+    version.extend(output.stderr);
+    versions.insert(tool.to_string(), version.clone());
+    Ok(version)
+}
+
+// kill the child, along with anything it started, which shares its process
+// group
+fn kill_group(child: &mut Child) {
+    unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+// run the command, returning its stdout followed by its exit status. The
+// command is killed if it outlives the timeout, as is anything it started
+// which still holds stdout open.
+fn command_output(argv: &[String], timeout: Option<Duration>) -> Result<Vec<u8>> {
+    let failed = |e: String| HashitError::Fingerprint(format!("{}: {}", argv.join(" "), e));
+    let (program, args) = argv
+        .split_first()
+        .ok_or_else(|| failed("empty command".to_string()))?;
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    // a group of its own lets us kill the command's children on timeout
+    if timeout.is_some() {
+        command.process_group(0);
+    }
+    let mut child = command.spawn().map_err(|e| failed(e.to_string()))?;
+    // drain stdout as we go, so that the command never blocks on a full pipe
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = sender.send(stdout.read_to_end(&mut output).map(|_| output));
+    });
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let timed_out = |child: &mut Child| {
+        kill_group(child);
+        failed("timed out".to_string())
+    };
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(timed_out(&mut child));
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    // stdout stays open for as long as anything the command started holds it
+    let output = match deadline {
+        Some(deadline) => receiver
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            .map_err(|_| timed_out(&mut child))?,
+        None => receiver
+            .recv()
+            .map_err(|_| failed("unable to read stdout".to_string()))?,
+    };
+    let mut output = output?;
+    output.extend(format!("\0{}", status).into_bytes());
+    Ok(output)
+}
+
+/// Split a command line into its program and arguments, the way a POSIX shell
+/// would, without any expansion. Single quotes preserve everything within
+/// them, and backslash escapes the next character outside of single quotes.
+pub fn split_command(line: &str) -> Result<Vec<String>> {
+    let unterminated = || HashitError::Fingerprint(format!("unterminated quote in '{}'", line));
+    let mut words = Vec::new();
+    let mut word = None::<String>;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or_else(unterminated)? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or_else(unterminated)? {
+                        '"' => break,
+                        '\\' => match chars.next().ok_or_else(unterminated)? {
+                            c @ ('"' | '\\' | '$' | '`') => word.push(c),
+                            c => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                let escaped = chars.next().unwrap_or('\\');
+                word.get_or_insert_with(String::new).push(escaped);
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+impl Component {
+    /// The current value of the component
+    pub fn value(&self) -> Result<Vec<u8>> {
+        match self {
+            Self::Env(name) => Ok(match std::env::var_os(name) {
+                Some(value) => {
+                    let mut bytes = b"set=".to_vec();
+                    bytes.extend(value.to_string_lossy().as_bytes());
+                    bytes
+                }
+                None => b"unset".to_vec(),
+            }),
+            Self::Tool(name) => tool_version(name),
+            Self::Value(_, value) => Ok(value.as_bytes().to_vec()),
+            Self::Command(argv, timeout) => command_output(argv, *timeout),
+        }
+    }
+
+    /// The digest of the component, which covers its name as well as its value
+    pub fn digest(&self) -> Result<Vec<u8>> {
+        let mut bytes = self.to_string().into_bytes();
+        bytes.push(0);
+        bytes.extend(self.value()?);
+        Ok(blake_hash(&bytes))
+    }
+}
+
+/// The components, beyond the inputs, which an output depends upon
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Fingerprint {
+    components: Vec<Component>,
+}
+
+impl Fingerprint {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Depend upon the value of the environment variable
+    pub fn with_env<N>(mut self, name: N) -> Self
+    where
+        N: Into<String>,
+    {
+        self.components.push(Component::Env(name.into()));
+        self
+    }
+
+    /// Depend upon the output of `tool --version`
+    pub fn with_tool<N>(mut self, tool: N) -> Self
+    where
+        N: Into<String>,
+    {
+        self.components.push(Component::Tool(tool.into()));
+        self
+    }
+
+    /// Depend upon an arbitrary value, reported by key
+    pub fn with_value<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.components
+            .push(Component::Value(key.into(), value.into()));
+        self
+    }
+
+    /// Depend upon the stdout and exit status of a command, given as the
+    /// program followed by its arguments. The command is killed, and the
+    /// digest fails, if it runs for longer than timeout.
+    pub fn with_command<A>(mut self, argv: &[A], timeout: Option<Duration>) -> Self
+    where
+        A: AsRef<str>,
+    {
+        let argv = argv.iter().map(|x| x.as_ref().to_string()).collect();
+        self.components.push(Component::Command(argv, timeout));
+        self
+    }
+
+    pub fn components(&self) -> &[Component] {
+        &self.components
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+
+    /// The digests of each component, concatenated in order
+    pub fn digests(&self) -> Result<Vec<u8>> {
+        let mut digests = Vec::new();
+        for component in &self.components {
+            digests.extend(component.digest()?);
+        }
+        Ok(digests)
+    }
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/fingerprint_test.rs"]
+mod tests;
@@ -0,0 +1,185 @@
+//! Git
+//!
+//! Calculates git blob and tree object IDs, in either the SHA-1 or SHA-256
+//! object format, so that a working tree may be compared against
+//! `git ls-tree` output, or the tree of a commit, without running git.
+//!
+//! As in git, a tree lists only files, symbolic links and non empty
+//! directories, with modes 100644, 100755, 120000 and 40000, and `.git`
+//! directories are skipped.
+use crate::error::{HashitError, Result};
+use crate::traits::CalcHash;
+use crate::utils::read_file;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The object formats supported by git
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectFormat {
+    Sha1,
+    Sha256,
+}
+
+impl ObjectFormat {
+    /// The object ID of an object, given its type and contents
+    pub fn object_id(&self, kind: &str, contents: &[u8]) -> Vec<u8> {
+        let header = format!("{} {}\0", kind, contents.len());
+        match self {
+            Self::Sha1 => {
+                let mut hasher = Sha1::new();
+                hasher.update(header.as_bytes());
+                hasher.update(contents);
+                hasher.finalize().to_vec()
+            }
+            Self::Sha256 => {
+                let mut hasher = Sha256::new();
+                hasher.update(header.as_bytes());
+                hasher.update(contents);
+                hasher.finalize().to_vec()
+            }
+        }
+    }
+
+    /// The blob ID of contents, as reported by `git hash-object`
+    pub fn blob_id(&self, contents: &[u8]) -> Vec<u8> {
+        self.object_id("blob", contents)
+    }
+}
+
+impl FromStr for ObjectFormat {
+    type Err = HashitError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sha1" | "sha-1" => Ok(Self::Sha1),
+            "sha256" | "sha-256" => Ok(Self::Sha256),
+            _ => Err(HashitError::UnknownAlgorithm(s.to_string())),
+        }
+    }
+}
+
+// a single entry of a tree object
+struct TreeEntry {
+    mode: &'static str,
+    name: Vec<u8>,
+    id: Vec<u8>,
+}
+
+impl TreeEntry {
+    // git sorts directories as though their names ended with a slash
+    fn sort_key(&self) -> Vec<u8> {
+        let mut key = self.name.clone();
+        if self.mode == "40000" {
+            key.push(b'/');
+        }
+        key
+    }
+}
+
+/// Calculate the object ID of the file, symbolic link or directory at path,
+/// returning its mode along with the ID. Returns None for a directory with
+/// nothing to track, which git omits from trees.
+pub fn entry_id<P>(path: P, format: ObjectFormat) -> Result<Option<(&'static str, Vec<u8>)>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let metadata = fs::symlink_metadata(path).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            HashitError::NotFound {
+                source: e,
+                file: PathBuf::from(path),
+            }
+        } else {
+            e.into()
+        }
+    })?;
+    if metadata.file_type().is_symlink() {
+        let target = fs::read_link(path)?;
+        let id = format.blob_id(target.to_string_lossy().as_bytes());
+        return Ok(Some(("120000", id)));
+    }
+    if !metadata.is_dir() {
+        let mode = if metadata.permissions().mode() & 0o111 != 0 {
+            "100755"
+        } else {
+            "100644"
+        };
+        return Ok(Some((mode, format.blob_id(&read_file(path)?))));
+    }
+
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == ".git" {
+            continue;
+        }
+        if let Some((mode, id)) = entry_id(entry.path(), format)? {
+            entries.push(TreeEntry {
+                mode,
+                name: name.to_string_lossy().as_bytes().to_vec(),
+                id,
+            });
+        }
+    }
+    if entries.is_empty() {
+        return Ok(None);
+    }
+    entries.sort_by_key(|e| e.sort_key());
+    let mut contents = Vec::new();
+    for entry in entries {
+        contents.extend(entry.mode.as_bytes());
+        contents.push(b' ');
+        contents.extend(entry.name);
+        contents.push(0);
+        contents.extend(entry.id);
+    }
+    Ok(Some(("40000", format.object_id("tree", &contents))))
+}
+
+/// Calculate the object ID of the file or directory at path. An empty
+/// directory has the ID of the empty tree.
+pub fn object_id<P>(path: P, format: ObjectFormat) -> Result<Vec<u8>>
+where
+    P: AsRef<Path>,
+{
+    match entry_id(path, format)? {
+        Some((_, id)) => Ok(id),
+        None => Ok(format.object_id("tree", &[])),
+    }
+}
+
+/// A CalcHash which produces the git object ID of each input: a blob ID for
+/// a file, and a tree ID for a directory
+#[derive(Debug, Clone, Copy)]
+pub struct GitHash {
+    format: ObjectFormat,
+}
+
+impl GitHash {
+    pub fn new(format: ObjectFormat) -> Self {
+        GitHash { format }
+    }
+}
+
+impl CalcHash for GitHash {
+    fn calc_hash<P>(&self, files: &[P]) -> Result<Vec<u8>>
+    where
+        P: AsRef<str>,
+    {
+        let mut resvec = Vec::new();
+        for f in files {
+            resvec.extend(object_id(f.as_ref(), self.format)?);
+        }
+        Ok(resvec)
+    }
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/git_test.rs"]
+mod tests;
@@ -0,0 +1,233 @@
+//! Graph
+//!
+//! A small incremental task engine built on top of Hashit. Each Target names
+//! the inputs it consumes, the stamp file used to cache their hash, and the
+//! targets it depends upon. The Graph orders targets so that dependencies
+//! run before their dependents, and reruns a target when either its inputs
+//! have changed or one of its dependencies was rebuilt.
+//!
+//! A target's stamp is only updated after its action succeeds, so a failed
+//! action is retried the next time the graph is run. The stamps of a
+//! target's dependencies are mixed into its own hash, so a dependent whose
+//! action failed after its dependencies were rebuilt is retried as well.
+use crate::error::{HashitError, Result};
+use crate::fingerprint::Fingerprint;
+use crate::hashit::Hashit;
+use crate::traits::{CalcHash, FetchCachedHash, OpenMut};
+use crate::utils::blake_hash;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// A named unit of work, along with the inputs and dependencies which
+/// determine whether it needs to be rerun.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    name: String,
+    stamp: PathBuf,
+    inputs: Vec<PathBuf>,
+    deps: Vec<String>,
+}
+
+impl Target {
+    /// New up a target with a name and the path of the stamp used to store
+    /// the hash of its inputs.
+    pub fn new<N, S>(name: N, stamp: S) -> Self
+    where
+        N: Into<String>,
+        S: AsRef<Path>,
+    {
+        Target {
+            name: name.into(),
+            stamp: PathBuf::from(stamp.as_ref()),
+            inputs: Vec::new(),
+            deps: Vec::new(),
+        }
+    }
+
+    /// Add an input to the target
+    pub fn with_input<P>(mut self, input: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.inputs.push(PathBuf::from(input.as_ref()));
+        self
+    }
+
+    /// Add a number of inputs to the target
+    pub fn with_inputs<P>(mut self, inputs: &[P]) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.inputs
+            .extend(inputs.iter().map(|x| PathBuf::from(x.as_ref())));
+        self
+    }
+
+    /// Declare that this target consumes the outputs of another target
+    pub fn depends_on<N>(mut self, name: N) -> Self
+    where
+        N: Into<String>,
+    {
+        self.deps.push(name.into());
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn stamp(&self) -> &Path {
+        &self.stamp
+    }
+
+    pub fn inputs(&self) -> &[PathBuf] {
+        &self.inputs
+    }
+
+    pub fn deps(&self) -> &[String] {
+        &self.deps
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mark {
+    Visiting,
+    Done,
+}
+
+/// A set of targets, keyed by name.
+#[derive(Debug, Default)]
+pub struct Graph {
+    targets: Vec<Target>,
+    index: HashMap<String, usize>,
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a target to the graph. Target names must be unique.
+    pub fn add(&mut self, target: Target) -> Result<()> {
+        if self.index.contains_key(target.name()) {
+            return Err(HashitError::DuplicateTarget(target.name.clone()));
+        }
+        self.index.insert(target.name.clone(), self.targets.len());
+        self.targets.push(target);
+        Ok(())
+    }
+
+    /// Retrieve a target by name
+    pub fn get(&self, name: &str) -> Option<&Target> {
+        self.index.get(name).map(|idx| &self.targets[*idx])
+    }
+
+    /// Return the targets ordered such that every target appears after the
+    /// targets it depends upon. Ties are broken by the order in which the
+    /// targets were added, so the result is stable from run to run.
+    ///
+    /// Returns an error if a target depends upon a target which does not
+    /// exist, or if the dependencies form a cycle.
+    pub fn order(&self) -> Result<Vec<&Target>> {
+        let mut marks = HashMap::new();
+        let mut ordered = Vec::with_capacity(self.targets.len());
+        let mut stack = Vec::new();
+        for idx in 0..self.targets.len() {
+            self.visit(idx, &mut marks, &mut stack, &mut ordered)?;
+        }
+        Ok(ordered.into_iter().map(|idx| &self.targets[idx]).collect())
+    }
+
+    // depth first traversal. stack holds the path currently being visited so
+    // that we may report the members of a cycle when we find one.
+    fn visit(
+        &self,
+        idx: usize,
+        marks: &mut HashMap<usize, Mark>,
+        stack: &mut Vec<usize>,
+        ordered: &mut Vec<usize>,
+    ) -> Result<()> {
+        match marks.get(&idx) {
+            Some(Mark::Done) => return Ok(()),
+            Some(Mark::Visiting) => {
+                let start = stack.iter().position(|x| *x == idx).unwrap_or(0);
+                let mut cycle = stack[start..]
+                    .iter()
+                    .map(|x| self.targets[*x].name.as_str())
+                    .collect::<Vec<_>>();
+                cycle.push(self.targets[idx].name.as_str());
+                return Err(HashitError::DependencyCycle(cycle.join(" -> ")));
+            }
+            None => (),
+        }
+        marks.insert(idx, Mark::Visiting);
+        stack.push(idx);
+        for dep in &self.targets[idx].deps {
+            let dep_idx = *self
+                .index
+                .get(dep)
+                .ok_or_else(|| HashitError::MissingTarget(dep.clone()))?;
+            self.visit(dep_idx, marks, stack, ordered)?;
+        }
+        stack.pop();
+        marks.insert(idx, Mark::Done);
+        ordered.push(idx);
+        Ok(())
+    }
+
+    /// Run the graph, invoking action for each target whose inputs have
+    /// changed, or whose dependencies have been rebuilt since it last ran.
+    /// Returns the names of the targets which were rebuilt, in the order in
+    /// which they were run.
+    ///
+    /// If action fails, the run stops and the error is returned. The stamp of
+    /// the failed target is left untouched, so it will be rerun next time.
+    pub fn run<R, H, F>(&self, hashit: &mut Hashit<R, H>, action: F) -> Result<Vec<String>>
+    where
+        R: for<'b> OpenMut<'b> + for<'b> FetchCachedHash<'b>,
+        H: CalcHash + std::fmt::Debug,
+        F: FnMut(&Target) -> Result<()>,
+    {
+        let fingerprint = hashit.fingerprint().clone();
+        let result = self.run_targets(hashit, &fingerprint, action);
+        hashit.replace_fingerprint(fingerprint);
+        result
+    }
+
+    fn run_targets<R, H, F>(
+        &self,
+        hashit: &mut Hashit<R, H>,
+        fingerprint: &Fingerprint,
+        mut action: F,
+    ) -> Result<Vec<String>>
+    where
+        R: for<'b> OpenMut<'b> + for<'b> FetchCachedHash<'b>,
+        H: CalcHash + std::fmt::Debug,
+        F: FnMut(&Target) -> Result<()>,
+    {
+        let mut rebuilt = Vec::new();
+        for target in self.order()? {
+            // a rebuilt dependency has a new stamp, which changes our hash
+            // until our own action succeeds
+            let mut target_fingerprint = fingerprint.clone();
+            for dep in &target.deps {
+                let stamp = hashit.stamp_contents(&self.targets[self.index[dep]].stamp)?;
+                target_fingerprint = target_fingerprint
+                    .with_value(format!("stamp of {}", dep), hex::encode(blake_hash(&stamp)));
+            }
+            hashit.replace_fingerprint(target_fingerprint);
+            let pending = match hashit.check(&target.inputs, &target.stamp)? {
+                Some(hash) => hash,
+                None => continue,
+            };
+            action(target)?;
+            hashit.commit(&target.stamp, &pending)?;
+            rebuilt.push(target.name.clone());
+        }
+        Ok(rebuilt)
+    }
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/graph_test.rs"]
+mod tests;
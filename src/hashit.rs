@@ -1,7 +1,12 @@
-use crate::error::Result;
+use crate::checksum::Algorithm;
+use crate::error::{HashitError, Result};
 use crate::file::{FileHash, HtFile};
+use crate::fingerprint::Fingerprint;
+use crate::lock::{Lockfile, Mismatch, Pin};
 use crate::open_mode::OpenMode;
+use crate::report::{Change, Report};
 use crate::traits::*;
+use crate::utils::blake_hash;
 
 use std::io::prelude::*;
 use std::path::Path;
@@ -11,6 +16,7 @@ use std::path::Path;
 pub struct Hashit<R, H> {
     inner: R,
     hasher: H,
+    fingerprint: Fingerprint,
 }
 
 /// Simplify default construction for production usage
@@ -21,6 +27,7 @@ impl Default for Hashit<HtFile, FileHash> {
         Hashit::<HtFile, FileHash> {
             inner: HtFile::new(),
             hasher: FileHash {},
+            fingerprint: Fingerprint::default(),
         }
     }
 }
@@ -31,6 +38,110 @@ impl Hashit<HtFile, FileHash> {
     }
 }
 
+impl<R, H> Hashit<R, H> {
+    /// Construct a Hashit from an alternative store and hasher
+    pub fn from_parts(inner: R, hasher: H) -> Self {
+        Hashit {
+            inner,
+            hasher,
+            fingerprint: Fingerprint::default(),
+        }
+    }
+
+    /// Mix the components of fingerprint into the hash, alongside the inputs
+    pub fn with_fingerprint(mut self, fingerprint: Fingerprint) -> Self {
+        self.fingerprint = fingerprint;
+        self
+    }
+
+    pub fn fingerprint(&self) -> &Fingerprint {
+        &self.fingerprint
+    }
+
+    /// Retrieve a reference to the hasher
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    // swap in another fingerprint, returning the one it replaced
+    pub(crate) fn replace_fingerprint(&mut self, fingerprint: Fingerprint) -> Fingerprint {
+        std::mem::replace(&mut self.fingerprint, fingerprint)
+    }
+}
+
+impl<R, H: CalcHash> Hashit<R, H> {
+    /// Calculate a stable digest of the inputs, returned as a hex string.
+    ///
+    /// Unlike has_changed, digest never reads or writes the output. The inputs
+    /// are sorted and deduplicated before hashing, so the result does not
+    /// depend upon the order in which they are supplied, making it suitable
+    /// for use as a cache key.
+    pub fn digest<IP>(&self, inputs: &[IP]) -> Result<String>
+    where
+        IP: AsRef<Path>,
+    {
+        let mut inputs2 = inputs
+            .iter()
+            .map(|x| x.as_ref().to_string_lossy())
+            .collect::<Vec<_>>();
+        inputs2.sort();
+        inputs2.dedup();
+        let mut hash = self.hasher.calc_hash(&inputs2[..])?;
+        hash.extend(self.fingerprint.digests()?);
+        Ok(hex::encode(blake_hash(&hash)))
+    }
+
+    // the digest of a single file. Blake2b is our own, so it goes via the hasher
+    fn pin_digest(&self, path: &Path, algorithm: Algorithm) -> Result<Vec<u8>> {
+        match algorithm {
+            Algorithm::Blake2b512 => self.hasher.calc_hash(&[path.to_string_lossy()]),
+            _ => algorithm.digest_file(path),
+        }
+    }
+
+    /// Pin each of the inputs to its current digest
+    pub fn lock<IP>(&self, inputs: &[IP], algorithm: Algorithm) -> Result<Lockfile>
+    where
+        IP: AsRef<Path>,
+    {
+        let mut pins = Vec::with_capacity(inputs.len());
+        for input in inputs {
+            let path = input.as_ref();
+            pins.push(Pin {
+                path: path.to_path_buf(),
+                algorithm,
+                digest: hex::encode(self.pin_digest(path, algorithm)?),
+            });
+        }
+        Ok(Lockfile::new(pins))
+    }
+
+    /// Verify that every file pinned by the lockfile matches its digest.
+    /// Every pin is checked before returning, so that a LockMismatch error
+    /// lists all of the files which fail.
+    pub fn verify_lock(&self, lockfile: &Lockfile) -> Result<()> {
+        let mut mismatches = Vec::new();
+        for pin in lockfile.pins() {
+            let actual = match self.pin_digest(&pin.path, pin.algorithm) {
+                Ok(digest) if hex::encode(&digest) == pin.digest => continue,
+                Ok(digest) => Ok(hex::encode(digest)),
+                Err(e) => Err(e.to_string()),
+            };
+            mismatches.push(Mismatch {
+                path: pin.path.clone(),
+                algorithm: pin.algorithm,
+                expected: pin.digest.clone(),
+                actual,
+            });
+        }
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(HashitError::LockMismatch(mismatches))
+        }
+    }
+}
+
 impl<'a, R: OpenMut<'a> + FetchCachedHash<'a>, H: CalcHash + std::fmt::Debug> Hashit<R, H> {
     /// Given a list of inputs, compare their collective hash to the value stored
     /// in a file to determine if any of the files has changed since the last
@@ -44,6 +155,42 @@ impl<'a, R: OpenMut<'a> + FetchCachedHash<'a>, H: CalcHash + std::fmt::Debug> Ha
     /// - If the inputs' hash matches the stored hash, we return false (the input(s)
     ///   have not changed)
     pub fn has_changed<IP, OP>(&'a mut self, inputs: &[IP], output: OP) -> Result<bool>
+    where
+        IP: AsRef<Path>,
+        OP: AsRef<Path>,
+    {
+        match self.check(inputs, &output)? {
+            Some(hash) => {
+                self.commit(output, &hash)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Compare the collective hash of the inputs to the value stored in output,
+    /// without updating the output.
+    ///
+    /// Returns the newly calculated hash if it differs from the stored hash, or
+    /// if any outputs recorded by commit_with_outputs have gone missing or been
+    /// modified, and None otherwise. The returned hash may be handed to `commit`
+    /// once the work which depends upon the inputs has completed successfully.
+    pub fn check<IP, OP>(&mut self, inputs: &[IP], output: OP) -> Result<Option<Vec<u8>>>
+    where
+        IP: AsRef<Path>,
+        OP: AsRef<Path>,
+    {
+        let report = self.report(inputs, output)?;
+        if report.has_changed() {
+            Ok(Some(report.into_hash()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Like check, but returns a Report describing which inputs and outputs
+    /// have changed.
+    pub fn report<IP, OP>(&mut self, inputs: &[IP], output: OP) -> Result<Report>
     where
         IP: AsRef<Path>,
         OP: AsRef<Path>,
@@ -57,25 +204,153 @@ impl<'a, R: OpenMut<'a> + FetchCachedHash<'a>, H: CalcHash + std::fmt::Debug> Ha
             .collect::<Vec<_>>();
 
         // Here we are calculating the hash of each of the inputs and
-        // returning an accumulated value.
-        let hash = (self.hasher).calc_hash(&inputs2[..])?;
+        // returning an accumulated value, followed by the digest of each
+        // component of the fingerprint.
+        let mut hash = (self.hasher).calc_hash(&inputs2[..])?;
+        hash.extend(self.fingerprint.digests()?);
         // now we are going to read the value of the hash that has previously been cached.
         let output_str = output.as_ref().to_string_lossy();
 
         // fetch_cached_hash will create the output if it does not exist, returning an
         // empty buffer in that case.
         let buffer = self.inner.fetch_cached_hash(output_str.as_ref())?;
-        let differs = hash != &buffer[..];
-        if differs {
-            let mut writer = self
-                .inner
-                .open_mut(output_str.as_ref(), OpenMode::WriteTruncate)?;
-            writer.write_all(&hash)?;
+        let mut report = Report::new(hash);
+        if buffer.is_empty() {
+            report.push(Change::New);
+            return Ok(report);
+        }
+        let hash = report.hash().to_vec();
+        // the recorded outputs, if any, follow the hash of the inputs
+        let outputs = match buffer.get(hash.len()..).and_then(parse_outputs) {
+            Some(outputs) => outputs,
+            None => {
+                report.push(Change::InputSet);
+                return Ok(report);
+            }
+        };
+
+        let stored = &buffer[..hash.len()];
+        if stored != &hash[..] {
+            // each input and component contributes a digest of the same
+            // length, which lets us attribute the change to each of them
+            let candidates = inputs2
+                .iter()
+                .map(|name| Change::Input(name.to_string()))
+                .chain(
+                    self.fingerprint
+                        .components()
+                        .iter()
+                        .map(|c| Change::Fingerprint(c.to_string())),
+                )
+                .collect::<Vec<_>>();
+            let chunk = hash.len().checked_div(candidates.len()).unwrap_or(0);
+            if chunk > 0 && chunk * candidates.len() == hash.len() {
+                for (change, (new, old)) in candidates
+                    .into_iter()
+                    .zip(hash.chunks(chunk).zip(stored.chunks(chunk)))
+                {
+                    if new == old {
+                        continue;
+                    }
+                    let entries = match &change {
+                        Change::Input(name) => self
+                            .hasher
+                            .changed_entries(name, old, new)
+                            .into_iter()
+                            .map(|entry| Change::Entry(name.clone(), entry))
+                            .collect(),
+                        _ => Vec::new(),
+                    };
+                    report.push(change);
+                    for entry in entries {
+                        report.push(entry);
+                    }
+                }
+            } else {
+                report.push(Change::InputSet);
+            }
+        }
+
+        for (digest, name) in outputs {
+            match self.hasher.calc_hash(&[name.as_str()]) {
+                Ok(current) if current == digest => (),
+                Ok(_) => report.push(Change::OutputModified(name)),
+                Err(HashitError::NotFound { .. }) => report.push(Change::OutputMissing(name)),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(report)
+    }
+
+    // the contents of the stamp at output, which is empty if there is none
+    pub(crate) fn stamp_contents<OP>(&mut self, output: OP) -> Result<Vec<u8>>
+    where
+        OP: AsRef<Path>,
+    {
+        let output_str = output.as_ref().to_string_lossy();
+        self.inner.fetch_cached_hash(output_str.as_ref())
+    }
+
+    /// Store the supplied hash in output, replacing the previously cached hash.
+    /// Any outputs recorded by commit_with_outputs are forgotten.
+    pub fn commit<OP>(&'a mut self, output: OP, hash: &[u8]) -> Result<()>
+    where
+        OP: AsRef<Path>,
+    {
+        let output_str = output.as_ref().to_string_lossy();
+        let mut writer = self
+            .inner
+            .open_mut(output_str.as_ref(), OpenMode::WriteTruncate)?;
+        writer.write_all(hash)?;
+        writer.flush()?;
+        self.hasher.committed(output_str.as_ref(), hash)
+    }
+
+    /// Store the supplied hash in output, along with the digests of outputs,
+    /// the files generated from the inputs. Should any of them go missing or
+    /// be modified, subsequent checks report a change even though the inputs
+    /// are untouched.
+    pub fn commit_with_outputs<OP, P>(
+        &'a mut self,
+        output: OP,
+        hash: &[u8],
+        outputs: &[P],
+    ) -> Result<()>
+    where
+        OP: AsRef<Path>,
+        P: AsRef<Path>,
+    {
+        let mut contents = hash.to_vec();
+        contents.extend_from_slice(OUTPUTS_MARKER);
+        for path in outputs {
+            let name = path.as_ref().to_string_lossy();
+            let digest = self.hasher.calc_hash(&[name.as_ref()])?;
+            contents.extend(format!("{}\t{}\n", hex::encode(digest), name).into_bytes());
         }
-        Ok(differs)
+        self.commit(output, &contents)
+    }
+}
+
+// separates the hash of the inputs from the digests of the outputs
+const OUTPUTS_MARKER: &[u8] = b"\nhashtest-outputs\n";
+
+// Parse the digests of the outputs which follow the hash of the inputs.
+// Returns None if the trailer is not an outputs section.
+fn parse_outputs(trailer: &[u8]) -> Option<Vec<(Vec<u8>, String)>> {
+    if trailer.is_empty() {
+        return Some(Vec::new());
     }
+    let trailer = std::str::from_utf8(trailer.strip_prefix(OUTPUTS_MARKER)?).ok()?;
+    trailer
+        .lines()
+        .map(|line| {
+            let (digest, name) = line.split_once('\t')?;
+            Some((hex::decode(digest).ok()?, name.to_string()))
+        })
+        .collect()
 }
 
 #[cfg(test)]
 #[path = "./unit_tests/hashit_test.rs"]
+#[allow(clippy::bool_assert_comparison)]
 mod tests;
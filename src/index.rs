@@ -0,0 +1,558 @@
+//! Index
+//!
+//! Reads the git index (`.git/index`), in which git records the blob ID of
+//! each tracked file along with the stat data the file had when git last
+//! hashed it. While the stat data of a file still matches, its recorded blob
+//! ID may be trusted, so only dirty and untracked files need to be read. In a
+//! large checkout, where most files are clean, this makes hashing nearly free.
+//!
+//! As in git, an entry modified no earlier than the second in which the index
+//! was written is treated as dirty, since a change made just after git hashed
+//! the file need not show up in its stat data. Every file is digested by its
+//! blob ID, whether recorded or calculated, so that the digest of a file does
+//! not depend upon whether it happens to be clean.
+//!
+//! GitIndex also expands directory inputs into the files git would see: the
+//! tracked files beneath them, along with untracked files which are not
+//! ignored by a `.gitignore` or by `.git/info/exclude`. The global excludes
+//! file is not consulted.
+use crate::error::{HashitError, Result};
+use crate::git::{self, ObjectFormat};
+use crate::traits::CalcHash;
+use crate::utils::blake_hash;
+use glob::{MatchOptions, Pattern};
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+// the modes of index entries which are not files on disk
+const GITLINK_MODE: u32 = 0o160000;
+const SPARSE_DIR_MODE: u32 = 0o040000;
+
+/// A single entry of the index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    /// path relative to the root of the working tree
+    pub path: PathBuf,
+    /// seconds and nanoseconds
+    pub ctime: (u32, u32),
+    /// seconds and nanoseconds
+    pub mtime: (u32, u32),
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// size in bytes, truncated to 32 bits
+    pub size: u32,
+    pub id: Vec<u8>,
+    /// the merge stage, which is non zero for a conflicted path
+    pub stage: u16,
+}
+
+// the mode git would record for a file with metadata
+fn mode_of(metadata: &fs::Metadata) -> u32 {
+    if metadata.file_type().is_symlink() {
+        0o120000
+    } else if metadata.mode() & 0o111 != 0 {
+        0o100755
+    } else {
+        0o100644
+    }
+}
+
+impl IndexEntry {
+    /// Whether metadata, as returned by symlink_metadata, matches the stat
+    /// data recorded for the entry. Nanoseconds are only compared when git
+    /// recorded them.
+    pub fn matches(&self, metadata: &fs::Metadata) -> bool {
+        let time_matches = |(secs, nsecs): (u32, u32), actual_secs: i64, actual_nsecs: i64| {
+            secs == actual_secs as u32 && (nsecs == 0 || nsecs as i64 == actual_nsecs)
+        };
+        !metadata.is_dir()
+            && time_matches(self.mtime, metadata.mtime(), metadata.mtime_nsec())
+            && time_matches(self.ctime, metadata.ctime(), metadata.ctime_nsec())
+            && self.ino == metadata.ino() as u32
+            && self.uid == metadata.uid()
+            && self.gid == metadata.gid()
+            && self.size == metadata.len() as u32
+            && self.mode == mode_of(metadata)
+    }
+}
+
+// a cursor over the bytes of an index
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    // a NUL terminated string, without the NUL
+    fn until_nul(&mut self) -> Option<&'a [u8]> {
+        let len = self.data.get(self.pos..)?.iter().position(|c| *c == 0)?;
+        let bytes = self.take(len)?;
+        self.pos += 1;
+        Some(bytes)
+    }
+
+    // the offset encoding used by version 4 to compress paths
+    fn varint(&mut self) -> Option<usize> {
+        let mut byte = self.take(1)?[0];
+        let mut value = (byte & 0x7f) as usize;
+        while byte & 0x80 != 0 {
+            byte = self.take(1)?[0];
+            value = ((value + 1) << 7) | (byte & 0x7f) as usize;
+        }
+        Some(value)
+    }
+}
+
+/// Parse the entries of an index, in versions 2 to 4. Extensions are ignored.
+pub fn parse(data: &[u8], format: ObjectFormat) -> Result<Vec<IndexEntry>> {
+    let mut reader = Reader { data, pos: 0 };
+    let malformed = || HashitError::Parse {
+        file: PathBuf::from("index"),
+        message: String::from("malformed index"),
+    };
+    if reader.take(4) != Some(b"DIRC") {
+        return Err(malformed());
+    }
+    let version = reader.u32().ok_or_else(malformed)?;
+    if !(2..=4).contains(&version) {
+        return Err(HashitError::Parse {
+            file: PathBuf::from("index"),
+            message: format!("unsupported index version {}", version),
+        });
+    }
+    let count = reader.u32().ok_or_else(malformed)?;
+    let id_len = format.blob_id(b"").len();
+    let mut entries = Vec::new();
+    let mut previous: Vec<u8> = Vec::new();
+    for _ in 0..count {
+        let entry = parse_entry(&mut reader, version, id_len, &mut previous);
+        entries.push(entry.ok_or_else(malformed)?);
+    }
+    Ok(entries)
+}
+
+fn parse_entry(
+    reader: &mut Reader,
+    version: u32,
+    id_len: usize,
+    previous: &mut Vec<u8>,
+) -> Option<IndexEntry> {
+    let start = reader.pos;
+    let ctime = (reader.u32()?, reader.u32()?);
+    let mtime = (reader.u32()?, reader.u32()?);
+    let _dev = reader.u32()?;
+    let ino = reader.u32()?;
+    let mode = reader.u32()?;
+    let uid = reader.u32()?;
+    let gid = reader.u32()?;
+    let size = reader.u32()?;
+    let id = reader.take(id_len)?.to_vec();
+    let flags = reader.u16()?;
+    if version >= 3 && flags & 0x4000 != 0 {
+        reader.u16()?;
+    }
+    let path = if version == 4 {
+        let strip = reader.varint()?;
+        previous.truncate(previous.len().checked_sub(strip)?);
+        previous.extend(reader.until_nul()?);
+        previous.clone()
+    } else {
+        let path = reader.until_nul()?.to_vec();
+        // entries are padded with NULs to a multiple of eight bytes
+        let len = reader.pos - start;
+        reader.take((8 - len % 8) % 8)?;
+        path
+    };
+    Some(IndexEntry {
+        path: PathBuf::from(String::from_utf8_lossy(&path).to_string()),
+        ctime,
+        mtime,
+        ino,
+        mode,
+        uid,
+        gid,
+        size,
+        id,
+        stage: (flags >> 12) & 0x3,
+    })
+}
+
+// a single pattern from a .gitignore or exclude file
+#[derive(Debug)]
+struct IgnoreRule {
+    pattern: Pattern,
+    // the directory holding the file the pattern came from, relative to the
+    // root of the working tree
+    base: PathBuf,
+    negated: bool,
+    dir_only: bool,
+    // a pattern containing a slash matches the path relative to base, and
+    // any other pattern matches the name alone
+    anchored: bool,
+}
+
+// ignore rules, in increasing order of precedence
+#[derive(Debug, Default)]
+struct Ignore {
+    rules: Vec<IgnoreRule>,
+}
+
+impl Ignore {
+    fn parse(&mut self, contents: &str, base: &Path) {
+        for line in contents.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            // a leading backslash escapes a literal # or !
+            let line = line.strip_prefix('\\').unwrap_or(line);
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            let anchored = line.contains('/');
+            // as in git, invalid patterns are skipped
+            if let Ok(pattern) = Pattern::new(line.strip_prefix('/').unwrap_or(line)) {
+                self.rules.push(IgnoreRule {
+                    pattern,
+                    base: base.to_path_buf(),
+                    negated,
+                    dir_only,
+                    anchored,
+                });
+            }
+        }
+    }
+
+    // read the rules from file, if it exists
+    fn load(&mut self, file: &Path, base: &Path) -> Result<()> {
+        match fs::read_to_string(file) {
+            Ok(contents) => self.parse(&contents, base),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+        Ok(())
+    }
+
+    // whether path, relative to the root of the working tree, is ignored.
+    // The last matching rule wins.
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        let mut ignored = false;
+        for rule in &self.rules {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            let relative = match path.strip_prefix(&rule.base) {
+                Ok(relative) => relative,
+                Err(_) => continue,
+            };
+            let matched = if rule.anchored {
+                rule.pattern.matches_path_with(relative, options)
+            } else {
+                path.file_name()
+                    .is_some_and(|name| rule.pattern.matches_with(&name.to_string_lossy(), options))
+            };
+            if matched {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+}
+
+/// The index of a git working tree. Also a CalcHash, which digests each
+/// file by its blob ID, reading only those files which are not clean.
+#[derive(Debug, Clone)]
+pub struct GitIndex {
+    root: PathBuf,
+    // the directory holding info/exclude and config, which is shared between
+    // worktrees
+    common_dir: PathBuf,
+    format: ObjectFormat,
+    entries: BTreeMap<PathBuf, IndexEntry>,
+    // when the index was written, in seconds since the epoch
+    written: i64,
+}
+
+// the object format of a repository, going by its config
+fn object_format(config: &Path) -> ObjectFormat {
+    let sha256 = fs::read_to_string(config).is_ok_and(|config| {
+        config.lines().any(|line| {
+            let line = line.split_whitespace().collect::<String>().to_lowercase();
+            line == "objectformat=sha256"
+        })
+    });
+    if sha256 {
+        ObjectFormat::Sha256
+    } else {
+        ObjectFormat::Sha1
+    }
+}
+
+impl GitIndex {
+    /// Read the index of the working tree containing path
+    pub fn discover<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let not_a_repository = || HashitError::NotARepository(path.display().to_string());
+        let start = fs::canonicalize(path).map_err(|_| not_a_repository())?;
+        for dir in start.ancestors() {
+            let dot_git = dir.join(".git");
+            if dot_git.is_dir() {
+                return Self::open(dir, &dot_git);
+            }
+            // worktrees and submodules point at their git directory
+            if dot_git.is_file() {
+                let contents = fs::read_to_string(&dot_git)?;
+                if let Some(git_dir) = contents.trim().strip_prefix("gitdir:") {
+                    return Self::open(dir, dir.join(git_dir.trim()));
+                }
+            }
+        }
+        Err(not_a_repository())
+    }
+
+    /// Read the index within git_dir, for the working tree at root
+    pub fn open<P, G>(root: P, git_dir: G) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        G: AsRef<Path>,
+    {
+        let git_dir = git_dir.as_ref();
+        let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+            Ok(common) => git_dir.join(common.trim()),
+            Err(_) => git_dir.to_path_buf(),
+        };
+        let format = object_format(&common_dir.join("config"));
+        // the index is timestamped before it is read, so that a concurrent
+        // rewrite can only make us more cautious
+        let path = git_dir.join("index");
+        let (written, data) = match fs::metadata(&path) {
+            Ok(metadata) => (metadata.mtime(), fs::read(&path)?),
+            // a repository into which nothing has been added has no index
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (0, Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let entries = if data.is_empty() {
+            Vec::new()
+        } else {
+            parse(&data, format).map_err(|e| match e {
+                HashitError::Parse { message, .. } => HashitError::Parse {
+                    file: path.clone(),
+                    message,
+                },
+                e => e,
+            })?
+        };
+        Ok(GitIndex {
+            root: fs::canonicalize(root)?,
+            common_dir,
+            format,
+            entries: entries
+                .into_iter()
+                .map(|entry| (entry.path.clone(), entry))
+                .collect(),
+            written,
+        })
+    }
+
+    /// The root of the working tree
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn format(&self) -> ObjectFormat {
+        self.format
+    }
+
+    // path relative to the root of the working tree. Symbolic links are not
+    // followed, as git tracks the links themselves.
+    fn relative(&self, path: &Path) -> Option<PathBuf> {
+        let absolute = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => {
+                let parent = if parent.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    parent
+                };
+                fs::canonicalize(parent).ok()?.join(name)
+            }
+            _ => fs::canonicalize(path).ok()?,
+        };
+        absolute
+            .strip_prefix(&self.root)
+            .ok()
+            .map(Path::to_path_buf)
+    }
+
+    /// The entry for path, if it is tracked
+    pub fn entry<P>(&self, path: P) -> Option<&IndexEntry>
+    where
+        P: AsRef<Path>,
+    {
+        self.entries.get(&self.relative(path.as_ref())?)
+    }
+
+    /// The blob ID recorded for path, provided that the file is clean
+    pub fn clean_id<P>(&self, path: P) -> Option<&[u8]>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let entry = self.entry(path)?;
+        let metadata = fs::symlink_metadata(path).ok()?;
+        let racy = entry.mtime.0 as i64 >= self.written;
+        if entry.stage == 0 && !racy && entry.matches(&metadata) {
+            Some(&entry.id)
+        } else {
+            None
+        }
+    }
+
+    /// The object ID of path, taken from the index when the file is clean
+    /// and calculated otherwise
+    pub fn object_id<P>(&self, path: P) -> Result<Vec<u8>>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        match self.clean_id(path) {
+            Some(id) => Ok(id.to_vec()),
+            None => git::object_id(path, self.format),
+        }
+    }
+
+    /// Expand the directories among inputs into the files beneath them which
+    /// git would see: tracked files which exist, and untracked files which
+    /// are not ignored. Other inputs are passed through untouched.
+    pub fn expand<P>(&self, inputs: &[P]) -> Result<Vec<PathBuf>>
+    where
+        P: AsRef<Path>,
+    {
+        let mut paths = Vec::new();
+        for input in inputs {
+            let input = input.as_ref();
+            if !input.is_dir() {
+                paths.push(input.to_path_buf());
+                continue;
+            }
+            let rel = self
+                .relative(input)
+                .ok_or_else(|| HashitError::NotARepository(input.display().to_string()))?;
+            let tracked = self
+                .entries
+                .range(rel.clone()..)
+                .take_while(|(path, _)| path.starts_with(&rel))
+                .filter(|(_, entry)| entry.mode != GITLINK_MODE && entry.mode != SPARSE_DIR_MODE);
+            for (path, _) in tracked {
+                let file = input.join(path.strip_prefix(&rel).unwrap_or(path));
+                if fs::symlink_metadata(&file).is_ok() {
+                    paths.push(file);
+                }
+            }
+
+            // gather the rules which apply above the directory, stopping
+            // short if the directory is itself ignored
+            let mut ignore = Ignore::default();
+            ignore.load(&self.common_dir.join("info/exclude"), Path::new(""))?;
+            let mut dir = PathBuf::new();
+            let mut ignored = false;
+            for component in rel.components() {
+                ignore.load(&self.root.join(&dir).join(".gitignore"), &dir)?;
+                dir.push(component);
+                if ignore.is_ignored(&dir, true) {
+                    ignored = true;
+                    break;
+                }
+            }
+            if !ignored {
+                self.untracked(input, &rel, &mut ignore, &mut paths)?;
+            }
+        }
+        paths.sort();
+        paths.dedup();
+        Ok(paths)
+    }
+
+    // gather the untracked files beneath dir which are not ignored
+    fn untracked(
+        &self,
+        dir: &Path,
+        rel: &Path,
+        ignore: &mut Ignore,
+        paths: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        let depth = ignore.rules.len();
+        ignore.load(&dir.join(".gitignore"), rel)?;
+        let mut names = fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.file_name()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        names.sort();
+        for name in names {
+            if name == ".git" {
+                continue;
+            }
+            let (path, child) = (dir.join(&name), rel.join(&name));
+            let is_dir = fs::symlink_metadata(&path)?.is_dir();
+            if ignore.is_ignored(&child, is_dir) {
+                continue;
+            }
+            if is_dir {
+                // nested repositories are left to themselves
+                if !path.join(".git").exists() {
+                    self.untracked(&path, &child, ignore, paths)?;
+                }
+            } else if !self.entries.contains_key(&child) {
+                paths.push(path);
+            }
+        }
+        ignore.rules.truncate(depth);
+        Ok(())
+    }
+}
+
+impl CalcHash for GitIndex {
+    fn calc_hash<P>(&self, files: &[P]) -> Result<Vec<u8>>
+    where
+        P: AsRef<str>,
+    {
+        let mut resvec = Vec::new();
+        for f in files {
+            resvec.extend(blake_hash(&self.object_id(f.as_ref())?));
+        }
+        Ok(resvec)
+    }
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/index_test.rs"]
+mod tests;
@@ -0,0 +1,202 @@
+//! Inputs
+//!
+//! Helpers for turning the inputs supplied by a user into a list of paths.
+//!
+//! Besides globs, inputs may be read from
+//! - response files (`@inputs.txt`), one path per line
+//! - stdin (`-`), separated by newlines, or by NULs as produced by `find -print0`
+//! - Make style dependency files, as emitted by `gcc -MD` or
+//!   `rustc --emit dep-info`
+use crate::error::{HashitError, Result};
+use crate::utils::read_file;
+use std::collections::HashSet;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// characters which mark a pattern as a glob rather than a literal path
+pub(crate) const GLOB_CHARS: &[char] = &['*', '?', '['];
+
+/// Expand a list of glob patterns into a sorted, deduplicated list of files.
+///
+/// Patterns without glob characters are passed through untouched, so that a
+/// missing literal path is reported when it is hashed rather than silently
+/// dropped. Directories matched by a glob are skipped.
+pub fn expand_globs<P>(patterns: &[P]) -> Result<Vec<PathBuf>>
+where
+    P: AsRef<str>,
+{
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        let pattern = pattern.as_ref();
+        if !pattern.contains(GLOB_CHARS) {
+            paths.push(PathBuf::from(pattern));
+            continue;
+        }
+        let entries = glob::glob(pattern)
+            .map_err(|e| HashitError::InvalidGlob(format!("{} - {}", pattern, e)))?;
+        for entry in entries {
+            let path = entry.map_err(std::io::Error::from)?;
+            if !path.is_dir() {
+                paths.push(path);
+            }
+        }
+    }
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+}
+
+/// Parse a list of paths. If the list contains a NUL, entries are separated
+/// by NULs. Otherwise they are separated by newlines. Empty entries are skipped.
+pub fn parse_list(contents: &[u8]) -> Vec<PathBuf> {
+    let contents = String::from_utf8_lossy(contents);
+    let separator = if contents.contains('\0') { '\0' } else { '\n' };
+    contents
+        .split(separator)
+        .map(|entry| {
+            if separator == '\n' {
+                entry.trim_end_matches('\r')
+            } else {
+                entry
+            }
+        })
+        .filter(|entry| !entry.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Read a list of paths from reader. See parse_list.
+pub fn read_list<R>(mut reader: R) -> Result<Vec<PathBuf>>
+where
+    R: Read,
+{
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer)?;
+    Ok(parse_list(&buffer))
+}
+
+/// Parse the prerequisites out of a Make style dependency file.
+///
+/// Targets are discarded, and prerequisites are returned in the order in which
+/// they first appear. Line continuations, escaped spaces and `$$` are handled,
+/// as are the empty rules emitted by `gcc -MP` and the comments emitted by
+/// rustc.
+pub fn parse_depfile(contents: &str) -> Result<Vec<PathBuf>> {
+    let mut deps: Vec<PathBuf> = Vec::new();
+    // depfiles for large translation units run to thousands of lines
+    let mut seen = HashSet::new();
+    // join continuation lines, so that each rule occupies a single line
+    let joined = contents.replace("\\\r\n", " ").replace("\\\n", " ");
+    for (idx, line) in joined.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let words = split_depfile_words(line);
+        let colon = words.iter().position(|w| w.is_none()).ok_or_else(|| {
+            HashitError::MalformedDepfile {
+                line: idx + 1,
+                text: line.to_string(),
+            }
+        })?;
+        for word in words[colon + 1..].iter().flatten() {
+            if seen.insert(word.clone()) {
+                deps.push(PathBuf::from(word));
+            }
+        }
+    }
+    Ok(deps)
+}
+
+// Split a rule into words. The rule separator is returned as None. A colon only
+// separates targets from prerequisites when followed by whitespace or the end of
+// the line, which leaves Windows drive letters intact.
+fn split_depfile_words(line: &str) -> Vec<Option<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut chars = line.chars().peekable();
+    let mut seen_colon = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if matches!(chars.peek(), Some(' ') | Some('#')) => {
+                word.push(chars.next().unwrap());
+            }
+            '$' if chars.peek() == Some(&'$') => {
+                word.push(chars.next().unwrap());
+            }
+            ':' if !seen_colon && chars.peek().is_none_or(|next| next.is_whitespace()) => {
+                if !word.is_empty() {
+                    words.push(Some(std::mem::take(&mut word)));
+                }
+                words.push(None);
+                seen_colon = true;
+            }
+            c if c.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(Some(std::mem::take(&mut word)));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(Some(word));
+    }
+    words
+}
+
+/// Read and parse the dependency file at path. See parse_depfile.
+pub fn read_depfile<P>(path: P) -> Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+{
+    let contents = read_file(path)?;
+    parse_depfile(&String::from_utf8_lossy(&contents))
+}
+
+/// Expand command line style arguments into a list of paths, preserving their
+/// order. An argument of the form `@file` is replaced by the paths listed in
+/// file, and `-` by the paths read from stdin. Any other argument is taken to
+/// be a path.
+pub fn expand_args<P>(args: &[P]) -> Result<Vec<PathBuf>>
+where
+    P: AsRef<str>,
+{
+    let mut paths = Vec::new();
+    for arg in args {
+        let arg = arg.as_ref();
+        if arg == "-" {
+            paths.extend(read_list(std::io::stdin().lock())?);
+        } else if let Some(file) = arg.strip_prefix('@') {
+            paths.extend(parse_list(&read_file(file)?));
+        } else {
+            paths.push(PathBuf::from(arg));
+        }
+    }
+    Ok(paths)
+}
+
+/// Like expand_args, but arguments which are not lists of paths are expanded
+/// as globs, and the result is sorted and deduplicated. The paths listed in
+/// files or read from stdin are taken literally, as they name real files and
+/// may well contain glob characters.
+pub fn expand_args_and_globs<P>(args: &[P]) -> Result<Vec<PathBuf>>
+where
+    P: AsRef<str>,
+{
+    let mut paths = Vec::new();
+    for arg in args {
+        let arg = arg.as_ref();
+        if arg == "-" || arg.starts_with('@') {
+            paths.extend(expand_args(&[arg])?);
+        } else {
+            paths.extend(expand_globs(&[arg])?);
+        }
+    }
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/inputs_test.rs"]
+mod tests;
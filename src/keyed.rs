@@ -0,0 +1,158 @@
+//! Keyed
+//!
+//! A CalcHash which keys the digests of another, using Blake2b's keyed mode,
+//! and mixes in a namespace. Tools which share a stamp and inputs, but use
+//! different namespaces, no longer see each other's hashes. Changing the key
+//! or salt (for instance, bumping a toolchain version) invalidates every
+//! stamp which was recorded with the old one.
+//!
+//! Hashers which report the entries that changed, or keep state about their
+//! digests, look them up by their own digests. Keyed remembers the digest
+//! each keyed digest was made from, and translates before delegating, so
+//! entries are reported for any digest calculated by the same process.
+//!
+//! ```
+//! use hashtest::file::FileHash;
+//! use hashtest::{HtFile, Hashit, Keyed};
+//!
+//! let hasher = Keyed::new(FileHash {})
+//!     .with_namespace("codegen")
+//!     .with_salt("toolchain-v2");
+//! let hashit = Hashit::from_parts(HtFile::new(), hasher);
+//! ```
+use crate::error::Result;
+use crate::traits::CalcHash;
+use crate::utils::blake_hash;
+use blake2::{Blake2b, Digest};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// Blake2b limits the key to 64 bytes, and the salt to 16
+const MAX_KEY_LEN: usize = 64;
+const MAX_SALT_LEN: usize = 16;
+// the length of the digests we produce
+const DIGEST_LEN: usize = 64;
+
+/// Wraps a CalcHash, keying each of the digests it produces
+#[derive(Debug, Clone, Default)]
+pub struct Keyed<H> {
+    hasher: H,
+    key: Vec<u8>,
+    salt: Vec<u8>,
+    namespace: String,
+    // the digest of the wrapped hasher which each keyed digest was made from
+    inner: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
+}
+
+impl<H> Keyed<H> {
+    pub fn new(hasher: H) -> Self {
+        Keyed {
+            hasher,
+            key: Vec::new(),
+            salt: Vec::new(),
+            namespace: String::new(),
+            inner: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Key the digests. Keys longer than 64 bytes are hashed down to 64.
+    pub fn with_key<K>(mut self, key: K) -> Self
+    where
+        K: AsRef<[u8]>,
+    {
+        let key = key.as_ref();
+        self.key = if key.len() > MAX_KEY_LEN {
+            blake_hash(key)
+        } else {
+            key.to_vec()
+        };
+        self
+    }
+
+    /// Salt the digests. Salts longer than 16 bytes are hashed down to 16.
+    pub fn with_salt<S>(mut self, salt: S) -> Self
+    where
+        S: AsRef<[u8]>,
+    {
+        let salt = salt.as_ref();
+        self.salt = if salt.len() > MAX_SALT_LEN {
+            blake_hash(salt)[..MAX_SALT_LEN].to_vec()
+        } else {
+            salt.to_vec()
+        };
+        self
+    }
+
+    /// Mix the namespace into the digests
+    pub fn with_namespace<N>(mut self, namespace: N) -> Self
+    where
+        N: Into<String>,
+    {
+        self.namespace = namespace.into();
+        self
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    // key a single digest. The namespace is length prefixed, so that it can
+    // not run into the digest
+    fn key_digest(&self, digest: &[u8]) -> Vec<u8> {
+        let mut hasher = Blake2b::with_params(&self.key, &self.salt, &[]);
+        hasher.update((self.namespace.len() as u64).to_le_bytes());
+        hasher.update(self.namespace.as_bytes());
+        hasher.update(digest);
+        let keyed = hasher.finalize().to_vec();
+        self.inner
+            .lock()
+            .unwrap()
+            .insert(keyed.clone(), digest.to_vec());
+        keyed
+    }
+
+    // the digest of the wrapped hasher which keyed was made from
+    fn unkey(&self, keyed: &[u8]) -> Option<Vec<u8>> {
+        self.inner.lock().unwrap().get(keyed).cloned()
+    }
+}
+
+impl<H: CalcHash> CalcHash for Keyed<H> {
+    /// Key the digest of each input separately, preserving the one digest per
+    /// input layout of the wrapped hasher
+    fn calc_hash<R>(&self, inputs: &[R]) -> Result<Vec<u8>>
+    where
+        R: AsRef<str>,
+    {
+        let hash = self.hasher.calc_hash(inputs)?;
+        if inputs.is_empty() || hash.len() % inputs.len() != 0 {
+            return Ok(self.key_digest(&hash));
+        }
+        Ok(hash
+            .chunks(hash.len() / inputs.len())
+            .flat_map(|digest| self.key_digest(digest))
+            .collect())
+    }
+
+    fn changed_entries(&self, input: &str, old: &[u8], new: &[u8]) -> Vec<String> {
+        match (self.unkey(old), self.unkey(new)) {
+            (Some(old), Some(new)) => self.hasher.changed_entries(input, &old, &new),
+            _ => Vec::new(),
+        }
+    }
+
+    // the wrapped hasher is told about its own digests, wherever we know them
+    fn committed(&self, output: &str, hash: &[u8]) -> Result<()> {
+        let inner = self.inner.lock().unwrap();
+        let hash = hash
+            .chunks(DIGEST_LEN)
+            .flat_map(|digest| inner.get(digest).map_or(digest, |d| &d[..]).to_vec())
+            .collect::<Vec<_>>();
+        drop(inner);
+        self.hasher.committed(output, &hash)
+    }
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/keyed_test.rs"]
+mod tests;
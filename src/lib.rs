@@ -54,11 +54,65 @@ pub use open_mode::OpenMode;
 pub mod file;
 pub use file::HtFile;
 //
+pub mod auth;
+pub use auth::{AuthFile, Authenticator};
+//
 pub mod utils;
+//
+pub mod normalize;
+//
+pub mod semantic;
+//
+pub mod archive;
+//
+pub mod merkle;
+//
+pub mod chunk;
+//
+pub mod sample;
+//
+pub mod append;
+//
+pub mod read;
+//
+pub mod git;
+//
+pub mod index;
+//
+pub mod keyed;
+pub use keyed::Keyed;
+//
+pub mod inputs;
 //use utils::*;
 //
+pub mod lock;
+pub use lock::Lockfile;
+//
+pub mod fingerprint;
+pub use fingerprint::Fingerprint;
+//
+pub mod report;
+pub use report::{Change, Report};
+//
 pub mod hashit;
 pub use hashit::*;
+//
+pub mod graph;
+pub use graph::{Graph, Target};
+//
+pub mod watch;
+pub use watch::{Reaction, Watcher};
+//
+pub mod daemon;
+//
+pub mod checksum;
+//
+pub mod build;
+//
+pub mod cache;
+pub use cache::ArtifactCache;
+//
+pub mod remote;
 
 #[cfg(test)]
 pub mod string;
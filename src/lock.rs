@@ -0,0 +1,145 @@
+//! Lock
+//!
+//! Pins files to known digests, so that vendored assets may be verified
+//! against the digests they were reviewed with, rather than merely against
+//! the previous run. A lockfile uses the coreutils `--tag` format, which
+//! records the algorithm along with each digest:
+//!
+//! ```text
+//! # hashtest lockfile
+//! BLAKE2b (vendor/jquery.js) = 786a02f7...
+//! SHA256 (vendor/logo.png) = e3b0c442...
+//! ```
+//!
+//! Lockfiles are generated and verified via `Hashit::lock` and
+//! `Hashit::verify_lock`.
+use crate::checksum::{escape, parse_line, Algorithm};
+use crate::error::{HashitError, Result};
+use crate::utils::read_file;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// The name of the lockfile used when none is supplied
+pub const DEFAULT_LOCKFILE: &str = "hashtest.lock";
+
+const HEADER: &str = "# hashtest lockfile";
+
+/// A file pinned to a digest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pin {
+    pub path: PathBuf,
+    pub algorithm: Algorithm,
+    /// hex encoded digest
+    pub digest: String,
+}
+
+/// A pinned file which failed verification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub path: PathBuf,
+    pub algorithm: Algorithm,
+    pub expected: String,
+    /// The digest found, or the reason the file could not be read
+    pub actual: std::result::Result<String, String>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: expected {} {}, ",
+            self.path.display(),
+            self.algorithm.tag(),
+            self.expected
+        )?;
+        match &self.actual {
+            Ok(actual) => write!(f, "found {}", actual),
+            Err(e) => write!(f, "could not be read ({})", e),
+        }
+    }
+}
+
+/// Format a list of mismatches, one per line
+pub fn format_mismatches(mismatches: &[Mismatch]) -> String {
+    mismatches.iter().map(|m| format!("\n  {}", m)).collect()
+}
+
+/// A set of pinned files
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lockfile {
+    pins: Vec<Pin>,
+}
+
+impl Lockfile {
+    pub fn new(pins: Vec<Pin>) -> Self {
+        Lockfile { pins }
+    }
+
+    pub fn pins(&self) -> &[Pin] {
+        &self.pins
+    }
+
+    /// Parse the contents of a lockfile
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut pins = Vec::new();
+        for (idx, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = parse_line(line, idx + 1)?;
+            // unlike a checksum file, the algorithm must be explicit
+            let algorithm = entry
+                .algorithm
+                .filter(|a| a.digest_len() * 2 == entry.digest.len())
+                .ok_or_else(|| HashitError::MalformedChecksum {
+                    line: idx + 1,
+                    text: line.to_string(),
+                })?;
+            pins.push(Pin {
+                path: entry.path,
+                algorithm,
+                digest: entry.digest,
+            });
+        }
+        Ok(Lockfile { pins })
+    }
+
+    /// Read the lockfile at path
+    pub fn read<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::parse(&String::from_utf8_lossy(&read_file(path)?))
+    }
+
+    /// Write the lockfile to path
+    pub fn write<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        std::fs::write(path, self.to_string())?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Lockfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        for pin in &self.pins {
+            let (escaped, name) = escape(&pin.path.to_string_lossy());
+            writeln!(
+                f,
+                "{}{} ({}) = {}",
+                if escaped { "\\" } else { "" },
+                pin.algorithm.tag(),
+                name,
+                pin.digest
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/lock_test.rs"]
+mod tests;
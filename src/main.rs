@@ -1,25 +1,457 @@
 //use hashtest::has_changed;
-use hashtest::Hashit;
-use hashtest::Result as HtResult;
-use std::path::PathBuf;
+use hashtest::checksum::{self, Algorithm, Status};
+use hashtest::daemon;
+use hashtest::file::FileHash;
+use hashtest::fingerprint::split_command;
+use hashtest::git::{self, ObjectFormat};
+use hashtest::index::GitIndex;
+use hashtest::inputs::{expand_args, expand_args_and_globs, read_depfile};
+use hashtest::lock::{Lockfile, DEFAULT_LOCKFILE};
+use hashtest::read::{ReadHash, ReadStrategy};
+use hashtest::traits::CalcHash;
+use hashtest::{AuthFile, Authenticator, Fingerprint, Hashit, HtFile};
+use hashtest::{HashitError, Result as HtResult};
+use hashtest::{Reaction, Watcher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use structopt::clap::{Error as ClapError, ErrorKind};
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "basic")]
 struct Opt {
     #[structopt(short, long, parse(from_os_str))]
-    outpath: PathBuf,
-    #[structopt(short, long, parse(from_os_str))]
-    sources: Vec<PathBuf>,
+    outpath: Option<PathBuf>,
+    #[structopt(flatten)]
+    inputs: InputArgs,
+    #[structopt(flatten)]
+    fingerprint: FingerprintArgs,
+    #[structopt(subcommand)]
+    cmd: Option<Cmd>,
+}
+
+#[derive(StructOpt, Debug)]
+struct InputArgs {
+    /// Source files. Use @FILE to read sources from a file, or - to read them
+    /// from stdin, one per line or NUL separated
+    #[structopt(short, long)]
+    sources: Vec<String>,
+    /// Make style dependency file (as written by gcc -MD or rustc
+    /// --emit dep-info) whose prerequisites are added to the sources
+    #[structopt(long, parse(from_os_str))]
+    depfile: Vec<PathBuf>,
+    /// Trust the blob IDs recorded in the git index for clean files, and
+    /// expand directory sources into the files git sees, respecting
+    /// .gitignore
+    #[structopt(long)]
+    git_index: bool,
+    /// How files are read while hashing: buffered, mmap (unsafe should a
+    /// file be truncated meanwhile), direct (bypassing the page cache) or
+    /// auto (buffered or direct, chosen by size). Not used with --git-index
+    #[structopt(long)]
+    read: Option<ReadStrategy>,
+}
+
+impl InputArgs {
+    fn paths(&self) -> HtResult<Vec<PathBuf>> {
+        let mut paths = expand_args(&self.sources)?;
+        for depfile in &self.depfile {
+            paths.extend(read_depfile(depfile)?);
+        }
+        Ok(paths)
+    }
+
+    // like paths, but globs given as sources are expanded. Paths read from
+    // lists and depfiles are taken literally.
+    fn expanded_paths(&self) -> HtResult<Vec<PathBuf>> {
+        let mut paths = expand_args_and_globs(&self.sources)?;
+        for depfile in &self.depfile {
+            paths.extend(read_depfile(depfile)?);
+        }
+        paths.sort();
+        paths.dedup();
+        Ok(paths)
+    }
+
+    // the index of the working tree containing the current directory, when
+    // --git-index is given
+    fn git_index(&self) -> HtResult<Option<GitIndex>> {
+        if self.git_index {
+            GitIndex::discover(".").map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+struct FingerprintArgs {
+    /// Environment variable whose value the outputs depend upon
+    #[structopt(long = "env")]
+    envs: Vec<String>,
+    /// Tool whose --version output the outputs depend upon
+    #[structopt(long = "tool")]
+    tools: Vec<String>,
+    /// Arbitrary KEY=VALUE which the outputs depend upon
+    #[structopt(long = "value", parse(try_from_str = parse_key_value))]
+    values: Vec<(String, String)>,
+    /// Command whose stdout and exit status the outputs depend upon, such as
+    /// "git rev-parse HEAD". Split into arguments as a shell would, so
+    /// arguments containing spaces may be quoted
+    #[structopt(long = "command")]
+    commands: Vec<String>,
+    /// Seconds after which a --command is killed, failing the check
+    #[structopt(long)]
+    command_timeout: Option<f64>,
+}
+
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, found '{}'", s))
+}
+
+impl FingerprintArgs {
+    fn fingerprint(&self) -> HtResult<Fingerprint> {
+        let mut fingerprint = Fingerprint::new();
+        for env in &self.envs {
+            fingerprint = fingerprint.with_env(env);
+        }
+        for tool in &self.tools {
+            fingerprint = fingerprint.with_tool(tool);
+        }
+        for (key, value) in &self.values {
+            fingerprint = fingerprint.with_value(key, value);
+        }
+        let timeout = self.command_timeout.map(Duration::from_secs_f64);
+        for command in &self.commands {
+            fingerprint = fingerprint.with_command(&split_command(command)?, timeout);
+        }
+        Ok(fingerprint)
+    }
+}
+
+#[derive(StructOpt, Debug)]
+enum Cmd {
+    /// Watch the sources, reporting (or running a command) whenever their
+    /// contents change
+    Watch {
+        #[structopt(short, long, parse(from_os_str))]
+        outpath: PathBuf,
+        #[structopt(flatten)]
+        inputs: InputArgs,
+        #[structopt(flatten)]
+        fingerprint: FingerprintArgs,
+        /// Milliseconds of quiet required before reacting to a burst of events
+        #[structopt(short, long, default_value = "200")]
+        debounce: u64,
+        /// Command to run when the sources change
+        #[structopt(last = true)]
+        command: Vec<String>,
+    },
+    /// Print a stable digest of the sources, suitable for use as a cache key
+    Digest {
+        /// Source files or glob patterns
+        #[structopt(flatten)]
+        inputs: InputArgs,
+        #[structopt(flatten)]
+        fingerprint: FingerprintArgs,
+    },
+    /// Print b2sum compatible checksums of the files
+    Sum {
+        /// Print checksums in BSD style (--tag) format
+        #[structopt(long)]
+        tag: bool,
+        /// Digest algorithm (blake2b or sha256). Requires --tag for sha256
+        #[structopt(short, long, default_value = "blake2b")]
+        algorithm: Algorithm,
+        /// Print git object IDs (sha1 or sha256 object format) instead: blob
+        /// IDs for files and tree IDs for directories
+        #[structopt(long)]
+        git: Option<ObjectFormat>,
+        #[structopt(parse(from_os_str))]
+        files: Vec<PathBuf>,
+    },
+    /// Verify the files listed in a b2sum or sha256sum checksum file
+    Check {
+        /// Digest algorithm. Inferred from each line when omitted
+        #[structopt(short, long)]
+        algorithm: Option<Algorithm>,
+        /// Do not print OK for each successfully verified file
+        #[structopt(long)]
+        quiet: bool,
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+    /// Pin the sources to their current digests, writing a lockfile
+    Lock {
+        #[structopt(flatten)]
+        inputs: InputArgs,
+        /// Digest algorithm (blake2b or sha256)
+        #[structopt(short, long, default_value = "blake2b")]
+        algorithm: Algorithm,
+        #[structopt(long, parse(from_os_str), default_value = DEFAULT_LOCKFILE)]
+        lockfile: PathBuf,
+    },
+    /// Verify that the files pinned by a lockfile match their digests
+    Verify {
+        #[structopt(long, parse(from_os_str), default_value = DEFAULT_LOCKFILE)]
+        lockfile: PathBuf,
+    },
+    /// Run a daemon which keeps file digests in memory, answering requests
+    /// over a Unix domain socket
+    Daemon {
+        /// Path of the socket. Defaults to $HASHTEST_SOCKET, or
+        /// $XDG_RUNTIME_DIR/hashtest.sock
+        #[structopt(long, parse(from_os_str))]
+        socket: Option<PathBuf>,
+        /// Ask a running daemon to exit
+        #[structopt(long)]
+        stop: bool,
+    },
 }
 
 fn main() -> HtResult<()> {
     let opt = Opt::from_args();
-    let mut hashit = Hashit::new();
-    println!(
-        "Has file changed? {}",
-        hashit.has_changed(&opt.sources[..], &opt.outpath)?
-    );
+    match opt.cmd {
+        Some(Cmd::Watch {
+            outpath,
+            inputs,
+            fingerprint,
+            debounce,
+            command,
+        }) => {
+            let paths = inputs.paths()?;
+            let fingerprint = fingerprint.fingerprint()?;
+            match (inputs.git_index()?, inputs.read) {
+                (Some(index), _) => {
+                    let paths = index.expand(&paths)?;
+                    watch(index, fingerprint, &paths, &outpath, debounce, &command)
+                }
+                (None, Some(read)) => watch(
+                    ReadHash::new(read),
+                    fingerprint,
+                    &paths,
+                    &outpath,
+                    debounce,
+                    &command,
+                ),
+                (None, None) => watch(
+                    FileHash {},
+                    fingerprint,
+                    &paths,
+                    &outpath,
+                    debounce,
+                    &command,
+                ),
+            }
+        }
+        Some(Cmd::Digest {
+            inputs,
+            fingerprint,
+        }) => {
+            let paths = inputs.expanded_paths()?;
+            let fingerprint = fingerprint.fingerprint()?;
+            let digest = match inputs.git_index()? {
+                Some(index) => {
+                    let paths = index.expand(&paths)?;
+                    Hashit::from_parts(HtFile::new(), index)
+                        .with_fingerprint(fingerprint)
+                        .digest(&paths)?
+                }
+                None => match inputs.read {
+                    Some(read) => Hashit::from_parts(HtFile::new(), ReadHash::new(read))
+                        .with_fingerprint(fingerprint)
+                        .digest(&paths)?,
+                    None => Hashit::new().with_fingerprint(fingerprint).digest(&paths)?,
+                },
+            };
+            println!("{}", digest);
+            Ok(())
+        }
+        Some(Cmd::Sum {
+            tag,
+            algorithm,
+            git,
+            files,
+        }) => {
+            // refuse before anything is written, even given no files
+            if git.is_none() && !tag && algorithm != Algorithm::Blake2b512 {
+                ClapError::with_description(
+                    "Only blake2b is supported without --tag",
+                    ErrorKind::InvalidValue,
+                )
+                .exit()
+            }
+            for file in files {
+                if let Some(format) = git {
+                    let id = git::object_id(&file, format)?;
+                    println!("{}  {}", hex::encode(id), file.display());
+                } else if tag {
+                    println!("{}", checksum::tag_line(&file, algorithm)?);
+                } else {
+                    println!("{}", checksum::sum_line(&file)?);
+                }
+            }
+            Ok(())
+        }
+        Some(Cmd::Check {
+            algorithm,
+            quiet,
+            file,
+        }) => check(&file, algorithm, quiet),
+        Some(Cmd::Lock {
+            inputs,
+            algorithm,
+            lockfile,
+        }) => Hashit::new()
+            .lock(&inputs.expanded_paths()?, algorithm)?
+            .write(&lockfile),
+        Some(Cmd::Verify { lockfile }) => {
+            match Hashit::new().verify_lock(&Lockfile::read(&lockfile)?) {
+                Err(e @ HashitError::LockMismatch(_)) => {
+                    eprintln!("hashtest: {}", e);
+                    std::process::exit(1);
+                }
+                result => result,
+            }
+        }
+        Some(Cmd::Daemon { socket, stop }) => {
+            let socket = socket.unwrap_or_else(daemon::default_socket_path);
+            if stop {
+                return daemon::Client::new(&socket).shutdown();
+            }
+            let mut daemon = daemon::Daemon::bind(&socket)?;
+            eprintln!("listening on {}", daemon.path().display());
+            daemon.serve()
+        }
+        None => {
+            let outpath = opt.outpath.unwrap_or_else(|| {
+                ClapError::with_description(
+                    "The following required arguments were not provided: --outpath <outpath>",
+                    ErrorKind::MissingRequiredArgument,
+                )
+                .exit()
+            });
+            let inputs = opt.inputs.paths()?;
+            let fingerprint = opt.fingerprint.fingerprint()?;
+            let changed = match (opt.inputs.git_index()?, opt.inputs.read) {
+                (Some(index), _) => {
+                    let inputs = index.expand(&inputs)?;
+                    has_changed(index, fingerprint, &inputs, &outpath)?
+                }
+                (None, Some(read)) => {
+                    has_changed(ReadHash::new(read), fingerprint, &inputs, &outpath)?
+                }
+                (None, None) => has_changed(FileHash {}, fingerprint, &inputs, &outpath)?,
+            };
+            println!("Has file changed? {}", changed);
+            Ok(())
+        }
+    }
+}
+
+// check the inputs against the stamp at outpath. Stamps are signed and
+// verified when a key is configured.
+fn has_changed<H>(
+    hasher: H,
+    fingerprint: Fingerprint,
+    inputs: &[PathBuf],
+    outpath: &Path,
+) -> HtResult<bool>
+where
+    H: CalcHash + std::fmt::Debug,
+{
+    match Authenticator::from_env()? {
+        Some(auth) => Hashit::from_parts(AuthFile::new(auth), hasher)
+            .with_fingerprint(fingerprint)
+            .has_changed(inputs, outpath),
+        None => Hashit::from_parts(HtFile::new(), hasher)
+            .with_fingerprint(fingerprint)
+            .has_changed(inputs, outpath),
+    }
+}
+
+fn watch<H>(
+    hasher: H,
+    fingerprint: Fingerprint,
+    sources: &[PathBuf],
+    outpath: &Path,
+    debounce: u64,
+    command: &[String],
+) -> HtResult<()>
+where
+    H: CalcHash + std::fmt::Debug,
+{
+    let mut hashit = Hashit::from_parts(HtFile::new(), hasher).with_fingerprint(fingerprint);
+    let mut watcher =
+        Watcher::new(sources, outpath)?.with_debounce(Duration::from_millis(debounce));
+    watcher.watch(&mut hashit, |_| {
+        match command.split_first() {
+            Some((program, args)) => {
+                let status = Command::new(program).args(args).status()?;
+                if !status.success() {
+                    // leave the stamp alone, so that the next change retries
+                    eprintln!("{} exited with {}", program, status);
+                    return Ok(Reaction::Failed);
+                }
+            }
+            None => println!("Files have changed"),
+        }
+        Ok(Reaction::Done)
+    })
+}
 
+// verify a checksum file, exiting with a non zero status if any entry fails
+fn check(file: &Path, algorithm: Option<Algorithm>, quiet: bool) -> HtResult<()> {
+    let outcomes = checksum::check_file(file, algorithm)?;
+    let mut failed = 0;
+    let mut unreadable = 0;
+    let mut malformed = 0;
+    for outcome in &outcomes {
+        match &outcome.status {
+            Status::Malformed(_) => {
+                malformed += 1;
+                continue;
+            }
+            Status::Ok if quiet => continue,
+            Status::Ok => (),
+            Status::Failed => failed += 1,
+            Status::Unreadable(e) => {
+                eprintln!("hashtest: {}", e);
+                unreadable += 1
+            }
+        }
+        println!("{}", outcome.line());
+    }
+    if malformed > 0 {
+        eprintln!(
+            "hashtest: WARNING: {} line(s) are improperly formatted",
+            malformed
+        );
+    }
+    if malformed == outcomes.len() {
+        eprintln!(
+            "hashtest: {}: no properly formatted checksum lines found",
+            file.display()
+        );
+        std::process::exit(1);
+    }
+    if unreadable > 0 {
+        eprintln!(
+            "hashtest: WARNING: {} listed file(s) could not be read",
+            unreadable
+        );
+    }
+    if failed > 0 {
+        eprintln!(
+            "hashtest: WARNING: {} computed checksum(s) did NOT match",
+            failed
+        );
+    }
+    if failed + unreadable > 0 {
+        std::process::exit(1);
+    }
     Ok(())
 }
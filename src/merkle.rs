@@ -0,0 +1,491 @@
+//! Merkle
+//!
+//! Hashes directories as Merkle trees, in which the digest of each directory
+//! derives from the names, kinds and digests of its children. Two trees may
+//! then be compared a subtree at a time, skipping any subtree whose digest is
+//! unchanged, and the difference summarized as, say, "only `src/net/`
+//! changed".
+//!
+//! When given a cache directory, MerkleHash persists each tree. The next
+//! time the same directory is hashed, files whose size, modification time
+//! and inode are unchanged reuse their recorded digests rather than being
+//! read again. As with git's racy-clean check, a file modified in the same
+//! second as the tree was built, or later, is always read again, since it
+//! may have been rewritten without its modification time moving on. Only
+//! the latest tree of each directory, and the trees which a stamp refers to,
+//! are kept.
+//!
+//! Special files (fifos, sockets and devices) are never read. They are
+//! hashed by their type, and for devices, their device number.
+//!
+//! Trees are persisted one entry per line, sorted by path, with the root
+//! recorded as `.`, after a header recording when the tree was built:
+//!
+//! ```text
+//! built\t<seconds since the epoch>
+//! <kind>\t<hex digest>\t<size>\t<mtime>\t<inode>\t<path>
+//! ```
+use crate::error::{HashitError, Result};
+use crate::traits::CalcHash;
+use crate::utils::{blake_hash, read_file};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// the length of a Blake2b digest, which is what stamps are made of
+const DIGEST_LEN: usize = 64;
+
+/// The kinds of entry within a tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    File,
+    Dir,
+    Symlink,
+    /// a fifo, socket or device, which is hashed by its type
+    Special,
+}
+
+impl Kind {
+    fn code(&self) -> char {
+        match self {
+            Self::File => 'F',
+            Self::Dir => 'D',
+            Self::Symlink => 'L',
+            Self::Special => 'S',
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "F" => Some(Self::File),
+            "D" => Some(Self::Dir),
+            "L" => Some(Self::Symlink),
+            "S" => Some(Self::Special),
+            _ => None,
+        }
+    }
+}
+
+/// A single file, directory or link within a tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub kind: Kind,
+    pub digest: Vec<u8>,
+    pub size: u64,
+    /// modification time, in nanoseconds since the epoch
+    pub mtime: i128,
+    pub inode: u64,
+}
+
+/// A directory, hashed as a Merkle tree
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tree {
+    /// keyed by path relative to the root, which is the empty path
+    entries: BTreeMap<PathBuf, Entry>,
+    /// when the tree was built, in seconds since the epoch, rounded down
+    built: u64,
+}
+
+impl Tree {
+    /// Hash the directory at root. Files which are unchanged since previous
+    /// was built, going by their size, modification time and inode, reuse
+    /// their digests from previous, unless they were modified no earlier
+    /// than the second in which previous was built.
+    pub fn build<P>(root: P, previous: Option<&Tree>) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let root = root.as_ref();
+        if !root.is_dir() {
+            return Err(HashitError::MissingDir(root.display().to_string()));
+        }
+        let built = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut tree = Tree {
+            built,
+            ..Tree::default()
+        };
+        tree.walk(root, PathBuf::new(), previous)?;
+        Ok(tree)
+    }
+
+    // hash the entry at path, and everything beneath it, returning its kind
+    // and digest
+    fn walk(
+        &mut self,
+        path: &Path,
+        rel: PathBuf,
+        previous: Option<&Tree>,
+    ) -> Result<(Kind, Vec<u8>)> {
+        let metadata = fs::symlink_metadata(path)?;
+        let mtime = metadata.mtime() as i128 * 1_000_000_000 + metadata.mtime_nsec() as i128;
+        let file_type = metadata.file_type();
+        let kind = if file_type.is_symlink() {
+            Kind::Symlink
+        } else if file_type.is_dir() {
+            Kind::Dir
+        } else if file_type.is_file() {
+            Kind::File
+        } else {
+            Kind::Special
+        };
+        let digest = match kind {
+            Kind::Symlink => blake_hash(fs::read_link(path)?.to_string_lossy().as_bytes()),
+            // reading a fifo would block until something writes to it
+            Kind::Special => {
+                let special = if file_type.is_fifo() {
+                    String::from("fifo")
+                } else if file_type.is_socket() {
+                    String::from("socket")
+                } else if file_type.is_char_device() {
+                    format!("char device {}", metadata.rdev())
+                } else {
+                    format!("block device {}", metadata.rdev())
+                };
+                blake_hash(special.as_bytes())
+            }
+            Kind::File => {
+                let reused = previous.and_then(|p| p.entries.get(&rel).map(|e| (p, e)));
+                let reused = reused.filter(|(p, e)| {
+                    e.kind == Kind::File
+                        && e.size == metadata.len()
+                        && e.mtime == mtime
+                        && e.inode == metadata.ino()
+                        && mtime < p.built as i128 * 1_000_000_000
+                });
+                match reused {
+                    Some((_, entry)) => entry.digest.clone(),
+                    None => blake_hash(&read_file(path)?),
+                }
+            }
+            Kind::Dir => {
+                let mut names = fs::read_dir(path)?
+                    .map(|entry| entry.map(|e| e.file_name()))
+                    .collect::<std::io::Result<Vec<_>>>()?;
+                names.sort();
+                let mut listing = Vec::new();
+                for name in names {
+                    let (kind, digest) = self.walk(&path.join(&name), rel.join(&name), previous)?;
+                    listing.extend(name.to_string_lossy().as_bytes());
+                    listing.push(0);
+                    listing.push(kind.code() as u8);
+                    listing.extend(digest);
+                }
+                blake_hash(&listing)
+            }
+        };
+        self.entries.insert(
+            rel,
+            Entry {
+                kind,
+                digest: digest.clone(),
+                size: metadata.len(),
+                mtime,
+                inode: metadata.ino(),
+            },
+        );
+        Ok((kind, digest))
+    }
+
+    /// The digest of the root directory
+    pub fn digest(&self) -> Vec<u8> {
+        self.entries
+            .get(Path::new(""))
+            .map(|e| e.digest.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn get<P>(&self, path: P) -> Option<&Entry>
+    where
+        P: AsRef<Path>,
+    {
+        self.entries.get(path.as_ref())
+    }
+
+    /// Parse a tree, as written by Display
+    pub fn parse(contents: &str) -> Option<Self> {
+        let mut lines = contents.lines();
+        let built = lines.next()?.strip_prefix("built\t")?.parse().ok()?;
+        let mut entries = BTreeMap::new();
+        for line in lines {
+            let mut fields = line.splitn(6, '\t');
+            let kind = Kind::from_code(fields.next()?)?;
+            let digest = hex::decode(fields.next()?).ok()?;
+            let size = fields.next()?.parse().ok()?;
+            let mtime = fields.next()?.parse().ok()?;
+            let inode = fields.next()?.parse().ok()?;
+            let path = match fields.next()? {
+                "." => PathBuf::new(),
+                path => PathBuf::from(path),
+            };
+            let entry = Entry {
+                kind,
+                digest,
+                size,
+                mtime,
+                inode,
+            };
+            entries.insert(path, entry);
+        }
+        Some(Tree { entries, built })
+    }
+
+    // the children of each directory
+    fn children(&self) -> HashMap<&Path, Vec<&Path>> {
+        let mut children: HashMap<&Path, Vec<&Path>> = HashMap::new();
+        for path in self.entries.keys() {
+            if let Some(parent) = path.parent() {
+                children.entry(parent).or_default().push(path);
+            }
+        }
+        children
+    }
+
+    /// Summarize what changed since previous. Subtrees whose digests match
+    /// are skipped. A directory in which more than one child changed is
+    /// reported as a whole, with a trailing `/`; otherwise the search
+    /// continues into the single child which changed.
+    pub fn diff(&self, previous: &Tree) -> Vec<String> {
+        let old = previous.children();
+        let new = self.children();
+        let root = Path::new("");
+        let changed = changed_children(root, previous, &old, self, &new);
+        let mut summary = Vec::new();
+        for child in changed {
+            summarize(child, previous, &old, self, &new, &mut summary);
+        }
+        summary.sort();
+        summary
+    }
+}
+
+// the children of dir which differ between old and new
+fn changed_children<'a>(
+    dir: &Path,
+    old: &'a Tree,
+    old_children: &HashMap<&Path, Vec<&'a Path>>,
+    new: &'a Tree,
+    new_children: &HashMap<&Path, Vec<&'a Path>>,
+) -> Vec<&'a Path> {
+    let mut children = old_children
+        .get(dir)
+        .into_iter()
+        .chain(new_children.get(dir))
+        .flatten()
+        .copied()
+        .filter(|path| {
+            let (before, after) = (old.entries.get(*path), new.entries.get(*path));
+            before.map(|e| (e.kind, &e.digest)) != after.map(|e| (e.kind, &e.digest))
+        })
+        .collect::<Vec<_>>();
+    children.sort();
+    children.dedup();
+    children
+}
+
+fn summarize(
+    path: &Path,
+    old: &Tree,
+    old_children: &HashMap<&Path, Vec<&Path>>,
+    new: &Tree,
+    new_children: &HashMap<&Path, Vec<&Path>>,
+    summary: &mut Vec<String>,
+) {
+    let is_dir = |tree: &Tree| tree.entries.get(path).is_some_and(|e| e.kind == Kind::Dir);
+    if !(is_dir(old) && is_dir(new)) {
+        let dir = if is_dir(old) || is_dir(new) { "/" } else { "" };
+        summary.push(format!("{}{}", path.display(), dir));
+        return;
+    }
+    match changed_children(path, old, old_children, new, new_children)[..] {
+        [child] => summarize(child, old, old_children, new, new_children, summary),
+        _ => summary.push(format!("{}/", path.display())),
+    }
+}
+
+impl fmt::Display for Tree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "built\t{}", self.built)?;
+        for (path, entry) in &self.entries {
+            let path = path.to_string_lossy();
+            writeln!(
+                f,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                entry.kind.code(),
+                hex::encode(&entry.digest),
+                entry.size,
+                entry.mtime,
+                entry.inode,
+                if path.is_empty() { "." } else { &path }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A CalcHash which hashes directories as Merkle trees. Other inputs are
+/// hashed by the fallback.
+#[derive(Debug, Clone)]
+pub struct MerkleHash<H> {
+    fallback: H,
+    cache: Option<PathBuf>,
+}
+
+impl<H> MerkleHash<H> {
+    pub fn new(fallback: H) -> Self {
+        MerkleHash {
+            fallback,
+            cache: None,
+        }
+    }
+
+    /// Persist trees within dir, allowing unchanged files to be skipped and
+    /// changed_entries to compare trees
+    pub fn with_cache<P>(mut self, dir: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.cache = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    // trees are stored by digest, with a pointer to the latest tree for each
+    // directory stored by the digest of its path
+    fn load(&self, digest: &[u8]) -> Option<Tree> {
+        let path = self.cache.as_ref()?.join("trees").join(hex::encode(digest));
+        Tree::parse(&fs::read_to_string(path).ok()?)
+    }
+
+    fn latest_path(&self, root: &Path) -> Option<PathBuf> {
+        let root = fs::canonicalize(root).ok()?;
+        let name = hex::encode(blake_hash(root.to_string_lossy().as_bytes()));
+        Some(self.cache.as_ref()?.join("roots").join(name))
+    }
+
+    fn latest(&self, root: &Path) -> Option<Tree> {
+        let digest = fs::read_to_string(self.latest_path(root)?).ok()?;
+        self.load(&hex::decode(digest.trim()).ok()?)
+    }
+
+    // the digests which stamps refer to
+    fn pins(dir: &Path) -> HashSet<String> {
+        let mut pins = HashSet::new();
+        if let Ok(entries) = fs::read_dir(dir.join("stamps")) {
+            for entry in entries.flatten() {
+                let contents = fs::read_to_string(entry.path()).unwrap_or_default();
+                pins.extend(contents.lines().map(String::from));
+            }
+        }
+        pins
+    }
+
+    fn store(&self, root: &Path, tree: &Tree) -> Result<()> {
+        let (dir, latest) = match (&self.cache, self.latest_path(root)) {
+            (Some(dir), Some(latest)) => (dir, latest),
+            _ => return Ok(()),
+        };
+        let superseded = fs::read_to_string(&latest).ok();
+        let digest = hex::encode(tree.digest());
+        let trees = dir.join("trees");
+        fs::create_dir_all(&trees)?;
+        fs::write(trees.join(&digest), tree.to_string())?;
+        fs::create_dir_all(dir.join("roots"))?;
+        fs::write(latest, &digest)?;
+        // the tree this one replaces is only kept while a stamp needs it
+        if let Some(superseded) = superseded {
+            let superseded = superseded.trim();
+            if superseded != digest && !Self::pins(dir).contains(superseded) {
+                let _ = fs::remove_file(trees.join(superseded));
+            }
+        }
+        Ok(())
+    }
+
+    // remove every tree which is neither referred to by a stamp nor the
+    // latest tree of a directory
+    fn collect_garbage(dir: &Path) -> Result<()> {
+        let mut live = Self::pins(dir);
+        if let Ok(entries) = fs::read_dir(dir.join("roots")) {
+            for entry in entries.flatten() {
+                if let Ok(digest) = fs::read_to_string(entry.path()) {
+                    live.insert(digest.trim().to_string());
+                }
+            }
+        }
+        if let Ok(entries) = fs::read_dir(dir.join("trees")) {
+            for entry in entries.flatten() {
+                if !live.contains(entry.file_name().to_string_lossy().as_ref()) {
+                    let _ = fs::remove_file(entry.path());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the tree for the directory at root, reusing what we can from the
+    /// cache
+    pub fn tree<P>(&self, root: P) -> Result<Tree>
+    where
+        P: AsRef<Path>,
+    {
+        let root = root.as_ref();
+        let tree = Tree::build(root, self.latest(root).as_ref())?;
+        self.store(root, &tree)?;
+        Ok(tree)
+    }
+}
+
+impl<H: CalcHash> CalcHash for MerkleHash<H> {
+    fn calc_hash<P>(&self, inputs: &[P]) -> Result<Vec<u8>>
+    where
+        P: AsRef<str>,
+    {
+        let mut resvec = Vec::new();
+        for input in inputs {
+            let path = Path::new(input.as_ref());
+            if path.is_dir() {
+                resvec.extend(self.tree(path)?.digest());
+            } else {
+                resvec.extend(self.fallback.calc_hash(&[input])?);
+            }
+        }
+        Ok(resvec)
+    }
+
+    fn changed_entries(&self, input: &str, old: &[u8], new: &[u8]) -> Vec<String> {
+        match (self.load(old), self.load(new)) {
+            (Some(old), Some(new)) => new.diff(&old),
+            _ => self.fallback.changed_entries(input, old, new),
+        }
+    }
+
+    // remember which of our trees the stamp refers to, replacing whatever it
+    // referred to before, and drop the trees nothing refers to any more
+    fn committed(&self, output: &str, hash: &[u8]) -> Result<()> {
+        self.fallback.committed(output, hash)?;
+        let dir = match &self.cache {
+            Some(dir) => dir,
+            None => return Ok(()),
+        };
+        let trees = dir.join("trees");
+        let pins = hash
+            .chunks(DIGEST_LEN)
+            .map(hex::encode)
+            .filter(|digest| trees.join(digest).exists())
+            .collect::<Vec<_>>();
+        let stamps = dir.join("stamps");
+        fs::create_dir_all(&stamps)?;
+        let name = hex::encode(blake_hash(output.as_bytes()));
+        fs::write(stamps.join(name), pins.join("\n"))?;
+        Self::collect_garbage(dir)
+    }
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/merkle_test.rs"]
+mod tests;
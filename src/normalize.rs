@@ -0,0 +1,224 @@
+//! Normalize
+//!
+//! Normalizers transform the contents of a file before it is hashed, so that
+//! changes which do not matter, such as a CRLF/LF flip from a different
+//! checkout or a `// Generated on <date>` header, do not count as changes.
+//!
+//! Files are read and hashed a line at a time, so that large files are never
+//! held in memory. Lines longer than MAX_LINE_LENGTH, such as minified code or
+//! binary data, are hashed as they are, a chunk at a time, without being
+//! normalized. NormalizedHash applies a Normalizer to the files matching each
+//! glob:
+//!
+//! ```
+//! use hashtest::normalize::{NormalizedHash, Normalizer};
+//! use hashtest::{Hashit, HtFile};
+//!
+//! # fn main() -> Result<(), hashtest::HashitError> {
+//! let generated = Normalizer::new()
+//!     .with_line_endings()
+//!     .with_dropped_lines(r"^// Generated on ")?;
+//! let hasher = NormalizedHash::new().with_rule("src/generated/*.rs", generated)?;
+//! let hashit = Hashit::from_parts(HtFile::new(), hasher);
+//! # Ok(())
+//! # }
+//! ```
+use crate::error::{HashitError, Result};
+use crate::traits::CalcHash;
+use blake2::{Blake2b, Digest};
+use glob::Pattern;
+use regex::bytes::Regex;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+const BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// The longest line which is normalized, including its line ending
+pub const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+// read up to and including the next newline onto line, stopping after
+// MAX_LINE_LENGTH bytes
+fn read_line<R: BufRead>(reader: &mut R, line: &mut Vec<u8>) -> Result<usize> {
+    Ok(reader
+        .take(MAX_LINE_LENGTH as u64)
+        .read_until(b'\n', line)?)
+}
+
+/// A pipeline of transformations applied to each line before it is hashed.
+/// In order: a leading byte order mark is removed, trailing whitespace is
+/// stripped, matching lines are dropped and line endings are normalized.
+#[derive(Debug, Clone, Default)]
+pub struct Normalizer {
+    strip_bom: bool,
+    trailing_whitespace: bool,
+    dropped: Vec<Regex>,
+    line_endings: bool,
+}
+
+impl Normalizer {
+    /// A normalizer which leaves contents untouched
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Treat CRLF line endings as LF
+    pub fn with_line_endings(mut self) -> Self {
+        self.line_endings = true;
+        self
+    }
+
+    /// Ignore spaces and tabs at the end of each line
+    pub fn with_trailing_whitespace(mut self) -> Self {
+        self.trailing_whitespace = true;
+        self
+    }
+
+    /// Ignore a UTF-8 byte order mark at the start of the file
+    pub fn with_bom(mut self) -> Self {
+        self.strip_bom = true;
+        self
+    }
+
+    /// Ignore lines matching the regular expression. The line ending is not
+    /// part of the line matched against.
+    pub fn with_dropped_lines(mut self, pattern: &str) -> Result<Self> {
+        let regex = Regex::new(pattern).map_err(|e| HashitError::InvalidRegex(e.to_string()))?;
+        self.dropped.push(regex);
+        Ok(self)
+    }
+
+    /// Calculate the digest of the normalized contents of reader
+    pub fn digest<R>(&self, reader: R) -> Result<Vec<u8>>
+    where
+        R: Read,
+    {
+        let mut reader = BufReader::new(reader);
+        let mut hasher = Blake2b::new();
+        let mut line = Vec::new();
+        let mut first = true;
+        loop {
+            line.clear();
+            if read_line(&mut reader, &mut line)? == 0 {
+                break;
+            }
+            let mut body = &line[..];
+            if first && self.strip_bom {
+                body = body.strip_prefix(BOM).unwrap_or(body);
+            }
+            first = false;
+
+            // too long to hold: hash the rest of the line as it is
+            if line.len() == MAX_LINE_LENGTH && !line.ends_with(b"\n") {
+                hasher.update(body);
+                loop {
+                    line.clear();
+                    if read_line(&mut reader, &mut line)? == 0 {
+                        break;
+                    }
+                    hasher.update(&line);
+                    if line.ends_with(b"\n") {
+                        break;
+                    }
+                }
+                continue;
+            }
+
+            let ending: &[u8] = if body.ends_with(b"\r\n") {
+                b"\r\n"
+            } else if body.ends_with(b"\n") {
+                b"\n"
+            } else {
+                b""
+            };
+            body = &body[..body.len() - ending.len()];
+            if self.trailing_whitespace {
+                let end = body
+                    .iter()
+                    .rposition(|c| *c != b' ' && *c != b'\t')
+                    .map_or(0, |idx| idx + 1);
+                body = &body[..end];
+            }
+            if self.dropped.iter().any(|regex| regex.is_match(body)) {
+                continue;
+            }
+            hasher.update(body);
+            if self.line_endings && ending == b"\r\n" {
+                hasher.update(b"\n");
+            } else {
+                hasher.update(ending);
+            }
+        }
+        Ok(hasher.finalize().to_vec())
+    }
+
+    /// Calculate the digest of the normalized contents of the file at path
+    pub fn digest_file<P>(&self, path: P) -> Result<Vec<u8>>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let file = fs::File::open(path).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                HashitError::NotFound {
+                    source: e,
+                    file: PathBuf::from(path),
+                }
+            } else {
+                e.into()
+            }
+        })?;
+        self.digest(file)
+    }
+}
+
+/// A CalcHash which applies a Normalizer to the files matching each glob.
+/// The first matching rule wins, and files matching none are hashed as is.
+#[derive(Debug, Clone, Default)]
+pub struct NormalizedHash {
+    rules: Vec<(Pattern, Normalizer)>,
+    raw: Normalizer,
+}
+
+impl NormalizedHash {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply normalizer to the files matching the glob pattern
+    pub fn with_rule(mut self, pattern: &str, normalizer: Normalizer) -> Result<Self> {
+        let pattern = Pattern::new(pattern)
+            .map_err(|e| HashitError::InvalidGlob(format!("{} - {}", pattern, e)))?;
+        self.rules.push((pattern, normalizer));
+        Ok(self)
+    }
+
+    /// The normalizer which applies to path
+    pub fn normalizer<P>(&self, path: P) -> &Normalizer
+    where
+        P: AsRef<Path>,
+    {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| pattern.matches_path(path.as_ref()))
+            .map_or(&self.raw, |(_, normalizer)| normalizer)
+    }
+}
+
+impl CalcHash for NormalizedHash {
+    fn calc_hash<P>(&self, files: &[P]) -> Result<Vec<u8>>
+    where
+        P: AsRef<str>,
+    {
+        let mut resvec = Vec::new();
+        for f in files {
+            let file = f.as_ref();
+            resvec.extend(self.normalizer(file).digest_file(file)?);
+        }
+        Ok(resvec)
+    }
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/normalize_test.rs"]
+mod tests;
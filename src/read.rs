@@ -0,0 +1,228 @@
+//! Read
+//!
+//! Strategies for reading files while hashing them. `utils::read_file` reads
+//! a whole file into memory, which is fine for sources but not for huge
+//! assets. The strategies here stream instead, and differ in how they treat
+//! the page cache:
+//!
+//! - buffered reads a MiB at a time through the page cache
+//! - mmap maps the file and hashes it in place, avoiding a copy. A file
+//!   truncated while it is mapped raises SIGBUS and kills the process, so
+//!   mmap is only ever used when asked for, on files known not to shrink
+//! - direct bypasses the page cache with `O_DIRECT`, so that a scan of a huge
+//!   asset tree does not evict everything else on a shared machine. Where the
+//!   filesystem does not support `O_DIRECT` (tmpfs, for one), pages are
+//!   dropped with `posix_fadvise(POSIX_FADV_DONTNEED)` as they are read
+//! - auto picks buffered or direct by the size of the file, never mmap
+//!
+//! Every strategy produces the same digest as FileHash.
+use crate::error::{HashitError, Result};
+use crate::traits::CalcHash;
+use blake2::{Blake2b, Digest};
+use std::fs;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const MIB: u64 = 1024 * 1024;
+// O_DIRECT requires buffers, offsets and lengths aligned to the block size
+const ALIGN: usize = 4096;
+const BUFFER_SIZE: usize = MIB as usize;
+
+/// Files of at least this size are read with Direct by Auto, and smaller
+/// files with Buffered
+pub const AUTO_DIRECT_SIZE: u64 = 1024 * MIB;
+
+/// How a file is read while it is hashed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReadStrategy {
+    Buffered,
+    /// Only safe for files which are not truncated while they are hashed
+    Mmap,
+    Direct,
+    /// Buffered below AUTO_DIRECT_SIZE, and Direct from it
+    #[default]
+    Auto,
+}
+
+impl FromStr for ReadStrategy {
+    type Err = HashitError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "buffered" => Ok(Self::Buffered),
+            "mmap" => Ok(Self::Mmap),
+            "direct" => Ok(Self::Direct),
+            "auto" => Ok(Self::Auto),
+            _ => Err(HashitError::UnknownReadStrategy(s.to_string())),
+        }
+    }
+}
+
+// open path, reporting a missing file as NotFound
+fn open(path: &Path, options: &fs::OpenOptions) -> Result<fs::File> {
+    options.open(path).map_err(|e| {
+        if e.kind() == ErrorKind::NotFound {
+            HashitError::NotFound {
+                source: e,
+                file: PathBuf::from(path),
+            }
+        } else {
+            e.into()
+        }
+    })
+}
+
+// feed everything read from reader into hasher. advise is told of each
+// range read, as (offset, length).
+pub(crate) fn stream<R, F>(
+    mut reader: R,
+    buffer: &mut [u8],
+    hasher: &mut Blake2b,
+    mut advise: F,
+) -> Result<()>
+where
+    R: Read,
+    F: FnMut(u64, usize),
+{
+    let mut offset = 0;
+    loop {
+        match reader.read(buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => {
+                hasher.update(&buffer[..read]);
+                advise(offset, read);
+                offset += read as u64;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+impl ReadStrategy {
+    /// The strategy used for a file of size bytes, which is only different
+    /// for Auto
+    pub fn resolve(&self, size: u64) -> ReadStrategy {
+        match self {
+            Self::Auto if size < AUTO_DIRECT_SIZE => Self::Buffered,
+            Self::Auto => Self::Direct,
+            strategy => *strategy,
+        }
+    }
+
+    /// Calculate the Blake2b digest of the file at path
+    pub fn digest_file<P>(&self, path: P) -> Result<Vec<u8>>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let mut hasher = Blake2b::new();
+        let size = match self {
+            Self::Auto => fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+            _ => 0,
+        };
+        match self.resolve(size) {
+            Self::Mmap => {
+                let file = open(path, fs::OpenOptions::new().read(true))?;
+                // an empty file cannot be mapped, and has nothing to hash
+                if file.metadata()?.len() > 0 {
+                    // the mapping is only read, and dropped before returning.
+                    // A file truncated underneath us raises SIGBUS, as with
+                    // any other use of mmap.
+                    let map = unsafe { memmap2::Mmap::map(&file)? };
+                    hasher.update(&map[..]);
+                }
+            }
+            Self::Direct => self.read_direct(path, &mut hasher)?,
+            _ => {
+                let file = open(path, fs::OpenOptions::new().read(true))?;
+                let mut buffer = vec![0; BUFFER_SIZE];
+                stream(file, &mut buffer, &mut hasher, |_, _| ())?;
+            }
+        }
+        Ok(hasher.finalize().to_vec())
+    }
+
+    fn read_direct(&self, path: &Path, hasher: &mut Blake2b) -> Result<()> {
+        let mut storage = vec![0; BUFFER_SIZE + ALIGN];
+        let start = storage.as_ptr().align_offset(ALIGN);
+        let buffer = &mut storage[start..start + BUFFER_SIZE];
+        let mut offset = 0;
+        let direct = fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(path);
+        if let Ok(mut file) = direct {
+            loop {
+                match file.read(buffer) {
+                    Ok(0) => return Ok(()),
+                    Ok(read) => {
+                        hasher.update(&buffer[..read]);
+                        offset += read as u64;
+                        // the next read would be unaligned, unless this was
+                        // the end of the file
+                        if read % ALIGN != 0 {
+                            break;
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    // the filesystem refuses O_DIRECT reads
+                    Err(e) if e.raw_os_error() == Some(libc::EINVAL) => break,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+
+        // carry on through the page cache, dropping pages as they are read.
+        // The advice is only advice, so failures are ignored.
+        let mut file = open(path, fs::OpenOptions::new().read(true))?;
+        file.seek(SeekFrom::Start(offset))?;
+        let fd = file.as_raw_fd();
+        unsafe { libc::posix_fadvise(fd, 0, 0, libc::POSIX_FADV_SEQUENTIAL) };
+        stream(&file, buffer, hasher, |start, read| unsafe {
+            libc::posix_fadvise(
+                fd,
+                (offset + start) as libc::off_t,
+                read as libc::off_t,
+                libc::POSIX_FADV_DONTNEED,
+            );
+        })
+    }
+}
+
+/// A CalcHash which reads files with a ReadStrategy. The digests match those
+/// of FileHash.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadHash {
+    strategy: ReadStrategy,
+}
+
+impl ReadHash {
+    pub fn new(strategy: ReadStrategy) -> Self {
+        ReadHash { strategy }
+    }
+
+    pub fn strategy(&self) -> ReadStrategy {
+        self.strategy
+    }
+}
+
+impl CalcHash for ReadHash {
+    fn calc_hash<P>(&self, files: &[P]) -> Result<Vec<u8>>
+    where
+        P: AsRef<str>,
+    {
+        let mut resvec = Vec::new();
+        for f in files {
+            resvec.extend(self.strategy.digest_file(f.as_ref())?);
+        }
+        Ok(resvec)
+    }
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/read_test.rs"]
+mod tests;
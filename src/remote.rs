@@ -0,0 +1,430 @@
+//! Remote
+//!
+//! An optional remote tier for the ArtifactCache, allowing CI machines and
+//! developers to share outputs. The remote speaks a simple HTTP protocol:
+//!
+//! ```text
+//! GET /ac/<key>        the entry recorded under key, 404 if absent
+//! PUT /ac/<key>        record an entry
+//! GET /cas/<digest>    the object with digest, 404 if absent
+//! PUT /cas/<digest>    store an object
+//! ```
+//!
+//! Entries and objects use the same formats as the local cache. Everything
+//! fetched from the remote is verified before it is written locally. As
+//! entries are shared between machines, their outputs must be relative paths
+//! which stay within the working directory, and only permission bits are
+//! restored from them.
+//!
+//! The transport is pluggable via the Transport trait. HttpTransport is a
+//! minimal HTTP/1.1 client; TLS is expected to be terminated by a proxy.
+//! Since a bearer token would otherwise cross the network in the clear,
+//! HttpTransport refuses to send one to anything other than a loopback
+//! address, such as a TLS terminating proxy on the same machine.
+//!
+//! Configuration may be read from the environment (see RemoteCache::from_env).
+//! Without an upload token, the remote is read only, which is the intended
+//! mode for developer machines.
+use crate::cache::{format_entry, parse_entry, validate_key, ArtifactCache};
+use crate::error::{HashitError, Result};
+use crate::utils::blake_hash;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{IpAddr, TcpStream};
+use std::path::{Component, Path};
+use std::time::Duration;
+
+/// Environment variable holding the url of the remote cache
+pub const URL_ENV: &str = "HASHTEST_REMOTE_URL";
+/// Environment variable holding the bearer token used for uploads
+pub const TOKEN_ENV: &str = "HASHTEST_REMOTE_TOKEN";
+/// Environment variable which, when set, disables uploads even with a token
+pub const READ_ONLY_ENV: &str = "HASHTEST_REMOTE_READ_ONLY";
+/// The largest response body accepted from the remote
+pub const MAX_RESPONSE_SIZE: usize = 1024 * 1024 * 1024;
+
+/// Moves bytes to and from a remote store
+pub trait Transport {
+    /// Fetch the resource at path, returning None if it does not exist
+    fn get(&self, path: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Store body at path
+    fn put(&self, path: &str, body: &[u8]) -> Result<()>;
+}
+
+/// A minimal HTTP/1.1 transport
+#[derive(Debug, Clone)]
+pub struct HttpTransport {
+    host: String,
+    port: u16,
+    base: String,
+    token: Option<String>,
+    timeout: Duration,
+}
+
+// whether host names the local machine
+fn is_loopback(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    host.eq_ignore_ascii_case("localhost")
+        || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+impl HttpTransport {
+    /// New up a transport for a url of the form `http://host[:port][/base]`
+    pub fn new(url: &str) -> Result<Self> {
+        let invalid = || HashitError::Remote(format!("unsupported url '{}'", url));
+        let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, base) = match rest.find('/') {
+            Some(idx) => (&rest[..idx], rest[idx..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        let (host, port) = match authority.rfind(':') {
+            Some(idx) => (
+                &authority[..idx],
+                authority[idx + 1..].parse().map_err(|_| invalid())?,
+            ),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(HttpTransport {
+            host: host.to_string(),
+            port,
+            base: base.to_string(),
+            token: None,
+            timeout: Duration::from_secs(30),
+        })
+    }
+
+    /// Send the token as a bearer token with each request. Requests to a host
+    /// other than a loopback address then fail, rather than send the token
+    /// over plain HTTP.
+    pub fn with_token<T>(mut self, token: T) -> Self
+    where
+        T: Into<String>,
+    {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Set the read and write timeout for requests
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn request(&self, method: &str, path: &str, body: &[u8]) -> Result<(u16, Vec<u8>)> {
+        self.try_request(method, path, body).map_err(|e| match e {
+            HashitError::IoError(e) => {
+                HashitError::Remote(format!("{}:{} - {}", self.host, self.port, e))
+            }
+            e => e,
+        })
+    }
+
+    fn try_request(&self, method: &str, path: &str, body: &[u8]) -> Result<(u16, Vec<u8>)> {
+        if self.token.is_some() && !is_loopback(&self.host) {
+            return Err(HashitError::Remote(format!(
+                "refusing to send a token over plain http to {}, use a local TLS proxy",
+                self.host
+            )));
+        }
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port))?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let mut request = format!(
+            "{} {}{} HTTP/1.1\r\nHost: {}:{}\r\nConnection: close\r\nContent-Length: {}\r\n",
+            method,
+            self.base,
+            path,
+            self.host,
+            self.port,
+            body.len()
+        );
+        if let Some(token) = &self.token {
+            request.push_str(&format!("Authorization: Bearer {}\r\n", token));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+        stream.write_all(body)?;
+        read_response(BufReader::new(stream))
+    }
+}
+
+fn malformed(what: &str) -> HashitError {
+    HashitError::Remote(format!("malformed response: {}", what))
+}
+
+fn too_large() -> HashitError {
+    HashitError::Remote(format!("response larger than {} bytes", MAX_RESPONSE_SIZE))
+}
+
+// read exactly length bytes onto the end of body, growing it as data arrives
+// rather than trusting length up front
+fn read_body<R: Read>(reader: &mut R, body: &mut Vec<u8>, length: usize) -> Result<()> {
+    if length > MAX_RESPONSE_SIZE - body.len() {
+        return Err(too_large());
+    }
+    let read = reader.take(length as u64).read_to_end(body)?;
+    if read != length {
+        return Err(malformed("truncated body"));
+    }
+    Ok(())
+}
+
+// Parse an HTTP/1.1 response, returning the status and body. Handles bodies
+// delimited by Content-Length, chunked encoding, or the end of the stream.
+fn read_response<R: BufRead>(mut reader: R) -> Result<(u16, Vec<u8>)> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| malformed("status line"))?;
+
+    let mut content_length = None;
+    let mut chunked = false;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(malformed("headers"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = Some(value.parse::<usize>().map_err(|_| malformed("length"))?);
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            }
+        }
+    }
+
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let size = line.trim().split(';').next().unwrap_or("");
+            let size = usize::from_str_radix(size, 16).map_err(|_| malformed("chunk"))?;
+            if size == 0 {
+                break;
+            }
+            read_body(&mut reader, &mut body, size)?;
+            // trailing CRLF
+            line.clear();
+            reader.read_line(&mut line)?;
+        }
+    } else if let Some(length) = content_length {
+        read_body(&mut reader, &mut body, length)?;
+    } else {
+        reader
+            .take(MAX_RESPONSE_SIZE as u64 + 1)
+            .read_to_end(&mut body)?;
+        if body.len() > MAX_RESPONSE_SIZE {
+            return Err(too_large());
+        }
+    }
+    Ok((status, body))
+}
+
+impl Transport for HttpTransport {
+    fn get(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        match self.request("GET", path, &[])? {
+            (200, body) => Ok(Some(body)),
+            (404, _) => Ok(None),
+            (status, _) => Err(HashitError::Remote(format!(
+                "GET {} returned {}",
+                path, status
+            ))),
+        }
+    }
+
+    fn put(&self, path: &str, body: &[u8]) -> Result<()> {
+        match self.request("PUT", path, body)? {
+            (status, _) if (200..300).contains(&status) => Ok(()),
+            (status, _) => Err(HashitError::Remote(format!(
+                "PUT {} returned {}",
+                path, status
+            ))),
+        }
+    }
+}
+
+// whether path may be shared with other machines: relative, and made up of
+// nothing but names, so that it cannot escape the working directory
+fn is_portable(path: &Path) -> bool {
+    path.components().next().is_some()
+        && path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// The local ArtifactCache, backed by a shared remote
+#[derive(Debug)]
+pub struct RemoteCache<T> {
+    local: ArtifactCache,
+    transport: T,
+    read_only: bool,
+}
+
+impl RemoteCache<HttpTransport> {
+    /// Configure the remote from the environment. Returns None if
+    /// HASHTEST_REMOTE_URL is not set. Uploads are only enabled when
+    /// HASHTEST_REMOTE_TOKEN is set, and HASHTEST_REMOTE_READ_ONLY is not.
+    pub fn from_env(local: ArtifactCache) -> Result<Option<Self>> {
+        let url = match std::env::var(URL_ENV) {
+            Ok(url) => url,
+            Err(_) => return Ok(None),
+        };
+        let mut transport = HttpTransport::new(&url)?;
+        let token = std::env::var(TOKEN_ENV).ok();
+        let read_only = token.is_none() || std::env::var_os(READ_ONLY_ENV).is_some();
+        if let Some(token) = token {
+            transport = transport.with_token(token);
+        }
+        Ok(Some(
+            RemoteCache::new(local, transport).with_read_only(read_only),
+        ))
+    }
+}
+
+impl<T: Transport> RemoteCache<T> {
+    pub fn new(local: ArtifactCache, transport: T) -> Self {
+        RemoteCache {
+            local,
+            transport,
+            read_only: false,
+        }
+    }
+
+    /// Never upload to the remote
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn local(&self) -> &ArtifactCache {
+        &self.local
+    }
+
+    // pull the entry for key, along with any objects we lack, into the local
+    // cache. Returns false if the remote does not have everything.
+    fn fetch(&self, key: &str) -> Result<bool> {
+        let entry = match self.transport.get(&format!("/ac/{}", key))? {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        let mut artifacts =
+            parse_entry(key, &entry).map_err(|e| HashitError::Remote(e.to_string()))?;
+        // refuse to let the remote direct writes outside of the working
+        // directory, or set anything beyond permission bits
+        if let Some(artifact) = artifacts.iter().find(|a| !is_portable(&a.path)) {
+            return Err(HashitError::Remote(format!(
+                "entry {} refers to {}, which is not a relative path",
+                key,
+                artifact.path.display()
+            )));
+        }
+        for artifact in &mut artifacts {
+            artifact.mode &= 0o777;
+        }
+        for artifact in &artifacts {
+            if self.local.get_object(&artifact.digest)?.is_some() {
+                continue;
+            }
+            let object = match self.transport.get(&format!("/cas/{}", artifact.digest))? {
+                Some(object) => object,
+                None => return Ok(false),
+            };
+            if hex::encode(blake_hash(&object)) != artifact.digest {
+                return Err(HashitError::Remote(format!(
+                    "object {} failed verification",
+                    artifact.digest
+                )));
+            }
+            self.local.put_object(&object)?;
+        }
+        self.local.put_entry(key, &artifacts)?;
+        Ok(true)
+    }
+
+    /// Restore the outputs saved under key, consulting the remote when the
+    /// local cache misses. Failing to reach the remote is treated as a miss.
+    pub fn restore(&self, key: &str) -> Result<bool> {
+        validate_key(key)?;
+        if self.local.restore(key)? {
+            return Ok(true);
+        }
+        match self.fetch(key) {
+            Ok(true) => self.local.restore(key),
+            Ok(false) | Err(HashitError::Remote(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Save the outputs under key locally and, unless read only, upload them.
+    /// The local save has completed by the time an upload error is returned.
+    pub fn save<P>(&self, key: &str, outputs: &[P]) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        self.local.save(key, outputs)?;
+        if self.read_only {
+            return Ok(());
+        }
+        // the entry may have been evicted straight away if it is too large
+        let artifacts = match self.local.entry(key)? {
+            Some(artifacts) => artifacts,
+            None => return Ok(()),
+        };
+        // other machines would refuse the entry
+        if let Some(artifact) = artifacts.iter().find(|a| !is_portable(&a.path)) {
+            return Err(HashitError::Remote(format!(
+                "not uploading {}, as {} is not a relative path",
+                key,
+                artifact.path.display()
+            )));
+        }
+        for artifact in &artifacts {
+            if let Some(object) = self.local.get_object(&artifact.digest)? {
+                self.transport
+                    .put(&format!("/cas/{}", artifact.digest), &object)?;
+            }
+        }
+        // objects go first, so that the entry never refers to missing objects
+        self.transport
+            .put(&format!("/ac/{}", key), format_entry(&artifacts).as_bytes())
+    }
+
+    /// Restore the outputs for key if present locally or remotely. Otherwise
+    /// invoke run, and save the outputs once it succeeds. Upload failures are
+    /// ignored, as the outputs are already in place. Returns whether the
+    /// outputs were restored.
+    pub fn restore_or_run<P, F>(&self, key: &str, outputs: &[P], run: F) -> Result<bool>
+    where
+        P: AsRef<Path>,
+        F: FnOnce() -> Result<()>,
+    {
+        if self.restore(key)? {
+            return Ok(true);
+        }
+        run()?;
+        match self.save(key, outputs) {
+            Ok(()) | Err(HashitError::Remote(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/remote_test.rs"]
+mod tests;
@@ -0,0 +1,92 @@
+//! Report
+//!
+//! Describes why Hashit considers a set of inputs to have changed.
+use std::fmt;
+
+/// A single reason for considering the inputs changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// No hash has been recorded yet
+    New,
+    /// The inputs differ in number or order from those recorded, so the
+    /// change cannot be attributed to individual inputs
+    InputSet,
+    /// The contents of an input differ from those recorded
+    Input(String),
+    /// An entry within an input, such as a file within an archive, differs
+    /// from that recorded
+    Entry(String, String),
+    /// A component of the fingerprint differs from that recorded
+    Fingerprint(String),
+    /// A recorded output no longer exists
+    OutputMissing(String),
+    /// The contents of a recorded output differ from those recorded
+    OutputModified(String),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::New => write!(f, "no previous hash"),
+            Self::InputSet => write!(f, "set of inputs changed"),
+            Self::Input(name) => write!(f, "input changed: {}", name),
+            Self::Entry(name, entry) => write!(f, "entry changed: {} ({})", name, entry),
+            Self::Fingerprint(name) => write!(f, "fingerprint changed: {}", name),
+            Self::OutputMissing(name) => write!(f, "output missing: {}", name),
+            Self::OutputModified(name) => write!(f, "output modified: {}", name),
+        }
+    }
+}
+
+/// The outcome of comparing the inputs (and any recorded outputs) against
+/// the stored hash.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    hash: Vec<u8>,
+    changes: Vec<Change>,
+}
+
+impl Report {
+    /// New up a report for the freshly calculated hash of the inputs
+    pub fn new(hash: Vec<u8>) -> Self {
+        Report {
+            hash,
+            changes: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, change: Change) {
+        self.changes.push(change);
+    }
+
+    /// Whether anything has changed
+    pub fn has_changed(&self) -> bool {
+        !self.changes.is_empty()
+    }
+
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    /// The hash of the inputs, suitable for passing to commit
+    pub fn hash(&self) -> &[u8] {
+        &self.hash
+    }
+
+    /// Consume the report, returning the hash of the inputs
+    pub fn into_hash(self) -> Vec<u8> {
+        self.hash
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.changes.is_empty() {
+            return writeln!(f, "unchanged");
+        }
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+//! Sample
+//!
+//! Hashes huge files quickly by reading only part of them: the size, the
+//! first and last few MiB, and a number of evenly spaced blocks in between.
+//! A sampled digest is probabilistic. It catches appends, truncation and
+//! most rewrites, but misses a change which happens to fall between samples.
+//!
+//! SampledHash samples files above a size threshold, leaving smaller files
+//! to another CalcHash. In strict mode every file goes to the fallback, which
+//! upgrades the digests of sampled files to full ones. As the two kinds of
+//! digest differ, switching between modes counts as a change.
+//!
+//! When given somewhere to keep manifests, SampledHash records each sampled
+//! digest, marked as probabilistic, along with how it was sampled:
+//!
+//! ```text
+//! probabilistic\t<size>\t<head>\t<tail>\t<blocks>\t<block size>
+//! ```
+use crate::error::{HashitError, Result};
+use crate::traits::CalcHash;
+use blake2::{Blake2b, Digest};
+use std::fmt;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+const MIB: u64 = 1024 * 1024;
+
+/// Decides which parts of a file are read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sampler {
+    head: u64,
+    tail: u64,
+    blocks: u64,
+    block_size: u64,
+}
+
+impl Default for Sampler {
+    /// The first and last MiB, and 16 blocks of 64 KiB
+    fn default() -> Self {
+        Self::new(MIB, MIB, 16, 64 * 1024)
+    }
+}
+
+/// How a sampled digest was calculated, as recorded in its manifest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    pub size: u64,
+    pub sampler: Sampler,
+}
+
+impl Sample {
+    /// Parse a manifest, as written by Display
+    pub fn parse(contents: &str) -> Option<Self> {
+        let mut fields = contents.trim_end().split('\t');
+        if fields.next()? != "probabilistic" {
+            return None;
+        }
+        let mut next = || fields.next()?.parse().ok();
+        Some(Sample {
+            size: next()?,
+            sampler: Sampler::new(next()?, next()?, next()?, next()?),
+        })
+    }
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sampler = &self.sampler;
+        writeln!(
+            f,
+            "probabilistic\t{}\t{}\t{}\t{}\t{}",
+            self.size, sampler.head, sampler.tail, sampler.blocks, sampler.block_size
+        )
+    }
+}
+
+impl Sampler {
+    /// Read head bytes from the start, tail bytes from the end, and blocks
+    /// blocks of block_size bytes evenly spaced in between
+    pub fn new(head: u64, tail: u64, blocks: u64, block_size: u64) -> Self {
+        Sampler {
+            head,
+            tail,
+            blocks,
+            block_size,
+        }
+    }
+
+    /// The ranges read from a file of size bytes, as (offset, length). They
+    /// may overlap in a small file.
+    pub fn ranges(&self, size: u64) -> Vec<(u64, u64)> {
+        let mut ranges = vec![(0, self.head.min(size))];
+        let middle_start = self.head.min(size);
+        let middle_end = size.saturating_sub(self.tail).max(middle_start);
+        let block = self.block_size.min(middle_end - middle_start);
+        let span = (middle_end - middle_start - block) as u128;
+        for idx in 1..=self.blocks {
+            let offset = span * idx as u128 / (self.blocks as u128 + 1);
+            ranges.push((middle_start + offset as u64, block));
+        }
+        ranges.push((middle_end, size - middle_end));
+        ranges
+    }
+
+    /// Calculate the sampled digest of the file at path
+    pub fn digest_file<P>(&self, path: P) -> Result<(Vec<u8>, Sample)>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let mut file = fs::File::open(path).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                HashitError::NotFound {
+                    source: e,
+                    file: PathBuf::from(path),
+                }
+            } else {
+                e.into()
+            }
+        })?;
+        let size = file.metadata()?.len();
+        let sample = Sample {
+            size,
+            sampler: *self,
+        };
+        // the manifest covers the size and how the file was sampled
+        let mut hasher = Blake2b::new();
+        hasher.update(sample.to_string().as_bytes());
+        let mut buffer = Vec::new();
+        for (offset, len) in self.ranges(size) {
+            buffer.resize(len as usize, 0);
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut buffer)?;
+            hasher.update(&buffer);
+        }
+        Ok((hasher.finalize().to_vec(), sample))
+    }
+}
+
+/// A CalcHash which samples files above a size threshold. Smaller files,
+/// directories, and every file in strict mode, are hashed by the fallback.
+#[derive(Debug, Clone)]
+pub struct SampledHash<H> {
+    fallback: H,
+    sampler: Sampler,
+    threshold: u64,
+    strict: bool,
+    manifests: Option<PathBuf>,
+}
+
+impl<H> SampledHash<H> {
+    /// New up a SampledHash which samples files above 256 MiB with the
+    /// default Sampler
+    pub fn new(fallback: H) -> Self {
+        SampledHash {
+            fallback,
+            sampler: Sampler::default(),
+            threshold: 256 * MIB,
+            strict: false,
+            manifests: None,
+        }
+    }
+
+    pub fn with_sampler(mut self, sampler: Sampler) -> Self {
+        self.sampler = sampler;
+        self
+    }
+
+    /// Sample files larger than threshold bytes
+    pub fn with_threshold(mut self, threshold: u64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Hash every file in full, via the fallback
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Keep a manifest for each sampled digest within dir, named by the
+    /// digest
+    pub fn with_manifests<P>(mut self, dir: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.manifests = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// How digest was sampled, provided that it is a sampled digest whose
+    /// manifest was kept
+    pub fn sample(&self, digest: &[u8]) -> Option<Sample> {
+        let path = self.manifests.as_ref()?.join(hex::encode(digest));
+        Sample::parse(&fs::read_to_string(path).ok()?)
+    }
+
+    // whether the file at path is sampled rather than hashed in full
+    fn sampled(&self, path: &str) -> bool {
+        !self.strict
+            && fs::metadata(path)
+                .is_ok_and(|metadata| metadata.is_file() && metadata.len() > self.threshold)
+    }
+
+    fn store(&self, digest: &[u8], sample: &Sample) -> Result<()> {
+        if let Some(dir) = &self.manifests {
+            let path = dir.join(hex::encode(digest));
+            if !path.exists() {
+                fs::create_dir_all(dir)?;
+                fs::write(path, sample.to_string())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<H: CalcHash> CalcHash for SampledHash<H> {
+    fn calc_hash<P>(&self, files: &[P]) -> Result<Vec<u8>>
+    where
+        P: AsRef<str>,
+    {
+        let mut resvec = Vec::new();
+        for f in files {
+            if self.sampled(f.as_ref()) {
+                let (digest, sample) = self.sampler.digest_file(f.as_ref())?;
+                self.store(&digest, &sample)?;
+                resvec.extend(digest);
+            } else {
+                resvec.extend(self.fallback.calc_hash(&[f])?);
+            }
+        }
+        Ok(resvec)
+    }
+
+    fn changed_entries(&self, input: &str, old: &[u8], new: &[u8]) -> Vec<String> {
+        match (self.sample(old), self.sample(new)) {
+            (Some(old), Some(new)) if old.size != new.size => {
+                vec![format!("size {} -> {}", old.size, new.size)]
+            }
+            (Some(_), Some(_)) => Vec::new(),
+            (None, None) => self.fallback.changed_entries(input, old, new),
+            // the file crossed the threshold, or strict mode was switched
+            (Some(old), None) => match fs::metadata(input).map(|m| m.len()) {
+                Ok(size) if size != old.size => vec![format!(
+                    "sampled -> full hash, size {} -> {}",
+                    old.size, size
+                )],
+                _ => vec![String::from("sampled -> full hash")],
+            },
+            (None, Some(new)) => vec![format!("full hash -> sampled, size {}", new.size)],
+        }
+    }
+
+    fn committed(&self, output: &str, hash: &[u8]) -> Result<()> {
+        self.fallback.committed(output, hash)
+    }
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/sample_test.rs"]
+mod tests;
@@ -0,0 +1,144 @@
+//! Semantic
+//!
+//! Hashes structured files by meaning rather than by bytes. JSON, TOML and
+//! YAML files are parsed and hashed in a canonical form, with keys sorted and
+//! formatting discarded, so that reformatting a file or reordering its keys
+//! does not count as a change. Comments are discarded along with formatting.
+//!
+//! SemanticHash selects the format by extension, falling back to another
+//! CalcHash for any other file:
+//!
+//! ```
+//! use hashtest::file::FileHash;
+//! use hashtest::semantic::SemanticHash;
+//! use hashtest::{Hashit, HtFile};
+//!
+//! let hashit = Hashit::from_parts(HtFile::new(), SemanticHash::new(FileHash {}));
+//! ```
+use crate::error::{HashitError, Result};
+use crate::traits::CalcHash;
+use crate::utils::{blake_hash, read_file};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The structured formats understood by SemanticHash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    /// Parse contents, returning a canonical serialization. Every format is
+    /// converted to a JSON value, whose objects keep their keys sorted.
+    pub fn canonicalize(&self, contents: &[u8], path: &Path) -> Result<Vec<u8>> {
+        let failed = |message: String| HashitError::Parse {
+            file: path.to_path_buf(),
+            message,
+        };
+        let value: Value = match self {
+            Self::Json => serde_json::from_slice(contents).map_err(|e| failed(e.to_string()))?,
+            Self::Toml => {
+                let contents = std::str::from_utf8(contents).map_err(|e| failed(e.to_string()))?;
+                let value: toml::Value =
+                    toml::from_str(contents).map_err(|e| failed(e.to_string()))?;
+                serde_json::to_value(value).map_err(|e| failed(e.to_string()))?
+            }
+            Self::Yaml => {
+                let value: serde_yaml::Value =
+                    serde_yaml::from_slice(contents).map_err(|e| failed(e.to_string()))?;
+                serde_json::to_value(value).map_err(|e| failed(e.to_string()))?
+            }
+        };
+        serde_json::to_vec(&value).map_err(|e| failed(e.to_string()))
+    }
+}
+
+/// A CalcHash which hashes structured files in canonical form, selecting the
+/// format by extension. Other files are hashed by the fallback.
+#[derive(Debug, Clone)]
+pub struct SemanticHash<H> {
+    fallback: H,
+    formats: HashMap<String, Format>,
+}
+
+impl<H> SemanticHash<H> {
+    /// New up a SemanticHash which handles the json, toml, yaml and yml
+    /// extensions
+    pub fn new(fallback: H) -> Self {
+        let formats = [
+            ("json", Format::Json),
+            ("toml", Format::Toml),
+            ("yaml", Format::Yaml),
+            ("yml", Format::Yaml),
+        ];
+        SemanticHash {
+            fallback,
+            formats: formats
+                .iter()
+                .map(|(ext, format)| (ext.to_string(), *format))
+                .collect(),
+        }
+    }
+
+    /// Handle files with extension as format, replacing any existing mapping
+    pub fn with_format<E>(mut self, extension: E, format: Format) -> Self
+    where
+        E: Into<String>,
+    {
+        self.formats.insert(extension.into(), format);
+        self
+    }
+
+    /// Stop handling files with extension, leaving them to the fallback
+    pub fn without_format(mut self, extension: &str) -> Self {
+        self.formats.remove(extension);
+        self
+    }
+
+    /// The format used for path, if any
+    pub fn format<P>(&self, path: P) -> Option<Format>
+    where
+        P: AsRef<Path>,
+    {
+        let ext = path.as_ref().extension()?.to_str()?;
+        self.formats.get(&ext.to_lowercase()).copied()
+    }
+}
+
+impl<H: CalcHash> CalcHash for SemanticHash<H> {
+    fn calc_hash<P>(&self, files: &[P]) -> Result<Vec<u8>>
+    where
+        P: AsRef<str>,
+    {
+        let mut resvec = Vec::new();
+        for f in files {
+            let path = Path::new(f.as_ref());
+            match self.format(path) {
+                Some(format) => {
+                    let canonical = format.canonicalize(&read_file(path)?, path)?;
+                    resvec.extend(blake_hash(&canonical));
+                }
+                None => resvec.extend(self.fallback.calc_hash(&[f])?),
+            }
+        }
+        Ok(resvec)
+    }
+
+    fn changed_entries(&self, input: &str, old: &[u8], new: &[u8]) -> Vec<String> {
+        match self.format(input) {
+            Some(_) => Vec::new(),
+            None => self.fallback.changed_entries(input, old, new),
+        }
+    }
+
+    fn committed(&self, output: &str, hash: &[u8]) -> Result<()> {
+        self.fallback.committed(output, hash)
+    }
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/semantic_test.rs"]
+mod tests;
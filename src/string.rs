@@ -3,6 +3,12 @@
 //!
 //! NB: This module only gets compiled into the library for tests.
 //!
+#![allow(
+    clippy::derivable_impls,
+    clippy::let_and_return,
+    clippy::unwrap_or_default,
+    clippy::unnecessary_cast
+)]
 use crate::traits::{CalcHash, FetchCachedHash, Open, OpenMut};
 use crate::utils::blake_hash;
 use crate::HashitError;
@@ -42,4 +42,17 @@ pub trait CalcHash {
     fn calc_hash<R>(&self, inputs: &[R]) -> HResult<Vec<u8>>
     where
         R: AsRef<str>;
+
+    /// Given the old and new digests of input, name the parts of it which
+    /// changed, for hashers which are able to tell (such as ArchiveHash).
+    fn changed_entries(&self, _input: &str, _old: &[u8], _new: &[u8]) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Told that hash, as calculated by calc_hash, has been stored in the
+    /// stamp output, for hashers which keep state about the digests they
+    /// produce (such as AppendHash).
+    fn committed(&self, _output: &str, _hash: &[u8]) -> HResult<()> {
+        Ok(())
+    }
 }
@@ -0,0 +1,192 @@
+use super::*;
+use crate::file::FileHash;
+use crate::{Change, Hashit, HtFile};
+use std::io::Write;
+
+fn append(path: &Path, contents: &[u8]) {
+    let mut file = fs::OpenOptions::new().append(true).open(path).unwrap();
+    file.write_all(contents).unwrap();
+}
+
+#[test]
+fn appending_resumes_from_the_last_block() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("app.log");
+    let stateful = AppendHash::new(FileHash {})
+        .with_block_size(4)
+        .with_state(dir.path().join("state"));
+    let stateless = AppendHash::new(FileHash {}).with_block_size(4);
+    let digest =
+        |hasher: &AppendHash<FileHash>| hasher.calc_hash(&[log.to_str().unwrap()]).unwrap();
+
+    fs::write(&log, "first\n").unwrap();
+    let first = digest(&stateful);
+    append(&log, b"second\n");
+    let record = stateful.record(&log).unwrap();
+    assert_eq!(record.extends, vec![first]);
+    assert_eq!(record.blocks.len(), 4);
+    assert_eq!(digest(&stateful), digest(&stateless));
+
+    // a file which changed in its last recorded block is hashed in full
+    let mut contents = fs::read(&log).unwrap();
+    contents[12] = b'D';
+    contents.extend(b"third\n");
+    fs::write(&log, &contents).unwrap();
+    let record = stateful.record(&log).unwrap();
+    assert!(record.extends.is_empty());
+    assert_eq!(digest(&stateful), digest(&stateless));
+}
+
+#[test]
+fn record_round_trips() {
+    let record = Record {
+        size: 6,
+        mtime: 1_600_000_000_000_000_000,
+        inode: 42,
+        block_size: 4,
+        extends: vec![vec![1; 64], vec![4; 64]],
+        blocks: vec![vec![2; 64], vec![3; 64]],
+    };
+    assert_eq!(Record::parse(&record.to_string()), Some(record.clone()));
+    let full = Record {
+        extends: Vec::new(),
+        ..record
+    };
+    assert_eq!(Record::parse(&full.to_string()), Some(full));
+}
+
+#[test]
+fn report_distinguishes_appended_from_modified() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("app.log");
+    let stamp = dir.path().join("stamp");
+    let name = log.to_string_lossy().to_string();
+    let mut hashit = Hashit::from_parts(
+        HtFile::new(),
+        AppendHash::new(FileHash {})
+            .with_block_size(4)
+            .with_state(dir.path().join("state")),
+    );
+
+    fs::write(&log, "first\n").unwrap();
+    assert!(hashit.has_changed(&[&log], &stamp).unwrap());
+    append(&log, b"second\n");
+    let report = hashit.report(&[&log], &stamp).unwrap();
+    assert_eq!(
+        report.changes(),
+        &[
+            Change::Input(name.clone()),
+            Change::Entry(name.clone(), "appended 7 bytes".to_string()),
+        ]
+    );
+    hashit.commit(&stamp, report.hash()).unwrap();
+
+    fs::write(&log, "rewritten\n").unwrap();
+    assert_eq!(
+        hashit.report(&[&log], &stamp).unwrap().changes(),
+        &[
+            Change::Input(name.clone()),
+            Change::Entry(name, "modified".to_string()),
+        ]
+    );
+}
+
+// a check which is never committed does not hide the append from the stamp
+#[test]
+fn report_spans_uncommitted_checks() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("app.log");
+    let stamp = dir.path().join("stamp");
+    let name = log.to_string_lossy().to_string();
+    let mut hashit = Hashit::from_parts(
+        HtFile::new(),
+        AppendHash::new(FileHash {})
+            .with_block_size(4)
+            .with_state(dir.path().join("state")),
+    );
+
+    fs::write(&log, "first\n").unwrap();
+    assert!(hashit.has_changed(&[&log], &stamp).unwrap());
+    append(&log, b"second\n");
+    assert!(hashit.report(&[&log], &stamp).unwrap().has_changed());
+    append(&log, b"third\n");
+    assert_eq!(
+        hashit.report(&[&log], &stamp).unwrap().changes(),
+        &[
+            Change::Input(name.clone()),
+            Change::Entry(name, "appended 13 bytes".to_string()),
+        ]
+    );
+}
+
+// a rewrite before the last block is caught, since every block is verified
+#[test]
+fn rewrite_of_an_earlier_block_is_hashed_in_full() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("app.log");
+    let stateful = AppendHash::new(FileHash {})
+        .with_block_size(4)
+        .with_state(dir.path().join("state"));
+    let stateless = AppendHash::new(FileHash {}).with_block_size(4);
+    let digest =
+        |hasher: &AppendHash<FileHash>| hasher.calc_hash(&[log.to_str().unwrap()]).unwrap();
+
+    fs::write(&log, "0123456789abcdef\n").unwrap();
+    digest(&stateful);
+    let mut contents = fs::read(&log).unwrap();
+    contents[1] = b'X';
+    contents.extend(b"more\n");
+    fs::write(&log, &contents).unwrap();
+    assert!(stateful.record(&log).unwrap().extends.is_empty());
+    assert_eq!(digest(&stateful), digest(&stateless));
+}
+
+// only the records a stamp refers to, and the latest, are kept
+#[test]
+fn records_are_pruned() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("app.log");
+    let stamp = dir.path().join("stamp");
+    let state = dir.path().join("state");
+    let mut hashit = Hashit::from_parts(
+        HtFile::new(),
+        AppendHash::new(FileHash {})
+            .with_block_size(4)
+            .with_state(&state),
+    );
+    let records = || fs::read_dir(state.join("records")).unwrap().count();
+
+    fs::write(&log, "first\n").unwrap();
+    assert!(hashit.has_changed(&[&log], &stamp).unwrap());
+    for line in ["second\n", "third\n", "fourth\n"] {
+        append(&log, line.as_bytes());
+        hashit.report(&[&log], &stamp).unwrap();
+    }
+    assert_eq!(records(), 2);
+    assert!(hashit.has_changed(&[&log], &stamp).unwrap());
+    assert_eq!(records(), 1);
+}
+
+// sampling is lossy: a rewrite of a block outside the sample, in a file
+// which also grew, is taken for an append
+#[test]
+fn sampled_verification_misses_unsampled_rewrites() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("app.log");
+    let sampled = AppendHash::new(FileHash {})
+        .with_block_size(4)
+        .with_verified_blocks(0)
+        .with_state(dir.path().join("state"));
+    let stateless = AppendHash::new(FileHash {}).with_block_size(4);
+    let digest =
+        |hasher: &AppendHash<FileHash>| hasher.calc_hash(&[log.to_str().unwrap()]).unwrap();
+
+    fs::write(&log, "0123456789abcdef\n").unwrap();
+    digest(&sampled);
+    let mut contents = fs::read(&log).unwrap();
+    contents[1] = b'X';
+    contents.extend(b"more\n");
+    fs::write(&log, &contents).unwrap();
+    assert!(!sampled.record(&log).unwrap().extends.is_empty());
+    assert_ne!(digest(&sampled), digest(&stateless));
+}
@@ -0,0 +1,145 @@
+use super::*;
+use crate::file::FileHash;
+use crate::report::Change;
+use crate::{Hashit, HtFile};
+use std::io::Write;
+
+// write a tar.gz holding files, with every entry stamped with mtime
+fn write_tar_gz(path: &Path, files: &[(&str, &str)], mtime: u64, level: u32) {
+    let encoder = flate2::write::GzEncoder::new(
+        fs::File::create(path).unwrap(),
+        flate2::Compression::new(level),
+    );
+    let mut builder = tar::Builder::new(encoder);
+    for (name, contents) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, name, contents.as_bytes())
+            .unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap();
+}
+
+#[test]
+fn repacked_tar_gz_has_same_digest() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = dir.path().join("first.tar.gz");
+    let second = dir.path().join("second.tgz");
+    write_tar_gz(&first, &[("b.txt", "b"), ("a.txt", "a")], 1, 1);
+    write_tar_gz(&second, &[("a.txt", "a"), ("b.txt", "b")], 2, 9);
+    assert_ne!(fs::read(&first).unwrap(), fs::read(&second).unwrap());
+
+    let hasher = ArchiveHash::new(FileHash {});
+    let hash = |path: &Path| hasher.calc_hash(&[path.to_string_lossy()]).unwrap();
+    assert_eq!(hash(&first), hash(&second));
+
+    let manifest = Manifest::from_archive(&first, ArchiveKind::TarGz).unwrap();
+    assert_eq!(Manifest::parse(&manifest.to_string()), Some(manifest));
+}
+
+#[test]
+fn zip_digest_matches_equivalent_tar() {
+    let dir = tempfile::tempdir().unwrap();
+    let tar = dir.path().join("files.tar.gz");
+    let zip = dir.path().join("files.zip");
+    write_tar_gz(&tar, &[("a.txt", "a")], 1, 6);
+    let mut writer = zip::ZipWriter::new(fs::File::create(&zip).unwrap());
+    let options = zip::write::FileOptions::default().unix_permissions(0o644);
+    writer.add_directory("empty/", options).unwrap();
+    writer.start_file("a.txt", options).unwrap();
+    writer.write_all(b"a").unwrap();
+    writer.finish().unwrap();
+
+    let digest = |path: &Path, kind| Manifest::from_archive(path, kind).unwrap().digest();
+    assert_eq!(
+        digest(&tar, ArchiveKind::TarGz),
+        digest(&zip, ArchiveKind::Zip)
+    );
+    assert!(matches!(
+        Manifest::from_archive(&tar, ArchiveKind::Zip),
+        Err(HashitError::Parse { .. })
+    ));
+}
+
+#[test]
+fn report_names_changed_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let archive = dir.path().join("assets.tar.gz");
+    let stamp = dir.path().join("stamp");
+    let hasher = || ArchiveHash::new(FileHash {}).with_manifests(dir.path().join("manifests"));
+
+    write_tar_gz(&archive, &[("a.txt", "a"), ("b.txt", "b")], 1, 6);
+    let mut hashit = Hashit::from_parts(HtFile::new(), hasher());
+    assert!(hashit.has_changed(&[&archive], &stamp).unwrap());
+
+    write_tar_gz(
+        &archive,
+        &[("a.txt", "a"), ("b.txt", "changed"), ("c.txt", "c")],
+        2,
+        6,
+    );
+    let mut hashit = Hashit::from_parts(HtFile::new(), hasher());
+    let name = archive.to_string_lossy().to_string();
+    assert_eq!(
+        hashit.report(&[&archive], &stamp).unwrap().changes(),
+        &[
+            Change::Input(name.clone()),
+            Change::Entry(name.clone(), "b.txt".to_string()),
+            Change::Entry(name, "c.txt".to_string()),
+        ]
+    );
+}
+
+// append an entry of kind to builder, with contents or a link target
+fn append_entry(
+    builder: &mut tar::Builder<fs::File>,
+    kind: tar::EntryType,
+    name: &str,
+    contents: &str,
+) {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(kind);
+    header.set_mode(0o644);
+    if kind.is_file() {
+        header.set_size(contents.len() as u64);
+        header.set_path(name).unwrap();
+        header.set_cksum();
+        builder.append(&header, contents.as_bytes()).unwrap();
+    } else {
+        header.set_size(0);
+        builder.append_link(&mut header, name, contents).unwrap();
+    }
+}
+
+// names are length prefixed, so a newline in one can not forge entries, and
+// the kind of each entry is part of the digest
+#[test]
+fn manifest_resists_forged_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let tar = |name: &str, entries: &[(tar::EntryType, &str, &str)]| {
+        let path = dir.path().join(name);
+        let mut builder = tar::Builder::new(fs::File::create(&path).unwrap());
+        for (kind, name, contents) in entries {
+            append_entry(&mut builder, *kind, name, contents);
+        }
+        builder.finish().unwrap();
+        Manifest::from_archive(&path, ArchiveKind::Tar).unwrap()
+    };
+    let regular = tar::EntryType::Regular;
+
+    let forged = tar("forged.tar", &[(regular, "a\nfake", "x")]);
+    assert_eq!(forged.entries().len(), 1);
+    assert_eq!(forged.entries()[0].name, "a\nfake");
+    assert_eq!(Manifest::parse(&forged.to_string()), Some(forged));
+
+    let file = tar("file.tar", &[(regular, "a", "target")]);
+    let symlink = tar("symlink.tar", &[(tar::EntryType::Symlink, "a", "target")]);
+    let hard_link = tar("link.tar", &[(tar::EntryType::Link, "a", "target")]);
+    assert_eq!(symlink.entries()[0].kind, EntryKind::Symlink);
+    assert_ne!(file.digest(), symlink.digest());
+    assert_ne!(symlink.digest(), hard_link.digest());
+}
@@ -0,0 +1,149 @@
+use super::*;
+use crate::file::FileHash;
+use crate::Hashit;
+
+const SECRET: [u8; 32] = [7u8; 32];
+
+#[test]
+fn hmac_stamp_round_trips_and_detects_tampering() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input");
+    let stamp = dir.path().join("stamp");
+    fs::write(&input, "input").unwrap();
+
+    let store = || AuthFile::new(Authenticator::hmac("secret").unwrap());
+    let mut hashit = Hashit::from_parts(store(), FileHash {});
+    assert!(hashit.has_changed(&[&input], &stamp).unwrap());
+    let mut hashit = Hashit::from_parts(store(), FileHash {});
+    assert!(!hashit.has_changed(&[&input], &stamp).unwrap());
+
+    // flipping a byte of the hash invalidates the tag
+    let mut contents = fs::read(&stamp).unwrap();
+    contents[0] ^= 1;
+    fs::write(&stamp, &contents).unwrap();
+    let mut hashit = Hashit::from_parts(store(), FileHash {});
+    assert!(matches!(
+        hashit.check(&[&input], &stamp),
+        Err(HashitError::StampVerification(_))
+    ));
+
+    // as does replacing the stamp with one written without a key
+    let mut plain = Hashit::new();
+    plain.has_changed(&[&input], &stamp).unwrap();
+    let mut hashit = Hashit::from_parts(store(), FileHash {});
+    assert!(matches!(
+        hashit.check(&[&input], &stamp),
+        Err(HashitError::StampVerification(_))
+    ));
+}
+
+// the stamp is named relative to the working directory, which other tests
+// move
+#[test]
+#[serial_test::serial]
+fn ed25519_public_key_verifies_but_can_not_commit() {
+    let signing = Authenticator::signing(&SECRET).unwrap();
+    let public = match &signing {
+        Authenticator::Sign(keypair) => keypair.public.to_bytes(),
+        _ => unreachable!(),
+    };
+    let verifying = Authenticator::verifying(&public).unwrap();
+
+    let sealed = signing.seal(b"contents", "stamp").unwrap();
+    assert_eq!(
+        verifying.open_sealed(&sealed, "stamp").unwrap(),
+        b"contents"
+    );
+    assert!(matches!(
+        Authenticator::hmac("secret")
+            .unwrap()
+            .open_sealed(&sealed, "stamp"),
+        Err(HashitError::StampVerification(_))
+    ));
+    assert!(verifying.seal(b"contents", "stamp").is_err());
+}
+
+// a valid stamp copied over that of another target does not verify
+#[test]
+fn stamp_can_not_be_replayed_onto_another_target() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input");
+    let stamp = dir.path().join("stamp");
+    let other = dir.path().join("other");
+    fs::write(&input, "input").unwrap();
+
+    let store = || AuthFile::new(Authenticator::hmac("secret").unwrap());
+    let mut hashit = Hashit::from_parts(store(), FileHash {});
+    assert!(hashit.has_changed(&[&input], &stamp).unwrap());
+    fs::copy(&stamp, &other).unwrap();
+    let mut hashit = Hashit::from_parts(store(), FileHash {});
+    assert!(matches!(
+        hashit.check(&[&input], &other),
+        Err(HashitError::StampVerification(_))
+    ));
+}
+
+#[test]
+fn debug_does_not_reveal_keys() {
+    let hmac = format!(
+        "{:?}",
+        AuthFile::new(Authenticator::hmac("hunter2").unwrap())
+    );
+    assert!(!hmac.contains("hunter2") && !hmac.contains("104, 117"));
+    let signing = format!("{:?}", Authenticator::signing(&SECRET).unwrap());
+    assert!(!signing.contains(&hex::encode(SECRET)) && !signing.contains("7, 7"));
+}
+
+// however the stamp is named, it is bound to the same file
+#[test]
+fn stamp_verifies_under_any_name() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out");
+    fs::create_dir(&out).unwrap();
+    let stamp = out.join("stamp");
+    let auth = Authenticator::hmac("secret").unwrap();
+    let sealed = auth.seal(b"contents", &stamp.to_string_lossy()).unwrap();
+    fs::write(&stamp, &sealed).unwrap();
+
+    let dotted = format!("{}/./out/../out/stamp", dir.path().display());
+    assert_eq!(auth.open_sealed(&sealed, &dotted).unwrap(), b"contents");
+    let other = out.join("other");
+    assert!(auth.open_sealed(&sealed, &other.to_string_lossy()).is_err());
+}
+
+// a trailing newline in a key file is not part of the key
+#[test]
+#[serial_test::serial]
+fn key_file_is_trimmed() {
+    let dir = tempfile::tempdir().unwrap();
+    let key = dir.path().join("key");
+    fs::write(&key, "secret\n").unwrap();
+    std::env::remove_var(HMAC_KEY_ENV);
+    std::env::set_var(HMAC_KEY_FILE_ENV, &key);
+    let from_file = Authenticator::from_env().unwrap().unwrap();
+    std::env::remove_var(HMAC_KEY_FILE_ENV);
+    let sealed = Authenticator::hmac("secret")
+        .unwrap()
+        .seal(b"contents", "stamp")
+        .unwrap();
+    assert_eq!(
+        from_file.open_sealed(&sealed, "stamp").unwrap(),
+        b"contents"
+    );
+}
+
+// the stamp is only sealed once, however many writes make it up
+#[test]
+fn writer_seals_on_flush() {
+    let dir = tempfile::tempdir().unwrap();
+    let stamp = dir.path().join("stamp");
+    let name = stamp.to_string_lossy().to_string();
+    let mut store = AuthFile::new(Authenticator::hmac("secret").unwrap());
+    let mut writer = store.open_mut(&name, OpenMode::WriteTruncate).unwrap();
+    writer.write_all(b"first ").unwrap();
+    writer.write_all(b"second").unwrap();
+    assert_eq!(fs::metadata(&stamp).map(|m| m.len()).unwrap_or(0), 0);
+    writer.flush().unwrap();
+    drop(writer);
+    assert_eq!(store.fetch_cached_hash(&name).unwrap(), b"first second");
+}
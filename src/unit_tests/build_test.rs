@@ -0,0 +1,50 @@
+use super::*;
+use std::fs;
+
+#[test]
+fn rerun_if_changed_includes_glob_roots() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("schema")).unwrap();
+    fs::write(dir.path().join("schema/a.json"), "{}").unwrap();
+    fs::write(dir.path().join("extra.txt"), "extra").unwrap();
+
+    let root = dir.path().display();
+    let build = Build::new(&[
+        format!("{}/schema/*.json", root),
+        format!("{}/extra.txt", root),
+    ])
+    .unwrap()
+    .with_stamp(dir.path().join("stamp"));
+    assert_eq!(
+        build.rerun_if_changed(),
+        vec![
+            format!("cargo:rerun-if-changed={}/extra.txt", root),
+            format!("cargo:rerun-if-changed={}/schema", root),
+            format!("cargo:rerun-if-changed={}/schema/a.json", root),
+        ]
+    );
+}
+
+// the closure runs the first time, is skipped while nothing changes, and runs
+// again if out goes missing. A failure leaves the stamp untouched.
+#[test]
+fn regenerate_if_changed_only_commits_on_success() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.json");
+    let out = dir.path().join("generated.rs");
+    fs::write(&input, "{}").unwrap();
+    let inputs = [input.to_string_lossy().to_string()];
+
+    let failed = regenerate_if_changed(&inputs, &out, |_| Err("boom"));
+    assert!(matches!(failed, Err(HashitError::Regenerate(_))));
+
+    let write = |out: &Path| fs::write(out, "// generated");
+    assert!(regenerate_if_changed(&inputs, &out, write).unwrap());
+    assert!(!regenerate_if_changed(&inputs, &out, write).unwrap());
+
+    fs::remove_file(&out).unwrap();
+    assert!(regenerate_if_changed(&inputs, &out, write).unwrap());
+
+    fs::write(&input, "{\"changed\": true}").unwrap();
+    assert!(regenerate_if_changed(&inputs, &out, write).unwrap());
+}
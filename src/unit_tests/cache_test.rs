@@ -0,0 +1,142 @@
+use super::*;
+use std::time::Duration;
+
+// set the last use of an entry, so that eviction order is deterministic
+fn set_last_used(cache: &ArtifactCache, key: &str, secs_ago: u64) {
+    fs::File::options()
+        .write(true)
+        .open(cache.entry_path(key))
+        .unwrap()
+        .set_modified(SystemTime::now() - Duration::from_secs(secs_ago))
+        .unwrap();
+}
+
+#[test]
+fn restore_given_saved_outputs_recreates_them() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ArtifactCache::new(dir.path().join("cache"));
+    let out = dir.path().join("out/generated.rs");
+    fs::create_dir_all(out.parent().unwrap()).unwrap();
+    fs::write(&out, "generated").unwrap();
+    fs::set_permissions(&out, fs::Permissions::from_mode(0o750)).unwrap();
+
+    assert!(!cache.restore("abc123").unwrap());
+    cache.save("abc123", &[&out]).unwrap();
+    assert!(cache.contains("abc123"));
+
+    fs::remove_dir_all(out.parent().unwrap()).unwrap();
+    assert!(cache.restore("abc123").unwrap());
+    assert_eq!(fs::read_to_string(&out).unwrap(), "generated");
+    assert_eq!(
+        fs::metadata(&out).unwrap().permissions().mode() & 0o7777,
+        0o750
+    );
+}
+
+#[test]
+fn restore_or_run_only_runs_on_miss() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ArtifactCache::new(dir.path().join("cache"));
+    let out = dir.path().join("out.txt");
+    let mut runs = 0;
+    for _ in 0..2 {
+        cache
+            .restore_or_run("feed", &[&out], || {
+                runs += 1;
+                fs::write(&out, "output")?;
+                Ok(())
+            })
+            .unwrap();
+    }
+    assert_eq!(runs, 1);
+}
+
+#[test]
+fn invalid_key_is_error() {
+    let cache = ArtifactCache::new("/nonexistent");
+    assert!(matches!(
+        cache.restore("../escape"),
+        Err(HashitError::InvalidCacheKey(_))
+    ));
+}
+
+// identical outputs share an object. Once the cache exceeds its limit, the
+// least recently used entry goes, along with objects only it refers to.
+#[test]
+fn evict_removes_least_recently_used() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ArtifactCache::new(dir.path().join("cache")).with_max_size(2000);
+    let shared = dir.path().join("shared");
+    let big_a = dir.path().join("a");
+    let big_b = dir.path().join("b");
+    fs::write(&shared, "shared").unwrap();
+    fs::write(&big_a, vec![b'a'; 600]).unwrap();
+    fs::write(&big_b, vec![b'b'; 600]).unwrap();
+
+    cache.save("aa", &[&shared, &big_a]).unwrap();
+    set_last_used(&cache, "aa", 100);
+    cache.save("bb", &[&shared, &big_b]).unwrap();
+    set_last_used(&cache, "bb", 50);
+    // touch aa, making bb the least recently used
+    assert!(cache.restore("aa").unwrap());
+
+    fs::write(&big_a, vec![b'c'; 600]).unwrap();
+    cache.save("cc", &[&shared, &big_a]).unwrap();
+
+    assert!(cache.contains("aa"));
+    assert!(!cache.contains("bb"));
+    assert!(cache.contains("cc"));
+    assert!(cache.size().unwrap() <= 2000);
+    assert!(cache.restore("aa").unwrap());
+}
+
+// objects nothing refers to, and abandoned temporary files, are swept before
+// any live entry is evicted
+#[test]
+fn evict_sweeps_orphans_first() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ArtifactCache::new(dir.path().join("cache")).with_max_size(1500);
+    let output = dir.path().join("output");
+    fs::write(&output, vec![b'a'; 400]).unwrap();
+    cache.save("aa", &[&output]).unwrap();
+
+    // an object orphaned by overwriting aa, one left by a crash before its
+    // entry was written, and a temporary file from an interrupted write
+    let orphan = cache.object_path(&cache.put_object(&[b'o'; 300]).unwrap());
+    let crashed = cache.object_path(&cache.put_object(&[b'c'; 300]).unwrap());
+    let tmp = dir.path().join("cache/objects/ff.tmp1");
+    fs::write(&tmp, vec![b't'; 300]).unwrap();
+    let old = SystemTime::now() - Duration::from_secs(3600);
+    for path in &[&orphan, &crashed, &tmp] {
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+    }
+
+    fs::write(&output, vec![b'b'; 400]).unwrap();
+    cache.save("bb", &[&output]).unwrap();
+    assert!(cache.contains("aa"));
+    assert!(cache.contains("bb"));
+    assert!(!orphan.exists() && !crashed.exists() && !tmp.exists());
+    assert!(cache.size().unwrap() <= 1500);
+}
+
+// a corrupt object is dropped rather than restored
+#[test]
+fn corrupt_object_is_not_restored() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ArtifactCache::new(dir.path().join("cache"));
+    let output = dir.path().join("output");
+    fs::write(&output, "contents").unwrap();
+    cache.save("aa", &[&output]).unwrap();
+    let digest = cache.entry("aa").unwrap().unwrap()[0].digest.clone();
+    fs::write(cache.object_path(&digest), "corrupt").unwrap();
+
+    fs::remove_file(&output).unwrap();
+    assert!(!cache.restore("aa").unwrap());
+    assert!(!output.exists());
+    assert!(!cache.object_path(&digest).exists());
+}
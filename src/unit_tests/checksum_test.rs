@@ -0,0 +1,91 @@
+use super::*;
+use std::fs;
+
+const EMPTY_B2: &str = "786a02f742015903c6c6fd852552d272912f4740e15847618a86e217f71f5419d25e1031afee585313896444934eb04b903a685b1448b755d56f701afe9be2ce";
+const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+#[test]
+fn sum_line_matches_b2sum() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("empty");
+    fs::write(&path, "").unwrap();
+    assert_eq!(
+        sum_line(&path).unwrap(),
+        format!("{}  {}", EMPTY_B2, path.display())
+    );
+}
+
+#[test]
+fn parse_line_handles_each_format() {
+    let entry = parse_line(&format!("{}  some file", EMPTY_B2), 1).unwrap();
+    assert_eq!(entry.path, PathBuf::from("some file"));
+    assert_eq!(entry.algorithm, None);
+
+    let entry = parse_line(&format!("{} *binary", EMPTY_SHA256), 1).unwrap();
+    assert_eq!(entry.path, PathBuf::from("binary"));
+
+    let entry = parse_line(&format!("SHA256 (tagged) = {}", EMPTY_SHA256), 1).unwrap();
+    assert_eq!(entry.path, PathBuf::from("tagged"));
+    assert_eq!(entry.algorithm, Some(Algorithm::Sha256));
+
+    let entry = parse_line(&format!("\\{}  back\\\\slash\\nnewline", EMPTY_B2), 1).unwrap();
+    assert_eq!(entry.path, PathBuf::from("back\\slash\nnewline"));
+
+    // an untagged path which happens to look like the --tag format
+    let entry = parse_line(&format!("{}  odd (name) = {}", EMPTY_B2, EMPTY_B2), 1).unwrap();
+    assert_eq!(
+        entry.path,
+        PathBuf::from(format!("odd (name) = {}", EMPTY_B2))
+    );
+    assert_eq!(entry.algorithm, None);
+
+    assert!(matches!(
+        parse_line("not a checksum", 3),
+        Err(HashitError::MalformedChecksum { line: 3, .. })
+    ));
+    assert!(matches!(
+        parse_line(&format!("MD5 (tagged) = {}", EMPTY_SHA256), 4),
+        Err(HashitError::MalformedChecksum { line: 4, .. })
+    ));
+}
+
+// a mix of b2sum and sha256sum lines, one of which no longer matches and one
+// of which is missing
+#[test]
+fn check_reports_each_line() {
+    let dir = tempfile::tempdir().unwrap();
+    let empty = dir.path().join("empty");
+    let changed = dir.path().join("changed");
+    let missing = dir.path().join("missing");
+    fs::write(&empty, "").unwrap();
+    fs::write(&changed, "no longer empty").unwrap();
+
+    let sums = format!(
+        "{}  {}\n{}  {}\nnot a checksum\n\n{}  {}\n",
+        EMPTY_B2,
+        empty.display(),
+        EMPTY_SHA256,
+        changed.display(),
+        EMPTY_SHA256,
+        missing.display()
+    );
+    let outcomes = check(sums.as_bytes(), None).unwrap();
+    assert_eq!(outcomes.len(), 4);
+    assert_eq!(outcomes[0].status, Status::Ok);
+    assert_eq!(outcomes[1].status, Status::Failed);
+    assert_eq!(outcomes[2].status, Status::Malformed(3));
+    assert!(matches!(outcomes[3].status, Status::Unreadable(_)));
+    assert_eq!(outcomes[1].line(), format!("{}: FAILED", changed.display()));
+}
+
+// like coreutils, blank and comment lines are skipped rather than reported
+#[test]
+fn check_skips_blank_and_comment_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    let empty = dir.path().join("empty");
+    fs::write(&empty, "").unwrap();
+    let sums = format!("# generated\n\n{}  {}\n", EMPTY_B2, empty.display());
+    let outcomes = check(sums.as_bytes(), None).unwrap();
+    assert_eq!(outcomes.len(), 1);
+    assert_eq!(outcomes[0].path, empty);
+}
@@ -0,0 +1,88 @@
+use super::*;
+use crate::file::FileHash;
+use crate::{Change, Hashit, HtFile};
+
+// len bytes of noise, which is the same for a given seed
+fn noise(seed: u64, len: usize) -> Vec<u8> {
+    (0..len as u64)
+        .map(|idx| splitmix(seed ^ idx.wrapping_mul(31)) as u8)
+        .collect()
+}
+
+#[test]
+fn chunks_cover_the_input_within_bounds() {
+    let chunker = Chunker::new(1024, 4096, 16384).unwrap();
+    let data = noise(1, 200_000);
+    let list = chunker.chunks(&data[..]).unwrap();
+    assert_eq!(list.size(), data.len() as u64);
+    // about 49 chunks of 4 KiB
+    assert!((25..=100).contains(&list.chunks().len()));
+    let mut offset = 0;
+    for (idx, chunk) in list.chunks().iter().enumerate() {
+        assert_eq!(chunk.offset, offset);
+        if idx + 1 < list.chunks().len() {
+            assert!((1024..=16384).contains(&chunk.len));
+        }
+        offset += chunk.len;
+    }
+    assert_eq!(ChunkList::parse(&list.to_string()), Some(list.clone()));
+    assert_eq!(chunker.chunks(&data[..]).unwrap(), list);
+    assert!(Chunker::new(1024, 3000, 16384).is_err());
+}
+
+#[test]
+fn insertion_only_disturbs_nearby_chunks() {
+    let chunker = Chunker::new(1024, 4096, 16384).unwrap();
+    let before = noise(2, 500_000);
+    let mut after = before.clone();
+    after.splice(250_000..250_000, b"inserted".iter().copied());
+
+    let old = chunker.chunks(&before[..]).unwrap();
+    let new = chunker.chunks(&after[..]).unwrap();
+    let ranges = new.changed_ranges(&old);
+    assert_eq!(ranges.len(), 1);
+    assert!(ranges[0].contains(&250_000));
+    assert!(ranges[0].end - ranges[0].start <= 2 * 16384);
+    assert!(old.changed_ranges(&old).is_empty());
+
+    // removing whole chunks adds nothing new
+    let first = &old.chunks()[0];
+    let tail = chunker.chunks(&before[first.len as usize..]).unwrap();
+    assert_eq!(
+        tail.diff(&old),
+        vec![format!("{} bytes removed", first.len)]
+    );
+}
+
+#[test]
+fn report_names_changed_ranges() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("asset.bin");
+    let stamp = dir.path().join("stamp");
+    let hasher = || {
+        ChunkedHash::new(FileHash {})
+            .with_chunker(Chunker::new(1024, 4096, 16384).unwrap())
+            .with_chunk_lists(dir.path().join("chunks"))
+    };
+
+    let mut data = noise(3, 100_000);
+    fs::write(&input, &data).unwrap();
+    let mut hashit = Hashit::from_parts(HtFile::new(), hasher());
+    assert!(hashit.has_changed(&[&input], &stamp).unwrap());
+
+    data[0] ^= 0xff;
+    fs::write(&input, &data).unwrap();
+    let mut hashit = Hashit::from_parts(HtFile::new(), hasher());
+    let report = hashit.report(&[&input], &stamp).unwrap();
+    let name = input.to_string_lossy().to_string();
+    let changes = report.changes();
+    assert_eq!(changes[0], Change::Input(name.clone()));
+    match &changes[1..] {
+        [Change::Entry(entry_name, entry)] => {
+            assert_eq!(entry_name, &name);
+            assert!(entry.starts_with("bytes 0.."), "{}", entry);
+            assert!(entry.ends_with("%)"), "{}", entry);
+        }
+        changes => panic!("unexpected changes {:?}", changes),
+    }
+}
@@ -0,0 +1,121 @@
+use super::*;
+use std::fs;
+use std::thread;
+
+// spin up a daemon on a socket in dir, returning a client for it along with
+// the handle of the serving thread
+fn spawn_daemon(dir: &Path) -> (Client, thread::JoinHandle<Result<()>>) {
+    let socket = dir.join("hashtest.sock");
+    let mut daemon = Daemon::bind(&socket).unwrap();
+    let handle = thread::spawn(move || daemon.serve());
+    (Client::new(socket), handle)
+}
+
+// the digests returned by the daemon must match those calculated directly
+#[test]
+fn hash_matches_direct_hash() {
+    let dir = tempfile::tempdir().unwrap();
+    let (client, handle) = spawn_daemon(dir.path());
+    let input = dir.path().join("input.txt");
+    fs::write(&input, "contents").unwrap();
+
+    let expected = blake_hash(b"contents");
+    assert_eq!(client.hash(&[&input]).unwrap(), expected);
+    // second request is served from memory
+    assert_eq!(client.hash(&[&input]).unwrap(), expected);
+
+    fs::write(&input, "new contents").unwrap();
+    assert_eq!(client.hash(&[&input]).unwrap(), blake_hash(b"new contents"));
+
+    client.shutdown().unwrap();
+    handle.join().unwrap().unwrap();
+}
+
+// check does not update the stamp. commit does.
+#[test]
+fn check_and_commit() {
+    let dir = tempfile::tempdir().unwrap();
+    let (client, handle) = spawn_daemon(dir.path());
+    let input = dir.path().join("input.txt");
+    let stamp = dir.path().join("stamp");
+    fs::write(&input, "contents").unwrap();
+
+    assert!(client.check(&[&input], &stamp).unwrap());
+    assert!(client.check(&[&input], &stamp).unwrap());
+    client.commit(&[&input], &stamp).unwrap();
+    assert!(!client.check(&[&input], &stamp).unwrap());
+
+    fs::write(&input, "changed").unwrap();
+    assert!(client.check(&[&input], &stamp).unwrap());
+
+    client.shutdown().unwrap();
+    handle.join().unwrap().unwrap();
+}
+
+#[test]
+fn missing_input_is_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let (client, handle) = spawn_daemon(dir.path());
+
+    let result = client.hash(&[dir.path().join("missing")]);
+    assert!(matches!(result, Err(HashitError::Daemon(_))));
+
+    client.shutdown().unwrap();
+    handle.join().unwrap().unwrap();
+}
+
+// a client which sends garbage, or hangs up without reading its response,
+// must not stop the daemon serving others
+#[test]
+fn misbehaving_client_does_not_stop_daemon() {
+    let dir = tempfile::tempdir().unwrap();
+    let (client, handle) = spawn_daemon(dir.path());
+    let input = dir.path().join("input.txt");
+    fs::write(&input, "contents").unwrap();
+
+    let mut stream = UnixStream::connect(client.path.clone()).unwrap();
+    writeln!(stream, "hash\n{}\n", input.display()).unwrap();
+    drop(stream);
+    let mut stream = UnixStream::connect(client.path.clone()).unwrap();
+    stream.write_all(&[0xff, 0xfe, b'\n', b'\n']).unwrap();
+    drop(stream);
+
+    assert_eq!(client.hash(&[&input]).unwrap(), blake_hash(b"contents"));
+    client.shutdown().unwrap();
+    handle.join().unwrap().unwrap();
+}
+
+// a daemon which never answers times out, rather than hanging the client
+#[test]
+fn unresponsive_daemon_times_out() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("wedged.sock");
+    let _listener = UnixListener::bind(&socket).unwrap();
+    let client = Client::new(&socket).with_timeout(Duration::from_millis(100));
+    let input = dir.path().join("input.txt");
+    fs::write(&input, "contents").unwrap();
+
+    let started = std::time::Instant::now();
+    assert!(client.hash(&[&input]).is_err());
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+// only a socket of ours, which no one else could have put there, is trusted
+#[test]
+fn untrusted_sockets_are_refused() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("hashtest.sock");
+    let _listener = UnixListener::bind(&socket).unwrap();
+    assert!(trusted(&socket));
+
+    let plain = dir.path().join("plain");
+    fs::write(&plain, "").unwrap();
+    assert!(!trusted(&plain));
+    assert!(!trusted(&dir.path().join("missing.sock")));
+
+    fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o777)).unwrap();
+    assert!(!trusted(&socket));
+    fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o1777)).unwrap();
+    assert!(trusted(&socket));
+}
@@ -0,0 +1,107 @@
+use super::*;
+use crate::report::Change;
+use crate::string::{reset_resources, HtString, StringHash};
+use crate::Hashit;
+use serial_test::serial;
+
+#[test]
+fn env_digest_distinguishes_unset_from_empty() {
+    let component = Component::Env("HASHTEST_FINGERPRINT_TEST_UNSET".to_string());
+    let unset = component.digest().unwrap();
+    let empty = Component::Value("HASHTEST_FINGERPRINT_TEST_UNSET".to_string(), String::new())
+        .digest()
+        .unwrap();
+    assert_ne!(unset, empty);
+    assert_eq!(unset, component.digest().unwrap());
+}
+
+#[test]
+fn missing_tool_is_error() {
+    let fingerprint = Fingerprint::new().with_tool("hashtest-no-such-tool");
+    assert!(matches!(
+        fingerprint.digests(),
+        Err(HashitError::Fingerprint(_))
+    ));
+}
+
+// a changed component is reported by name, rather than as a change to the inputs
+#[test]
+#[serial]
+fn report_names_changed_component() {
+    reset_resources();
+    let output = "output";
+    let hashit = |target: &str| {
+        Hashit::from_parts(HtString::new(), StringHash {}).with_fingerprint(
+            Fingerprint::new()
+                .with_env("HASHTEST_FINGERPRINT_TEST_UNSET")
+                .with_value("target", target),
+        )
+    };
+    let mut first = hashit("x86_64-unknown-linux-gnu");
+    let hash = first.check(&["/input"], output).unwrap().unwrap();
+    first.commit(output, &hash).unwrap();
+
+    let mut same = hashit("x86_64-unknown-linux-gnu");
+    assert!(same.check(&["/input"], output).unwrap().is_none());
+
+    let mut other = hashit("aarch64-unknown-linux-gnu");
+    let report = other.report(&["/input"], output).unwrap();
+    assert_eq!(
+        report.changes(),
+        &[Change::Fingerprint("value target".to_string())]
+    );
+}
+
+#[test]
+fn command_digest_covers_stdout_and_status() {
+    let digest = |argv: &[&str]| {
+        Component::Command(argv.iter().map(|x| x.to_string()).collect(), None)
+            .digest()
+            .unwrap()
+    };
+    let echo = Fingerprint::new().with_command(&["echo", "-n", "a"], None);
+    assert_eq!(echo.components()[0].to_string(), "command echo -n a");
+    assert_eq!(echo.components()[0].value().unwrap(), b"a\0exit status: 0");
+    // the same (empty) output, but a different exit status
+    assert_ne!(digest(&["true"]), digest(&["false"]));
+}
+
+#[test]
+fn command_outliving_timeout_is_error() {
+    let started = std::time::Instant::now();
+    let fingerprint =
+        Fingerprint::new().with_command(&["sleep", "5"], Some(Duration::from_millis(100)));
+    assert!(matches!(
+        fingerprint.digests(),
+        Err(HashitError::Fingerprint(_))
+    ));
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+// a command which leaves a child holding stdout open must still time out
+#[test]
+fn command_given_lingering_child_times_out() {
+    let started = std::time::Instant::now();
+    let fingerprint = Fingerprint::new().with_command(
+        &["sh", "-c", "sleep 5 & echo started"],
+        Some(Duration::from_millis(200)),
+    );
+    assert!(matches!(
+        fingerprint.digests(),
+        Err(HashitError::Fingerprint(_))
+    ));
+    assert!(started.elapsed() < Duration::from_secs(4));
+}
+
+#[test]
+fn split_command_handles_quotes() {
+    assert_eq!(
+        split_command(r#"pkg-config --cflags 'gtk+ 3.0' "a \"b\"" c\ d"#).unwrap(),
+        vec!["pkg-config", "--cflags", "gtk+ 3.0", "a \"b\"", "c d"]
+    );
+    assert_eq!(split_command("  ''  x ").unwrap(), vec!["", "x"]);
+    assert!(matches!(
+        split_command("echo 'oops"),
+        Err(HashitError::Fingerprint(_))
+    ));
+}
@@ -0,0 +1,46 @@
+use super::*;
+
+// as reported by `git hash-object` and `git write-tree`
+const EMPTY_BLOB_SHA1: &str = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+const EMPTY_BLOB_SHA256: &str = "473a0f4c3be8a93681a267e3b1e9a7dcda1185436fe141f7749120a303721813";
+const EMPTY_TREE_SHA1: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+#[test]
+fn blob_id_matches_git() {
+    assert_eq!(
+        hex::encode(ObjectFormat::Sha1.blob_id(b"")),
+        EMPTY_BLOB_SHA1
+    );
+    assert_eq!(
+        hex::encode(ObjectFormat::Sha256.blob_id(b"")),
+        EMPTY_BLOB_SHA256
+    );
+}
+
+#[test]
+fn tree_id_matches_git() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join("a.txt"), "a\n").unwrap();
+    fs::write(root.join("b.txt"), "bt\n").unwrap();
+    fs::create_dir(root.join("b")).unwrap();
+    fs::write(root.join("b/c.sh"), "c\n").unwrap();
+    fs::set_permissions(root.join("b/c.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+    // neither empty directories nor .git appear in the tree
+    fs::create_dir(root.join("empty")).unwrap();
+    fs::create_dir(root.join(".git")).unwrap();
+    fs::write(root.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+
+    assert_eq!(
+        hex::encode(object_id(root, ObjectFormat::Sha1).unwrap()),
+        "34f90420d964da2f29ca574be1a410c0014de5f1"
+    );
+    assert_eq!(
+        hex::encode(object_id(root, ObjectFormat::Sha256).unwrap()),
+        "00976c4913131337cef77d87f09ca34c604eab133887c3219dd444e004834ff2"
+    );
+    assert_eq!(
+        hex::encode(object_id(root.join("empty"), ObjectFormat::Sha1).unwrap()),
+        EMPTY_TREE_SHA1
+    );
+}
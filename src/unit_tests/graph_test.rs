@@ -0,0 +1,146 @@
+use super::*;
+use serial_test::serial;
+
+use crate::string::reset_resources;
+use crate::string::{HtString, StringHash};
+
+fn hashit() -> Hashit<HtString, StringHash> {
+    Hashit::from_parts(HtString::new(), StringHash {})
+}
+
+// targets should be ordered after their dependencies, regardless of the order
+// in which they were added
+#[test]
+fn order_places_dependencies_first() {
+    let mut graph = Graph::new();
+    graph
+        .add(Target::new("compile", "compile.stamp").depends_on("codegen"))
+        .unwrap();
+    graph.add(Target::new("codegen", "codegen.stamp")).unwrap();
+    graph
+        .add(Target::new("link", "link.stamp").depends_on("compile"))
+        .unwrap();
+    let names = graph
+        .order()
+        .unwrap()
+        .iter()
+        .map(|t| t.name().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["codegen", "compile", "link"]);
+}
+
+#[test]
+fn order_given_cycle_is_error() {
+    let mut graph = Graph::new();
+    graph
+        .add(Target::new("a", "a.stamp").depends_on("b"))
+        .unwrap();
+    graph
+        .add(Target::new("b", "b.stamp").depends_on("a"))
+        .unwrap();
+    match graph.order() {
+        Err(HashitError::DependencyCycle(cycle)) => assert_eq!(cycle, "a -> b -> a"),
+        other => panic!("expected cycle, got {:?}", other),
+    }
+}
+
+#[test]
+fn order_given_unknown_dependency_is_error() {
+    let mut graph = Graph::new();
+    graph
+        .add(Target::new("a", "a.stamp").depends_on("missing"))
+        .unwrap();
+    assert!(matches!(graph.order(), Err(HashitError::MissingTarget(_))));
+}
+
+// The first run builds everything. The second run builds nothing. Changing
+// the inputs of codegen rebuilds codegen, and compile along with it, even
+// though compile's own inputs have not changed.
+#[test]
+#[serial]
+fn run_rebuilds_dependents_of_rebuilt_targets() {
+    reset_resources();
+    let mut hashit = hashit();
+    let build = |codegen_input: &str| {
+        let mut graph = Graph::new();
+        graph
+            .add(Target::new("codegen", "codegen.stamp").with_input(codegen_input))
+            .unwrap();
+        graph
+            .add(
+                Target::new("compile", "compile.stamp")
+                    .with_input("main.rs")
+                    .depends_on("codegen"),
+            )
+            .unwrap();
+        graph
+            .add(Target::new("docs", "docs.stamp").with_input("README"))
+            .unwrap();
+        graph
+    };
+
+    let rebuilt = build("schema v1").run(&mut hashit, |_| Ok(())).unwrap();
+    assert_eq!(rebuilt, vec!["codegen", "compile", "docs"]);
+
+    let rebuilt = build("schema v1").run(&mut hashit, |_| Ok(())).unwrap();
+    assert!(rebuilt.is_empty());
+
+    let rebuilt = build("schema v2").run(&mut hashit, |_| Ok(())).unwrap();
+    assert_eq!(rebuilt, vec!["codegen", "compile"]);
+}
+
+// a failing action must not update the stamp
+#[test]
+#[serial]
+fn run_given_failed_action_reruns_target() {
+    reset_resources();
+    let mut hashit = hashit();
+    let mut graph = Graph::new();
+    graph
+        .add(Target::new("gen", "gen.stamp").with_input("input"))
+        .unwrap();
+
+    let result = graph.run(&mut hashit, |t| {
+        Err(HashitError::NotImplemented(t.name().to_string()))
+    });
+    assert!(result.is_err());
+
+    let rebuilt = graph.run(&mut hashit, |_| Ok(())).unwrap();
+    assert_eq!(rebuilt, vec!["gen"]);
+}
+
+// a dependent whose action failed after its dependency was rebuilt must be
+// rerun, even though neither its own inputs nor the dependency have changed
+// since
+#[test]
+#[serial]
+fn run_given_failed_dependent_reruns_it() {
+    reset_resources();
+    let mut hashit = hashit();
+    let build = |codegen_input: &str| {
+        let mut graph = Graph::new();
+        graph
+            .add(Target::new("codegen", "codegen.stamp").with_input(codegen_input))
+            .unwrap();
+        graph
+            .add(
+                Target::new("compile", "compile.stamp")
+                    .with_input("main.rs")
+                    .depends_on("codegen"),
+            )
+            .unwrap();
+        graph
+    };
+    build("schema v1").run(&mut hashit, |_| Ok(())).unwrap();
+
+    let result = build("schema v2").run(&mut hashit, |t| match t.name() {
+        "compile" => Err(HashitError::NotImplemented(t.name().to_string())),
+        _ => Ok(()),
+    });
+    assert!(result.is_err());
+
+    let rebuilt = build("schema v2").run(&mut hashit, |_| Ok(())).unwrap();
+    assert_eq!(rebuilt, vec!["compile"]);
+    let rebuilt = build("schema v2").run(&mut hashit, |_| Ok(())).unwrap();
+    assert!(rebuilt.is_empty());
+}
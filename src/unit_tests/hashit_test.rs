@@ -1,4 +1,5 @@
 use super::*;
+use crate::report::Change;
 use serial_test::serial;
 
 use crate::string::reset_resources;
@@ -14,6 +15,7 @@ fn has_changed_given_new_items_is_true() {
     let mut hashit = Hashit {
         inner: HtString::new(),
         hasher: StringHash {},
+        fingerprint: Fingerprint::default(),
     };
     let has_changed = hashit.has_changed(&vec![input][..], output);
     assert!(has_changed.unwrap());
@@ -41,6 +43,7 @@ fn has_changed_given_existing_item_is_false() {
     let mut hashit = Hashit {
         inner: HtString::new(),
         hasher: StringHash {},
+        fingerprint: Fingerprint::default(),
     };
     // first time we expect the output to
     let has_changed = hashit.has_changed(&vec![input][..], output);
@@ -60,6 +63,7 @@ fn has_changed_given_existing_items_is_false() {
     let mut hashit = Hashit {
         inner: HtString::new(),
         hasher: StringHash {},
+        fingerprint: Fingerprint::default(),
     };
     // first time we expect the output to
     let has_changed = hashit.has_changed(&vec![input, input2][..], output);
@@ -67,3 +71,81 @@ fn has_changed_given_existing_items_is_false() {
     let has_changed = hashit.has_changed(&vec![input, input2][..], output);
     assert_eq!(has_changed.unwrap(), false);
 }
+
+// digest should not depend upon the order of the inputs, or on duplicates
+#[test]
+#[serial]
+fn digest_is_independent_of_input_order() {
+    reset_resources();
+    let hashit = Hashit {
+        inner: HtString::new(),
+        hasher: StringHash {},
+        fingerprint: Fingerprint::default(),
+    };
+    let first = hashit.digest(&["/this/is/new", "/second/input"]).unwrap();
+    let second = hashit
+        .digest(&["/second/input", "/this/is/new", "/second/input"])
+        .unwrap();
+    assert_eq!(first, second);
+    assert_eq!(first.len(), 128);
+
+    let third = hashit.digest(&["/second/input"]).unwrap();
+    assert_ne!(first, third);
+}
+
+// with the same number of inputs, a change is attributed to the input responsible
+#[test]
+#[serial]
+fn report_names_changed_input() {
+    reset_resources();
+    let output = "output";
+    let mut hashit = Hashit {
+        inner: HtString::new(),
+        hasher: StringHash {},
+        fingerprint: Fingerprint::default(),
+    };
+    let report = hashit.report(&["/first", "/second"], output).unwrap();
+    assert_eq!(report.changes(), &[Change::New]);
+    let hash = report.into_hash();
+    hashit.commit(output, &hash).unwrap();
+
+    let mut hashit = Hashit {
+        inner: HtString::new(),
+        hasher: StringHash {},
+        fingerprint: Fingerprint::default(),
+    };
+    let report = hashit.report(&["/first", "/altered"], output).unwrap();
+    assert_eq!(report.changes(), &[Change::Input("/altered".to_string())]);
+    let report = hashit.report(&["/first"], output).unwrap();
+    assert_eq!(report.changes(), &[Change::InputSet]);
+}
+
+// outputs recorded on commit are reported once they drift
+#[test]
+#[serial]
+fn report_given_modified_or_missing_outputs() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    let generated = dir.path().join("generated.txt");
+    let stamp = dir.path().join("stamp");
+    std::fs::write(&input, "input").unwrap();
+    std::fs::write(&generated, "generated").unwrap();
+
+    let mut hashit = Hashit::new();
+    let hash = hashit.check(&[&input], &stamp).unwrap().unwrap();
+    hashit
+        .commit_with_outputs(&stamp, &hash, &[&generated])
+        .unwrap();
+
+    let mut hashit = Hashit::new();
+    assert!(!hashit.report(&[&input], &stamp).unwrap().has_changed());
+
+    std::fs::write(&generated, "tampered").unwrap();
+    let name = generated.to_string_lossy().to_string();
+    let report = hashit.report(&[&input], &stamp).unwrap();
+    assert_eq!(report.changes(), &[Change::OutputModified(name.clone())]);
+
+    std::fs::remove_file(&generated).unwrap();
+    let report = hashit.report(&[&input], &stamp).unwrap();
+    assert_eq!(report.changes(), &[Change::OutputMissing(name)]);
+}
@@ -0,0 +1,152 @@
+use super::*;
+use std::time::{Duration, SystemTime};
+
+// encode entries as an index of the given version, recording the stat data of
+// each file beneath root
+fn encode(version: u32, root: &Path, entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let mut data = b"DIRC".to_vec();
+    data.extend(version.to_be_bytes());
+    data.extend((entries.len() as u32).to_be_bytes());
+    let mut previous = "";
+    for (path, id) in entries {
+        let start = data.len();
+        let metadata = fs::symlink_metadata(root.join(path)).unwrap();
+        for field in [
+            metadata.ctime() as u32,
+            metadata.ctime_nsec() as u32,
+            metadata.mtime() as u32,
+            metadata.mtime_nsec() as u32,
+            metadata.dev() as u32,
+            metadata.ino() as u32,
+            mode_of(&metadata),
+            metadata.uid(),
+            metadata.gid(),
+            metadata.len() as u32,
+        ] {
+            data.extend(field.to_be_bytes());
+        }
+        data.extend(id);
+        data.extend((path.len() as u16).to_be_bytes());
+        if version == 4 {
+            let common = previous
+                .bytes()
+                .zip(path.bytes())
+                .take_while(|(a, b)| a == b)
+                .count();
+            // small enough to need a single byte
+            data.push((previous.len() - common) as u8);
+            data.extend(&path.as_bytes()[common..]);
+            data.push(0);
+        } else {
+            data.extend(path.as_bytes());
+            data.push(0);
+            while !(data.len() - start).is_multiple_of(8) {
+                data.push(0);
+            }
+        }
+        previous = path;
+    }
+    data
+}
+
+// backdate the file at path, so that it is not racily clean
+fn backdate(path: &Path) {
+    let file = fs::File::options().write(true).open(path).unwrap();
+    file.set_modified(SystemTime::now() - Duration::from_secs(60))
+        .unwrap();
+}
+
+#[test]
+fn parses_version_2_and_4() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir(root.join("src")).unwrap();
+    fs::write(root.join("src/lib.rs"), "lib").unwrap();
+    fs::write(root.join("src/main.rs"), "main").unwrap();
+    let entries = [("src/lib.rs", vec![1; 20]), ("src/main.rs", vec![2; 20])];
+    for version in [2, 4] {
+        let parsed = parse(&encode(version, root, &entries), ObjectFormat::Sha1).unwrap();
+        let paths = parsed.iter().map(|e| e.path.clone()).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![PathBuf::from("src/lib.rs"), PathBuf::from("src/main.rs")]
+        );
+        assert_eq!(parsed[1].id, vec![2; 20]);
+        assert_eq!(parsed[1].size, 4);
+        assert_eq!(parsed[1].stage, 0);
+    }
+    assert!(parse(b"DIRC\0\0\0\x05\0\0\0\0", ObjectFormat::Sha1).is_err());
+}
+
+#[test]
+fn trusts_recorded_id_only_while_clean() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir(root.join(".git")).unwrap();
+    fs::write(root.join("clean.txt"), "clean\n").unwrap();
+    fs::write(root.join("racy.txt"), "racy\n").unwrap();
+    backdate(&root.join("clean.txt"));
+    // a bogus id shows whether the index was trusted
+    let bogus = vec![0xab; 20];
+    let entries = [("clean.txt", bogus.clone()), ("racy.txt", bogus.clone())];
+    fs::write(root.join(".git/index"), encode(2, root, &entries)).unwrap();
+
+    let index = GitIndex::discover(root).unwrap();
+    let clean = root.join("clean.txt");
+    assert_eq!(index.object_id(&clean).unwrap(), bogus);
+    // modified in the same second as the index was written
+    let racy = root.join("racy.txt");
+    assert_eq!(
+        index.object_id(&racy).unwrap(),
+        ObjectFormat::Sha1.blob_id(b"racy\n")
+    );
+
+    fs::write(&clean, "dirty\n").unwrap();
+    assert_eq!(
+        index.object_id(&clean).unwrap(),
+        ObjectFormat::Sha1.blob_id(b"dirty\n")
+    );
+}
+
+#[test]
+fn expand_respects_gitignore() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir_all(root.join(".git/info")).unwrap();
+    fs::write(root.join(".git/info/exclude"), "*.swp\n").unwrap();
+    fs::create_dir_all(root.join("src/target")).unwrap();
+    fs::create_dir_all(root.join("src/gen")).unwrap();
+    fs::write(root.join(".gitignore"), "target/\n*.log\n!keep.log\n").unwrap();
+    fs::write(root.join("src/.gitignore"), "/gen/*.rs\n").unwrap();
+    fs::write(root.join("src/lib.rs"), "").unwrap();
+    fs::write(root.join("src/lib.rs.swp"), "").unwrap();
+    fs::write(root.join("src/debug.log"), "").unwrap();
+    fs::write(root.join("src/keep.log"), "").unwrap();
+    fs::write(root.join("src/gen/out.rs"), "").unwrap();
+    fs::write(root.join("src/gen/out.txt"), "").unwrap();
+    fs::write(root.join("src/target/built"), "").unwrap();
+    fs::write(root.join("src/target/tracked"), "").unwrap();
+    // tracked files are included even when ignored
+    let entries = [("src/target/tracked", vec![0; 20])];
+    fs::write(root.join(".git/index"), encode(2, root, &entries)).unwrap();
+
+    let index = GitIndex::discover(root.join("src")).unwrap();
+    let src = root.join("src");
+    let expanded = index
+        .expand(&[&src])
+        .unwrap()
+        .into_iter()
+        .map(|path| path.strip_prefix(&src).unwrap().to_path_buf())
+        .collect::<Vec<_>>();
+    let expected = [
+        ".gitignore",
+        "gen/out.txt",
+        "keep.log",
+        "lib.rs",
+        "target/tracked",
+    ];
+    assert_eq!(
+        expanded,
+        expected.iter().map(PathBuf::from).collect::<Vec<_>>()
+    );
+}
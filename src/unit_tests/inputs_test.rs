@@ -0,0 +1,114 @@
+use super::*;
+use std::fs;
+
+#[test]
+fn expand_globs_is_sorted_and_deduplicated() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("b.rs"), "b").unwrap();
+    fs::write(dir.path().join("a.rs"), "a").unwrap();
+    fs::write(dir.path().join("sub/c.rs"), "c").unwrap();
+    fs::write(dir.path().join("notes.txt"), "notes").unwrap();
+
+    let root = dir.path().to_string_lossy();
+    let patterns = vec![
+        format!("{}/**/*.rs", root),
+        format!("{}/a.rs", root),
+        format!("{}/*", root),
+    ];
+    let paths = expand_globs(&patterns).unwrap();
+    assert_eq!(
+        paths,
+        vec![
+            dir.path().join("a.rs"),
+            dir.path().join("b.rs"),
+            dir.path().join("notes.txt"),
+            dir.path().join("sub/c.rs"),
+        ]
+    );
+}
+
+// literal paths are passed through even if they do not exist
+#[test]
+fn expand_globs_keeps_literal_paths() {
+    let paths = expand_globs(&["does/not/exist"]).unwrap();
+    assert_eq!(paths, vec![PathBuf::from("does/not/exist")]);
+}
+
+// a response file names real files, which must not be globbed again even
+// when their names contain glob characters
+#[test]
+fn expand_args_and_globs_takes_listed_paths_literally() {
+    let dir = tempfile::tempdir().unwrap();
+    let odd = dir.path().join("[a].txt");
+    let other = dir.path().join("a.txt");
+    fs::write(&odd, "").unwrap();
+    fs::write(&other, "").unwrap();
+    let list = dir.path().join("inputs.txt");
+    fs::write(&list, format!("{}\n", odd.display())).unwrap();
+
+    let listed = format!("@{}", list.display());
+    assert_eq!(
+        expand_args_and_globs(&[&listed]).unwrap(),
+        vec![odd.clone()]
+    );
+    let pattern = dir.path().join("*.txt").to_string_lossy().to_string();
+    assert_eq!(
+        expand_args_and_globs(&[&pattern, &listed]).unwrap(),
+        vec![odd, other, list]
+    );
+}
+
+#[test]
+fn expand_globs_given_bad_pattern_is_error() {
+    assert!(matches!(
+        expand_globs(&["src/[.rs"]),
+        Err(HashitError::InvalidGlob(_))
+    ));
+}
+
+#[test]
+fn parse_list_handles_newlines_and_nuls() {
+    assert_eq!(
+        parse_list(b"a.rs\r\nb.rs\n\nc d.rs\n"),
+        vec![
+            PathBuf::from("a.rs"),
+            PathBuf::from("b.rs"),
+            PathBuf::from("c d.rs")
+        ]
+    );
+    // as produced by find -print0. newlines are part of the name
+    assert_eq!(
+        parse_list(b"a.rs\0odd\nname\0"),
+        vec![PathBuf::from("a.rs"), PathBuf::from("odd\nname")]
+    );
+}
+
+// gcc -MD -MP style output
+#[test]
+fn parse_depfile_given_gcc_output() {
+    let contents = "main.o: main.c include/my\\ header.h \\\n  /usr/include/stdio.h \\\n  C:\\sdk\\lib.h\n\ninclude/my\\ header.h:\n/usr/include/stdio.h:\n";
+    assert_eq!(
+        parse_depfile(contents).unwrap(),
+        vec![
+            PathBuf::from("main.c"),
+            PathBuf::from("include/my header.h"),
+            PathBuf::from("/usr/include/stdio.h"),
+            PathBuf::from("C:\\sdk\\lib.h"),
+        ]
+    );
+}
+
+// rustc --emit dep-info style output
+#[test]
+fn parse_depfile_given_rustc_output() {
+    let contents = "/target/debug/libfoo.rlib: src/lib.rs src/util.rs\n\nsrc/lib.rs:\nsrc/util.rs:\n\n# env-dep:CARGO_PKG_NAME=foo\n";
+    assert_eq!(
+        parse_depfile(contents).unwrap(),
+        vec![PathBuf::from("src/lib.rs"), PathBuf::from("src/util.rs")]
+    );
+    assert!(matches!(
+        parse_depfile("no rule here"),
+        Err(HashitError::MalformedDepfile { line: 1, .. })
+    ));
+}
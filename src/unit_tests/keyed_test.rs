@@ -0,0 +1,69 @@
+use super::*;
+use crate::append::AppendHash;
+use crate::file::FileHash;
+use crate::string::StringHash;
+use crate::{Change, Hashit, HtFile};
+use std::fs;
+use std::io::Write;
+
+#[test]
+fn keyed_digests_differ_by_namespace_and_salt() {
+    let inputs = ["/first", "/second"];
+    let plain = StringHash {}.calc_hash(&inputs).unwrap();
+    let tool_a = Keyed::new(StringHash {}).with_namespace("a");
+    let tool_b = Keyed::new(StringHash {}).with_namespace("b");
+    let a = tool_a.calc_hash(&inputs).unwrap();
+    let b = tool_b.calc_hash(&inputs).unwrap();
+    assert_eq!(a.len(), plain.len());
+    assert_ne!(a, plain);
+    assert_ne!(a, b);
+    assert_eq!(a, tool_a.calc_hash(&inputs).unwrap());
+
+    let bumped = Keyed::new(StringHash {})
+        .with_namespace("a")
+        .with_salt("toolchain-v2")
+        .calc_hash(&inputs)
+        .unwrap();
+    assert_ne!(a, bumped);
+}
+
+// a change to one input only alters that input's digest, so that reports
+// can still attribute changes
+#[test]
+fn keyed_digests_preserve_layout() {
+    let keyed = Keyed::new(StringHash {}).with_key([7u8; 100]);
+    let before = keyed.calc_hash(&["/first", "/second"]).unwrap();
+    let after = keyed.calc_hash(&["/first", "/altered"]).unwrap();
+    assert_eq!(before[..64], after[..64]);
+    assert_ne!(before[64..], after[64..]);
+}
+
+// a stateful hasher still reports its entries, and has its records pinned
+#[test]
+fn keyed_forwards_to_stateful_hasher() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("app.log");
+    let stamp = dir.path().join("stamp");
+    let state = dir.path().join("state");
+    let name = log.to_string_lossy().to_string();
+    let append = AppendHash::new(FileHash {})
+        .with_block_size(4)
+        .with_state(&state);
+    let mut hashit = Hashit::from_parts(HtFile::new(), Keyed::new(append).with_namespace("logs"));
+
+    fs::write(&log, "first\n").unwrap();
+    assert!(hashit.has_changed(&[&log], &stamp).unwrap());
+    let pins = fs::read_dir(state.join("stamps")).unwrap().next().unwrap();
+    let pinned = fs::read_to_string(pins.unwrap().path()).unwrap();
+    assert!(state.join("records").join(pinned.trim()).exists());
+
+    let mut file = fs::OpenOptions::new().append(true).open(&log).unwrap();
+    file.write_all(b"second\n").unwrap();
+    assert_eq!(
+        hashit.report(&[&log], &stamp).unwrap().changes(),
+        &[
+            Change::Input(name.clone()),
+            Change::Entry(name, "appended 7 bytes".to_string()),
+        ]
+    );
+}
@@ -0,0 +1,64 @@
+use super::*;
+use crate::Hashit;
+use std::fs;
+
+#[test]
+fn lockfile_round_trips() {
+    let lockfile = Lockfile::new(vec![
+        Pin {
+            path: PathBuf::from("vendor/a.js"),
+            algorithm: Algorithm::Sha256,
+            digest: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_string(),
+        },
+        Pin {
+            path: PathBuf::from("odd\\name"),
+            algorithm: Algorithm::Blake2b512,
+            digest: "ab".repeat(64),
+        },
+    ]);
+    let contents = lockfile.to_string();
+    assert!(contents.starts_with(HEADER));
+    assert_eq!(Lockfile::parse(&contents).unwrap(), lockfile);
+}
+
+// the algorithm may not be left to inference
+#[test]
+fn parse_given_untagged_line_is_error() {
+    let line = format!("{}  vendor/a.js", "ab".repeat(64));
+    assert!(matches!(
+        Lockfile::parse(&line),
+        Err(HashitError::MalformedChecksum { line: 1, .. })
+    ));
+}
+
+#[test]
+fn verify_lock_reports_every_mismatch() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = dir.path().join("a.js");
+    let b = dir.path().join("b.png");
+    fs::write(&a, "a").unwrap();
+    fs::write(&b, "b").unwrap();
+
+    let hashit = Hashit::new();
+    let mut pins = hashit
+        .lock(&[&a], Algorithm::Blake2b512)
+        .unwrap()
+        .pins()
+        .to_vec();
+    pins.extend_from_slice(hashit.lock(&[&b], Algorithm::Sha256).unwrap().pins());
+    let lockfile = Lockfile::new(pins);
+    hashit.verify_lock(&lockfile).unwrap();
+
+    fs::write(&a, "tampered").unwrap();
+    fs::remove_file(&b).unwrap();
+    match hashit.verify_lock(&lockfile) {
+        Err(HashitError::LockMismatch(mismatches)) => {
+            assert_eq!(mismatches.len(), 2);
+            assert_eq!(mismatches[0].path, a);
+            assert!(mismatches[0].actual.is_ok());
+            assert_eq!(mismatches[1].path, b);
+            assert!(mismatches[1].actual.is_err());
+        }
+        other => panic!("expected a mismatch, got {:?}", other),
+    }
+}
@@ -0,0 +1,139 @@
+use super::*;
+use crate::file::FileHash;
+use crate::report::Change;
+use crate::{Hashit, HtFile};
+use std::time::SystemTime;
+
+fn write(root: &Path, path: &str, contents: &str) {
+    let path = root.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+// move the modification time of path back by an hour
+fn backdate(path: &Path) {
+    fs::File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(SystemTime::now() - std::time::Duration::from_secs(3600))
+        .unwrap();
+}
+
+#[test]
+fn diff_summarizes_changed_subtrees() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    write(root, "src/net/tcp.rs", "tcp");
+    write(root, "src/net/udp.rs", "udp");
+    write(root, "src/lib.rs", "lib");
+    write(root, "README", "readme");
+    let before = Tree::build(root, None).unwrap();
+    assert_eq!(Tree::parse(&before.to_string()), Some(before.clone()));
+
+    write(root, "src/net/tcp.rs", "tcp2");
+    write(root, "src/net/quic.rs", "quic");
+    let after = Tree::build(root, Some(&before)).unwrap();
+    assert_ne!(before.digest(), after.digest());
+    assert_eq!(
+        before.get("src/lib.rs").unwrap().digest,
+        after.get("src/lib.rs").unwrap().digest
+    );
+    assert_eq!(after.diff(&before), vec!["src/net/".to_string()]);
+
+    write(root, "src/net/udp.rs", "udp2");
+    write(root, "README", "readme2");
+    let last = Tree::build(root, Some(&after)).unwrap();
+    assert_eq!(
+        last.diff(&after),
+        vec!["README".to_string(), "src/net/udp.rs".to_string()]
+    );
+}
+
+// unchanged files reuse the recorded digest rather than being read
+#[test]
+fn build_reuses_digests_of_unchanged_files() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "a.txt", "a");
+    backdate(&dir.path().join("a.txt"));
+    let mut previous = Tree::build(dir.path(), None).unwrap();
+    previous.entries.get_mut(Path::new("a.txt")).unwrap().digest = vec![0; 64];
+    let tree = Tree::build(dir.path(), Some(&previous)).unwrap();
+    assert_eq!(tree.get("a.txt").unwrap().digest, vec![0; 64]);
+}
+
+// a file modified no earlier than the previous build may have been rewritten
+// within the same tick, so is read again
+#[test]
+fn build_rereads_racily_clean_files() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "a.txt", "a");
+    let mut previous = Tree::build(dir.path(), None).unwrap();
+    previous.entries.get_mut(Path::new("a.txt")).unwrap().digest = vec![0; 64];
+    let tree = Tree::build(dir.path(), Some(&previous)).unwrap();
+    assert_eq!(tree.get("a.txt").unwrap().digest, blake_hash(b"a"));
+}
+
+// a fifo is hashed by its type rather than read, which would block
+#[test]
+fn special_files_are_not_read() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "a.txt", "a");
+    let fifo = std::ffi::CString::new(dir.path().join("pipe").to_str().unwrap()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+    let tree = Tree::build(dir.path(), None).unwrap();
+    let pipe = tree.get("pipe").unwrap();
+    assert_eq!(pipe.kind, Kind::Special);
+    assert_eq!(pipe.digest, blake_hash(b"fifo"));
+    assert_eq!(Tree::parse(&tree.to_string()), Some(tree.clone()));
+}
+
+// only the trees a stamp refers to, and the latest, are kept
+#[test]
+fn superseded_trees_are_pruned() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("repo");
+    let stamp = dir.path().join("stamp");
+    let cache = dir.path().join("cache");
+    let mut hashit = Hashit::from_parts(
+        HtFile::new(),
+        MerkleHash::new(FileHash {}).with_cache(&cache),
+    );
+    let trees = || fs::read_dir(cache.join("trees")).unwrap().count();
+
+    write(&root, "a.txt", "a");
+    assert!(hashit.has_changed(&[&root], &stamp).unwrap());
+    for contents in &["b", "bb", "bbb"] {
+        write(&root, "a.txt", contents);
+        assert!(hashit.report(&[&root], &stamp).unwrap().has_changed());
+    }
+    assert_eq!(trees(), 2);
+    assert!(hashit.has_changed(&[&root], &stamp).unwrap());
+    assert_eq!(trees(), 1);
+}
+
+#[test]
+fn report_names_changed_subtree() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("repo");
+    let stamp = dir.path().join("stamp");
+    write(&root, "src/net/tcp.rs", "tcp");
+    write(&root, "src/lib.rs", "lib");
+    let hasher = || MerkleHash::new(FileHash {}).with_cache(dir.path().join("cache"));
+
+    let mut hashit = Hashit::from_parts(HtFile::new(), hasher());
+    assert!(hashit.has_changed(&[&root], &stamp).unwrap());
+    let mut hashit = Hashit::from_parts(HtFile::new(), hasher());
+    assert!(!hashit.has_changed(&[&root], &stamp).unwrap());
+
+    write(&root, "src/net/tcp.rs", "tcp2");
+    let mut hashit = Hashit::from_parts(HtFile::new(), hasher());
+    let name = root.to_string_lossy().to_string();
+    assert_eq!(
+        hashit.report(&[&root], &stamp).unwrap().changes(),
+        &[
+            Change::Input(name.clone()),
+            Change::Entry(name, "src/net/tcp.rs".to_string()),
+        ]
+    );
+}
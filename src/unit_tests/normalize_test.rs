@@ -0,0 +1,72 @@
+use super::*;
+use crate::utils::blake_hash;
+
+// without any steps, the digest matches that of the raw contents
+#[test]
+fn empty_normalizer_digests_raw_contents() {
+    let contents = b"first\r\nsecond  \nno newline";
+    assert_eq!(
+        Normalizer::new().digest(&contents[..]).unwrap(),
+        blake_hash(contents)
+    );
+}
+
+#[test]
+fn normalizer_ignores_irrelevant_differences() {
+    let normalizer = Normalizer::new()
+        .with_bom()
+        .with_line_endings()
+        .with_trailing_whitespace()
+        .with_dropped_lines(r"^// Generated on ")
+        .unwrap();
+    let unix = b"// Generated on Monday\nfn main() {}\n";
+    let windows = b"\xef\xbb\xbf// Generated on Tuesday\r\nfn main() {}  \r\n";
+    assert_eq!(
+        normalizer.digest(&unix[..]).unwrap(),
+        normalizer.digest(&windows[..]).unwrap()
+    );
+    assert_ne!(
+        normalizer.digest(&unix[..]).unwrap(),
+        normalizer.digest(&b"fn main() { }\n"[..]).unwrap()
+    );
+}
+
+// a line too long to hold in memory is hashed as it is
+#[test]
+fn overlong_line_is_hashed_as_is() {
+    let normalizer = Normalizer::new()
+        .with_line_endings()
+        .with_trailing_whitespace()
+        .with_dropped_lines("^x")
+        .unwrap();
+    let mut contents = vec![b'x'; MAX_LINE_LENGTH * 2 + 7];
+    contents.extend(b"  \r\nx\r\nshort  \r\n");
+    let mut expected = contents[..contents.len() - 12].to_vec();
+    expected.extend(b"short\n");
+    assert_eq!(
+        normalizer.digest(&contents[..]).unwrap(),
+        blake_hash(&expected)
+    );
+}
+
+#[test]
+fn normalized_hash_applies_first_matching_rule() {
+    let dir = tempfile::tempdir().unwrap();
+    let generated = dir.path().join("generated.rs");
+    let plain = dir.path().join("plain.txt");
+    fs::write(&generated, "a\r\n").unwrap();
+    fs::write(&plain, "a\r\n").unwrap();
+
+    let hasher = NormalizedHash::new()
+        .with_rule("*.rs", Normalizer::new().with_line_endings())
+        .unwrap();
+    let hash = hasher
+        .calc_hash(&[generated.to_string_lossy(), plain.to_string_lossy()])
+        .unwrap();
+    assert_eq!(hash[..64], blake_hash(b"a\n")[..]);
+    assert_eq!(hash[64..], blake_hash(b"a\r\n")[..]);
+    assert!(matches!(
+        Normalizer::new().with_dropped_lines("("),
+        Err(HashitError::InvalidRegex(_))
+    ));
+}
@@ -0,0 +1,55 @@
+use super::*;
+use crate::file::FileHash;
+
+#[test]
+fn every_strategy_matches_file_hash() {
+    let dir = tempfile::tempdir().unwrap();
+    let strategies = [
+        ReadStrategy::Buffered,
+        ReadStrategy::Mmap,
+        ReadStrategy::Direct,
+        ReadStrategy::Auto,
+    ];
+    for size in [0, 1, ALIGN - 1, ALIGN, ALIGN + 1, BUFFER_SIZE + 3] {
+        let file = dir.path().join(format!("{}.bin", size));
+        let contents = (0..size).map(|idx| (idx % 251) as u8).collect::<Vec<_>>();
+        fs::write(&file, contents).unwrap();
+        let file = file.to_str().unwrap();
+        let expected = FileHash {}.calc_hash(&[file]).unwrap();
+        for strategy in &strategies {
+            assert_eq!(
+                ReadHash::new(*strategy).calc_hash(&[file]).unwrap(),
+                expected,
+                "{:?} reading {} bytes",
+                strategy,
+                size
+            );
+        }
+    }
+}
+
+#[test]
+fn auto_chooses_by_size() {
+    let auto = ReadStrategy::Auto;
+    assert_eq!(auto.resolve(0), ReadStrategy::Buffered);
+    assert_eq!(auto.resolve(AUTO_DIRECT_SIZE - 1), ReadStrategy::Buffered);
+    assert_eq!(auto.resolve(AUTO_DIRECT_SIZE), ReadStrategy::Direct);
+    assert_eq!(ReadStrategy::Mmap.resolve(0), ReadStrategy::Mmap);
+    assert_eq!("MMAP".parse::<ReadStrategy>().unwrap(), ReadStrategy::Mmap);
+    assert!(matches!(
+        "cached".parse::<ReadStrategy>(),
+        Err(HashitError::UnknownReadStrategy(_))
+    ));
+}
+
+#[test]
+fn missing_file_is_not_found() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("missing");
+    for strategy in &[ReadStrategy::Mmap, ReadStrategy::Direct] {
+        assert!(matches!(
+            strategy.digest_file(&missing),
+            Err(HashitError::NotFound { .. })
+        ));
+    }
+}
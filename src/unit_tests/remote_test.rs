@@ -0,0 +1,254 @@
+use super::*;
+use serial_test::serial;
+use std::collections::HashMap;
+use std::fs;
+use std::io::BufRead;
+use std::net::TcpListener;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+type Store = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+// A stand in for a remote cache server. PUTs require the bearer token
+// "secret". Serves until the test process exits.
+fn spawn_server() -> (String, Store) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/cache", listener.local_addr().unwrap());
+    let store: Store = Arc::new(Mutex::new(HashMap::new()));
+    let server_store = store.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let mut parts = line.split_whitespace();
+            let method = parts.next().unwrap().to_string();
+            let path = parts.next().unwrap().to_string();
+            let mut length = 0;
+            let mut authorized = false;
+            loop {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                let header = line.trim_end().to_lowercase();
+                if header.is_empty() {
+                    break;
+                }
+                if let Some(value) = header.strip_prefix("content-length: ") {
+                    length = value.parse().unwrap();
+                }
+                authorized |= header == "authorization: bearer secret";
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            let mut store = server_store.lock().unwrap();
+            let (status, body) = match method.as_str() {
+                "GET" => match store.get(&path) {
+                    Some(body) => ("200 OK", body.clone()),
+                    None => ("404 Not Found", Vec::new()),
+                },
+                "PUT" if authorized => {
+                    store.insert(path, body);
+                    ("201 Created", Vec::new())
+                }
+                _ => ("401 Unauthorized", Vec::new()),
+            };
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n",
+                status,
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(&body).unwrap();
+        }
+    });
+    (url, store)
+}
+
+// makes a temporary directory the working directory, until dropped
+struct WorkingDir {
+    previous: PathBuf,
+    _dir: tempfile::TempDir,
+}
+
+impl Drop for WorkingDir {
+    fn drop(&mut self) {
+        let _ = std::env::set_current_dir(&self.previous);
+    }
+}
+
+// a directory for the test to work in, along with its path relative to the
+// working directory. Outputs shared via the remote must be relative paths, so
+// the working directory is moved into a temporary directory meanwhile, which
+// is why these tests run serially.
+fn relative_tempdir() -> (WorkingDir, PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let previous = std::env::current_dir().unwrap();
+    std::env::set_current_dir(dir.path()).unwrap();
+    fs::create_dir("work").unwrap();
+    (
+        WorkingDir {
+            previous,
+            _dir: dir,
+        },
+        PathBuf::from("work"),
+    )
+}
+
+// one machine saves, another with an empty local cache restores
+#[test]
+#[serial]
+fn restore_given_remote_hit_fetches_outputs() {
+    let (url, store) = spawn_server();
+    let (_dir, dir) = relative_tempdir();
+    let dir = dir.as_path();
+    let out = dir.join("out.txt");
+    fs::write(&out, "shared output").unwrap();
+
+    let ci = RemoteCache::new(
+        ArtifactCache::new(dir.join("ci")),
+        HttpTransport::new(&url).unwrap().with_token("secret"),
+    );
+    ci.save("abcd", &[&out]).unwrap();
+    assert!(store.lock().unwrap().contains_key("/cache/ac/abcd"));
+
+    fs::remove_file(&out).unwrap();
+    let dev = RemoteCache::new(
+        ArtifactCache::new(dir.join("dev")),
+        HttpTransport::new(&url).unwrap(),
+    )
+    .with_read_only(true);
+    assert!(dev.restore("abcd").unwrap());
+    assert_eq!(fs::read_to_string(&out).unwrap(), "shared output");
+    assert!(dev.local().contains("abcd"));
+    assert!(!dev.restore("ef01").unwrap());
+}
+
+// read only caches never upload, and uploads without a token are refused
+#[test]
+#[serial]
+fn save_respects_read_only_and_credentials() {
+    let (url, store) = spawn_server();
+    let (_dir, dir) = relative_tempdir();
+    let dir = dir.as_path();
+    let out = dir.join("out.txt");
+    fs::write(&out, "output").unwrap();
+
+    let read_only = RemoteCache::new(
+        ArtifactCache::new(dir.join("a")),
+        HttpTransport::new(&url).unwrap().with_token("secret"),
+    )
+    .with_read_only(true);
+    read_only.save("abcd", &[&out]).unwrap();
+    assert!(store.lock().unwrap().is_empty());
+
+    let anonymous = RemoteCache::new(
+        ArtifactCache::new(dir.join("b")),
+        HttpTransport::new(&url).unwrap(),
+    );
+    assert!(matches!(
+        anonymous.save("abcd", &[&out]),
+        Err(HashitError::Remote(_))
+    ));
+    // the local save still happened
+    assert!(anonymous.local().contains("abcd"));
+}
+
+// an unreachable remote degrades to a miss
+#[test]
+fn restore_given_unreachable_remote_is_miss() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+    let dir = tempfile::tempdir().unwrap();
+    let cache = RemoteCache::new(
+        ArtifactCache::new(dir.path()),
+        HttpTransport::new(&url).unwrap(),
+    );
+    assert!(!cache.restore("abcd").unwrap());
+}
+
+#[test]
+fn read_response_handles_chunked_bodies() {
+    let response = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+    let (status, body) = read_response(&response[..]).unwrap();
+    assert_eq!(status, 200);
+    assert_eq!(body, b"hello world");
+}
+
+// a remote which serves canned entries and objects
+struct Canned(HashMap<String, Vec<u8>>);
+
+impl Transport for Canned {
+    fn get(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.0.get(path).cloned())
+    }
+
+    fn put(&self, _path: &str, _body: &[u8]) -> Result<()> {
+        Ok(())
+    }
+}
+
+// entries naming absolute paths or parent directories are refused, and
+// anything beyond permission bits is dropped from the mode
+#[test]
+#[serial]
+fn fetch_given_hostile_entry_is_refused() {
+    let (_dir, dir) = relative_tempdir();
+    let object = b"payload".to_vec();
+    let digest = hex::encode(blake_hash(&object));
+    let remote = |path: &Path| {
+        let mut store = HashMap::new();
+        let entry = format!("{}\t4755\t{}\n", digest, path.display());
+        store.insert("/ac/abcd".to_string(), entry.into_bytes());
+        store.insert(format!("/cas/{}", digest), object.clone());
+        RemoteCache::new(ArtifactCache::new(dir.join("cache")), Canned(store))
+    };
+
+    for path in &[Path::new("/tmp/escaped"), Path::new("a/../../escaped")] {
+        assert!(matches!(
+            remote(path).fetch("abcd"),
+            Err(HashitError::Remote(_))
+        ));
+    }
+
+    let out = dir.join("out.txt");
+    assert!(remote(&out).restore("abcd").unwrap());
+    let mode = fs::metadata(&out).unwrap().permissions().mode();
+    assert_eq!(mode & 0o7777, 0o755);
+}
+
+#[test]
+fn read_response_given_huge_length_is_error() {
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\nshort",
+        usize::MAX
+    );
+    assert!(matches!(
+        read_response(response.as_bytes()),
+        Err(HashitError::Remote(_))
+    ));
+    let response = b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort";
+    assert!(matches!(
+        read_response(&response[..]),
+        Err(HashitError::Remote(_))
+    ));
+}
+
+// a token is never sent in the clear to anything but the local machine
+#[test]
+fn token_is_refused_over_remote_http() {
+    let remote = HttpTransport::new("http://cache.example.com:8080")
+        .unwrap()
+        .with_token("secret");
+    assert!(matches!(
+        remote.get("/ac/abcd"),
+        Err(HashitError::Remote(_))
+    ));
+    assert!(is_loopback("localhost") && is_loopback("127.0.0.1") && is_loopback("[::1]"));
+    assert!(!is_loopback("cache.example.com"));
+}
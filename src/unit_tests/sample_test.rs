@@ -0,0 +1,126 @@
+use super::*;
+use crate::file::FileHash;
+use crate::{Change, Hashit, HtFile};
+
+// a sampler reading 4 bytes at either end and two 2 byte blocks
+fn sampler() -> Sampler {
+    Sampler::new(4, 4, 2, 2)
+}
+
+#[test]
+fn ranges_are_spread_across_the_file() {
+    assert_eq!(
+        sampler().ranges(100),
+        vec![(0, 4), (34, 2), (64, 2), (96, 4)]
+    );
+    // a small file is read in full, with nothing read twice
+    assert_eq!(sampler().ranges(6), vec![(0, 4), (4, 0), (4, 0), (4, 2)]);
+}
+
+#[test]
+fn sampled_digest_misses_changes_between_samples() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("cache.bin");
+    let hasher = SampledHash::new(FileHash {})
+        .with_sampler(sampler())
+        .with_threshold(10);
+    let digest =
+        |hasher: &SampledHash<FileHash>| hasher.calc_hash(&[file.to_str().unwrap()]).unwrap();
+
+    let mut contents = vec![b'a'; 100];
+    fs::write(&file, &contents).unwrap();
+    let original = digest(&hasher);
+
+    // byte 20 lies between samples
+    contents[20] = b'b';
+    fs::write(&file, &contents).unwrap();
+    assert_eq!(digest(&hasher), original);
+    contents[98] = b'b';
+    fs::write(&file, &contents).unwrap();
+    assert_ne!(digest(&hasher), original);
+
+    // strict mode upgrades to a full hash
+    let strict = hasher.with_strict(true);
+    assert_eq!(
+        digest(&strict),
+        FileHash {}.calc_hash(&[file.to_str().unwrap()]).unwrap()
+    );
+}
+
+#[test]
+fn manifest_marks_digest_as_probabilistic() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("cache.bin");
+    let stamp = dir.path().join("stamp");
+    let hasher = || {
+        SampledHash::new(FileHash {})
+            .with_sampler(sampler())
+            .with_threshold(10)
+            .with_manifests(dir.path().join("manifests"))
+    };
+
+    fs::write(&file, vec![b'a'; 100]).unwrap();
+    let mut hashit = Hashit::from_parts(HtFile::new(), hasher());
+    let report = hashit.report(&[&file], &stamp).unwrap();
+    let sample = hasher().sample(report.hash()).unwrap();
+    assert_eq!(sample.size, 100);
+    assert_eq!(sample.sampler, sampler());
+    assert!(fs::read_to_string(
+        dir.path()
+            .join("manifests")
+            .join(hex::encode(report.hash()))
+    )
+    .unwrap()
+    .starts_with("probabilistic\t"));
+    hashit.commit(&stamp, report.hash()).unwrap();
+
+    fs::write(&file, vec![b'a'; 120]).unwrap();
+    let mut hashit = Hashit::from_parts(HtFile::new(), hasher());
+    let name = file.to_string_lossy().to_string();
+    assert_eq!(
+        hashit.report(&[&file], &stamp).unwrap().changes(),
+        &[
+            Change::Input(name.clone()),
+            Change::Entry(name, "size 100 -> 120".to_string()),
+        ]
+    );
+}
+
+// a file moving between sampled and full hashing is still reported
+#[test]
+fn report_names_switch_between_sampled_and_full() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("cache.bin");
+    let stamp = dir.path().join("stamp");
+    let name = file.to_string_lossy().to_string();
+    let hasher = |strict| {
+        SampledHash::new(FileHash {})
+            .with_sampler(sampler())
+            .with_threshold(10)
+            .with_strict(strict)
+            .with_manifests(dir.path().join("manifests"))
+    };
+    let entries = |strict| {
+        let mut hashit = Hashit::from_parts(HtFile::new(), hasher(strict));
+        let report = hashit.report(&[&file], &stamp).unwrap();
+        hashit.commit(&stamp, report.hash()).unwrap();
+        report.changes()[1..].to_vec()
+    };
+
+    fs::write(&file, vec![b'a'; 100]).unwrap();
+    entries(false);
+    assert_eq!(
+        entries(true),
+        vec![Change::Entry(
+            name.clone(),
+            "sampled -> full hash".to_string()
+        )]
+    );
+    assert_eq!(
+        entries(false),
+        vec![Change::Entry(
+            name,
+            "full hash -> sampled, size 100".to_string()
+        )]
+    );
+}
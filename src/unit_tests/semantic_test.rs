@@ -0,0 +1,52 @@
+use super::*;
+use crate::file::FileHash;
+use std::fs;
+
+#[test]
+fn canonical_form_ignores_formatting_and_key_order() {
+    let path = Path::new("config");
+    let json = Format::Json
+        .canonicalize(br#"{"b": [1, 2], "a": {"y": true, "x": "s"}}"#, path)
+        .unwrap();
+    let reformatted = Format::Json
+        .canonicalize(
+            b"{\n  \"a\": {\"x\": \"s\", \"y\": true},\n  \"b\": [1, 2]\n}\n",
+            path,
+        )
+        .unwrap();
+    assert_eq!(json, reformatted);
+
+    let toml = Format::Toml
+        .canonicalize(b"b = [1, 2]\n# comment\n[a]\ny = true\nx = \"s\"\n", path)
+        .unwrap();
+    let yaml = Format::Yaml
+        .canonicalize(b"a:\n  x: s\n  y: true\nb: [1, 2]\n", path)
+        .unwrap();
+    assert_eq!(json, toml);
+    assert_eq!(json, yaml);
+}
+
+#[test]
+fn semantic_hash_selects_format_by_extension() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = dir.path().join("first.json");
+    let second = dir.path().join("second.json");
+    let text = dir.path().join("notes.txt");
+    fs::write(&first, r#"{"a": 1, "b": 2}"#).unwrap();
+    fs::write(&second, "{ \"b\": 2,\n  \"a\": 1 }").unwrap();
+    fs::write(&text, "notes").unwrap();
+
+    let hasher = SemanticHash::new(FileHash {});
+    let hash = |path: &Path| hasher.calc_hash(&[path.to_string_lossy()]).unwrap();
+    assert_eq!(hash(&first), hash(&second));
+    assert_eq!(
+        hash(&text),
+        FileHash {}.calc_hash(&[text.to_string_lossy()]).unwrap()
+    );
+
+    fs::write(&second, "{ \"b\": 2,").unwrap();
+    assert!(matches!(
+        hasher.calc_hash(&[second.to_string_lossy()]),
+        Err(HashitError::Parse { .. })
+    ));
+}
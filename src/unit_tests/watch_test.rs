@@ -0,0 +1,138 @@
+use super::*;
+use std::fs;
+
+// writing to a watched input should be picked up once the events settle
+#[test]
+fn wait_for_events_given_write_is_true() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    fs::write(&input, "one").unwrap();
+    let mut watcher = Watcher::new(&[&input], dir.path().join("stamp"))
+        .unwrap()
+        .with_debounce(Duration::from_millis(50));
+
+    let writer = {
+        let input = input.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            fs::write(&input, "two").unwrap();
+        })
+    };
+    assert!(watcher
+        .wait_for_events(Some(Duration::from_secs(5)))
+        .unwrap());
+    writer.join().unwrap();
+}
+
+// events for files which are not inputs should be ignored
+#[test]
+fn wait_for_events_given_unrelated_write_is_false() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    fs::write(&input, "one").unwrap();
+    let mut watcher = Watcher::new(&[&input], dir.path().join("stamp")).unwrap();
+
+    fs::write(dir.path().join("other.txt"), "other").unwrap();
+    assert!(!watcher
+        .wait_for_events(Some(Duration::from_millis(200)))
+        .unwrap());
+}
+
+// the first check fires because there is no stamp yet. Rewriting the same
+// contents does not fire; changing them does.
+#[test]
+fn watch_fires_only_on_content_change() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    let stamp = dir.path().join("stamp");
+    fs::write(&input, "one").unwrap();
+    let mut watcher = Watcher::new(&[&input], &stamp)
+        .unwrap()
+        .with_debounce(Duration::from_millis(50));
+    let mut hashit = Hashit::new();
+
+    let writer = {
+        let input = input.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            fs::write(&input, "one").unwrap();
+            thread::sleep(Duration::from_millis(300));
+            fs::write(&input, "two").unwrap();
+        })
+    };
+
+    let mut seen = Vec::new();
+    watcher
+        .watch(&mut hashit, |inputs| {
+            seen.push(fs::read_to_string(&inputs[0]).unwrap());
+            Ok(if seen.len() < 2 {
+                Reaction::Done
+            } else {
+                Reaction::Stop
+            })
+        })
+        .unwrap();
+    writer.join().unwrap();
+    assert_eq!(seen, vec!["one", "two"]);
+}
+
+// a failed reaction leaves the stamp alone, so the next event retries even
+// though the contents are unchanged since
+#[test]
+fn watch_retries_after_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    let stamp = dir.path().join("stamp");
+    fs::write(&input, "one").unwrap();
+    let mut watcher = Watcher::new(&[&input], &stamp)
+        .unwrap()
+        .with_debounce(Duration::from_millis(50));
+    let mut hashit = Hashit::new();
+
+    let writer = {
+        let input = input.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            fs::write(&input, "one").unwrap();
+        })
+    };
+    let mut attempts = 0;
+    watcher
+        .watch(&mut hashit, |_| {
+            attempts += 1;
+            Ok(if attempts < 2 {
+                Reaction::Failed
+            } else {
+                Reaction::Stop
+            })
+        })
+        .unwrap();
+    writer.join().unwrap();
+    assert_eq!(attempts, 2);
+}
+
+// a watched directory which is removed and created afresh is watched again
+#[test]
+fn replaced_directory_is_watched_again() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out");
+    fs::create_dir(&out).unwrap();
+    let input = out.join("input.txt");
+    fs::write(&input, "one").unwrap();
+    let mut watcher = Watcher::new(&[&input], dir.path().join("stamp"))
+        .unwrap()
+        .with_debounce(Duration::from_millis(50));
+
+    fs::remove_dir_all(&out).unwrap();
+    assert!(watcher
+        .wait_for_events(Some(Duration::from_secs(5)))
+        .unwrap());
+    fs::create_dir(&out).unwrap();
+    assert!(watcher
+        .wait_for_events(Some(Duration::from_secs(5)))
+        .unwrap());
+    fs::write(&input, "two").unwrap();
+    assert!(watcher
+        .wait_for_events(Some(Duration::from_secs(5)))
+        .unwrap());
+}
@@ -0,0 +1,252 @@
+//! Watch
+//!
+//! Watch a set of inputs using inotify, and react when their contents change.
+//!
+//! Raw file watchers fire on every save, touch, or metadata update. The Watcher
+//! instead treats filesystem events as a hint: once a burst of events has
+//! settled (debounced), it recalculates the inputs' hash via Hashit and only
+//! fires when the contents actually differ from the cached hash.
+//!
+//! Rather than watching the inputs themselves, we watch their parent
+//! directories. Editors frequently save by writing a new file and renaming it
+//! over the old one, which would otherwise orphan a watch on the original file.
+//! Should a watched directory itself be removed or replaced, it is watched
+//! afresh once it exists again.
+use crate::error::Result;
+use crate::hashit::Hashit;
+use crate::traits::{CalcHash, FetchCachedHash, OpenMut};
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The default period of quiet required before a burst of events is acted upon
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(200);
+
+// how often we poll the inotify descriptor for events
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// the file names we care about within a watched directory. None means that
+// the input is the directory itself, and any event within it is relevant.
+type WatchedNames = Option<HashSet<OsString>>;
+
+/// What became of a change, as returned by the on_change callback of watch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reaction {
+    /// The change was dealt with. The stamp is updated, and watching goes on.
+    Done,
+    /// Dealing with the change failed. The stamp is left alone, so the
+    /// inputs count as changed when the next event arrives.
+    Failed,
+    /// The change was dealt with, and watching stops once the stamp is
+    /// updated.
+    Stop,
+}
+
+/// Watches the inputs of a Hashit stamp for changes.
+pub struct Watcher {
+    inputs: Vec<PathBuf>,
+    stamp: PathBuf,
+    debounce: Duration,
+    inotify: Inotify,
+    // the directory and names of interest behind each watch
+    watches: HashMap<WatchDescriptor, (PathBuf, WatchedNames)>,
+    // directories whose watch went away with them, to be watched again once
+    // they reappear
+    lost: Vec<(PathBuf, WatchedNames)>,
+}
+
+// the events which concern us in a watched directory, including its own
+// removal or replacement
+fn mask() -> WatchMask {
+    WatchMask::MODIFY
+        | WatchMask::CLOSE_WRITE
+        | WatchMask::CREATE
+        | WatchMask::DELETE
+        | WatchMask::MOVED_FROM
+        | WatchMask::MOVED_TO
+        | WatchMask::DELETE_SELF
+        | WatchMask::MOVE_SELF
+}
+
+impl std::fmt::Debug for Watcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Watcher")
+            .field("inputs", &self.inputs)
+            .field("stamp", &self.stamp)
+            .field("debounce", &self.debounce)
+            .finish()
+    }
+}
+
+impl Watcher {
+    /// New up a Watcher for the supplied inputs, whose hash is cached in stamp.
+    /// The watches are registered immediately, so any change made after new
+    /// returns will be picked up.
+    pub fn new<P, S>(inputs: &[P], stamp: S) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        S: AsRef<Path>,
+    {
+        let mut inotify = Inotify::init()?;
+        let mut watches: HashMap<WatchDescriptor, (PathBuf, WatchedNames)> = HashMap::new();
+        for input in inputs {
+            let input = input.as_ref();
+            let (dir, name) = if input.is_dir() {
+                (input.to_path_buf(), None)
+            } else {
+                let dir = match input.parent() {
+                    Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+                    _ => PathBuf::from("."),
+                };
+                (dir, input.file_name().map(|x| x.to_os_string()))
+            };
+            // inotify hands back the same descriptor when a directory is
+            // watched more than once, so we merge the names of interest.
+            let wd = inotify.add_watch(&dir, mask())?;
+            let (_, entry) = watches
+                .entry(wd)
+                .or_insert_with(|| (dir, Some(HashSet::new())));
+            match (entry.as_mut(), name) {
+                (Some(names), Some(name)) => {
+                    names.insert(name);
+                }
+                _ => *entry = None,
+            }
+        }
+
+        Ok(Watcher {
+            inputs: inputs.iter().map(|x| x.as_ref().to_path_buf()).collect(),
+            stamp: stamp.as_ref().to_path_buf(),
+            debounce: DEFAULT_DEBOUNCE,
+            inotify,
+            watches,
+            lost: Vec::new(),
+        })
+    }
+
+    /// Set the period of quiet required before a burst of events is acted upon
+    pub fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    pub fn inputs(&self) -> &[PathBuf] {
+        &self.inputs
+    }
+
+    pub fn stamp(&self) -> &Path {
+        &self.stamp
+    }
+
+    // drain pending events, returning true if any of them concern the inputs
+    fn drain_events(&mut self) -> Result<bool> {
+        let mut buffer = [0; 4096];
+        let mut relevant = self.rewatch();
+        loop {
+            let events = self.inotify.read_events(&mut buffer)?;
+            let mut count = 0;
+            for event in events {
+                count += 1;
+                relevant |= match self.watches.get(&event.wd) {
+                    // the directory itself went away
+                    Some(_) if event.name.is_none() => true,
+                    Some((_, None)) => true,
+                    Some((_, Some(names))) => event.name.is_some_and(|n| names.contains(n)),
+                    None => false,
+                };
+                // a directory moved elsewhere keeps its watch, which we no
+                // longer want. Removing it is followed by IGNORED.
+                if event.mask.contains(EventMask::MOVE_SELF) {
+                    let _ = self.inotify.rm_watch(event.wd.clone());
+                }
+                // the watch is gone along with its directory
+                if event.mask.contains(EventMask::IGNORED) {
+                    if let Some(watch) = self.watches.remove(&event.wd) {
+                        self.lost.push(watch);
+                    }
+                }
+            }
+            if count == 0 {
+                return Ok(relevant | self.rewatch());
+            }
+        }
+    }
+
+    // watch again any lost directories which have reappeared, returning true
+    // if there were any, as their contents may well differ
+    fn rewatch(&mut self) -> bool {
+        let mut found = false;
+        let mut lost = Vec::new();
+        for (dir, names) in self.lost.drain(..) {
+            match self.inotify.add_watch(&dir, mask()) {
+                Ok(wd) => {
+                    self.watches.insert(wd, (dir, names));
+                    found = true;
+                }
+                Err(_) => lost.push((dir, names)),
+            }
+        }
+        self.lost = lost;
+        found
+    }
+
+    /// Block until events touching the inputs arrive and then settle for the
+    /// debounce period. Returns false if timeout elapses first.
+    pub fn wait_for_events(&mut self, timeout: Option<Duration>) -> Result<bool> {
+        let start = Instant::now();
+        loop {
+            if self.drain_events()? {
+                break;
+            }
+            if let Some(timeout) = timeout {
+                if start.elapsed() >= timeout {
+                    return Ok(false);
+                }
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        // debounce. keep swallowing events until we have seen a full period
+        // of quiet.
+        let mut quiet_since = Instant::now();
+        while quiet_since.elapsed() < self.debounce {
+            thread::sleep(POLL_INTERVAL);
+            if self.drain_events()? {
+                quiet_since = Instant::now();
+            }
+        }
+        Ok(true)
+    }
+
+    /// Watch the inputs, invoking on_change whenever their contents differ
+    /// from the hash cached in the stamp. The inputs are checked once when
+    /// watch is first called, so changes made while nobody was watching are
+    /// not missed.
+    ///
+    /// The stamp is only updated once on_change reports that it dealt with
+    /// the change (see Reaction). An error from on_change stops watching,
+    /// leaving the stamp alone.
+    pub fn watch<R, H, F>(&mut self, hashit: &mut Hashit<R, H>, mut on_change: F) -> Result<()>
+    where
+        R: for<'b> OpenMut<'b> + for<'b> FetchCachedHash<'b>,
+        H: CalcHash + std::fmt::Debug,
+        F: FnMut(&[PathBuf]) -> Result<Reaction>,
+    {
+        loop {
+            if let Some(hash) = hashit.check(&self.inputs, &self.stamp)? {
+                match on_change(&self.inputs)? {
+                    Reaction::Done => hashit.commit(&self.stamp, &hash)?,
+                    Reaction::Failed => (),
+                    Reaction::Stop => return hashit.commit(&self.stamp, &hash),
+                }
+            }
+            self.wait_for_events(None)?;
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/watch_test.rs"]
+mod tests;